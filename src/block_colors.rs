@@ -0,0 +1,130 @@
+/// Rough top face colors of blocks, grouped into families we can pick from by color.
+/// Grass is shown with the plains tint.
+pub const NATURAL : [(&str, u32); 22] = [
+	("minecraft:grass_block", 0x6A9A3C),
+	("minecraft:moss_block", 0x596E2D),
+	("minecraft:dirt", 0x866043),
+	("minecraft:coarse_dirt", 0x77553B),
+	("minecraft:rooted_dirt", 0x90674C),
+	("minecraft:podzol", 0x5B3F1D),
+	("minecraft:mud", 0x3C393D),
+	("minecraft:dirt_path", 0x94793F),
+	("minecraft:clay", 0xA0A6B3),
+	("minecraft:gravel", 0x837F7E),
+	("minecraft:sand", 0xDBCFA3),
+	("minecraft:red_sand", 0xBE6721),
+	("minecraft:sandstone", 0xD8CB9B),
+	("minecraft:stone", 0x7E7E7E),
+	("minecraft:andesite", 0x888889),
+	("minecraft:cobblestone", 0x7F7F7F),
+	("minecraft:mossy_cobblestone", 0x6E775F),
+	("minecraft:tuff", 0x6C6D67),
+	("minecraft:deepslate", 0x505053),
+	("minecraft:calcite", 0xDFE0DC),
+	("minecraft:snow_block", 0xF9FEFE),
+	("minecraft:packed_ice", 0x8DB4FA),
+];
+
+pub const CONCRETE : [(&str, u32); 16] = [
+	("minecraft:white_concrete", 0xCFD5D6),
+	("minecraft:orange_concrete", 0xE06101),
+	("minecraft:magenta_concrete", 0xA9309F),
+	("minecraft:light_blue_concrete", 0x2489C7),
+	("minecraft:yellow_concrete", 0xF1AF15),
+	("minecraft:lime_concrete", 0x5EA918),
+	("minecraft:pink_concrete", 0xD5658E),
+	("minecraft:gray_concrete", 0x36393D),
+	("minecraft:light_gray_concrete", 0x7D7D73),
+	("minecraft:cyan_concrete", 0x157788),
+	("minecraft:purple_concrete", 0x64209C),
+	("minecraft:blue_concrete", 0x2D2F8F),
+	("minecraft:brown_concrete", 0x603C20),
+	("minecraft:green_concrete", 0x495B24),
+	("minecraft:red_concrete", 0x8E2121),
+	("minecraft:black_concrete", 0x080A0F),
+];
+
+pub const TERRACOTTA : [(&str, u32); 17] = [
+	("minecraft:terracotta", 0x985E43),
+	("minecraft:white_terracotta", 0xD1B2A1),
+	("minecraft:orange_terracotta", 0xA15325),
+	("minecraft:magenta_terracotta", 0x95586C),
+	("minecraft:light_blue_terracotta", 0x716C89),
+	("minecraft:yellow_terracotta", 0xBA8523),
+	("minecraft:lime_terracotta", 0x677534),
+	("minecraft:pink_terracotta", 0xA14E4E),
+	("minecraft:gray_terracotta", 0x392A23),
+	("minecraft:light_gray_terracotta", 0x876A61),
+	("minecraft:cyan_terracotta", 0x565B5B),
+	("minecraft:purple_terracotta", 0x764656),
+	("minecraft:blue_terracotta", 0x4A3B5B),
+	("minecraft:brown_terracotta", 0x4D3323),
+	("minecraft:green_terracotta", 0x4C532A),
+	("minecraft:red_terracotta", 0x8F3D2E),
+	("minecraft:black_terracotta", 0x251610),
+];
+
+pub const WOOL : [(&str, u32); 16] = [
+	("minecraft:white_wool", 0xE9ECEC),
+	("minecraft:orange_wool", 0xF07613),
+	("minecraft:magenta_wool", 0xBD44B3),
+	("minecraft:light_blue_wool", 0x3AAFD9),
+	("minecraft:yellow_wool", 0xF8C527),
+	("minecraft:lime_wool", 0x70B919),
+	("minecraft:pink_wool", 0xED8DAC),
+	("minecraft:gray_wool", 0x3E4447),
+	("minecraft:light_gray_wool", 0x8E8E86),
+	("minecraft:cyan_wool", 0x158991),
+	("minecraft:purple_wool", 0x792AAC),
+	("minecraft:blue_wool", 0x35399D),
+	("minecraft:brown_wool", 0x724728),
+	("minecraft:green_wool", 0x546D1B),
+	("minecraft:red_wool", 0xA12722),
+	("minecraft:black_wool", 0x141519),
+];
+
+pub const FAMILIES : [(&str, &[(&str, u32)]); 4] = [
+	("natural", &NATURAL),
+	("concrete", &CONCRETE),
+	("terracotta", &TERRACOTTA),
+	("wool", &WOOL),
+];
+
+pub fn family(name : &str) -> Option<&'static [(&'static str, u32)]> {
+	FAMILIES.iter().find(|(n, _)| *n == name).map(|(_, blocks)| *blocks)
+}
+
+/// The reference color of a block from any family, if we know it.
+pub fn color_of(block : &str) -> Option<[u8; 3]> {
+	FAMILIES.iter().flat_map(|(_, blocks)| blocks.iter()).find(|(name, _)| *name == block).map(|(_, color)| rgb(*color))
+}
+
+pub fn rgb(color : u32) -> [u8; 3] {
+	[(color >> 16) as u8, (color >> 8) as u8, color as u8]
+}
+
+/// Parses `#rrggbb`.
+pub fn parse_hex(s : &str) -> Option<[u8; 3]> {
+	let hex = s.strip_prefix('#')?;
+	if hex.len() != 6 {
+		return None;
+	}
+	u32::from_str_radix(hex, 16).ok().map(rgb)
+}
+
+/// "Redmean" weighted RGB distance, which is cheap and tracks how different colors look much better than plain RGB distance.
+pub fn distance(a : [u8; 3], b : [u8; 3]) -> f32 {
+	let r_mean = (a[0] as f32 + b[0] as f32) / 2.0;
+	let dr = a[0] as f32 - b[0] as f32;
+	let dg = a[1] as f32 - b[1] as f32;
+	let db = a[2] as f32 - b[2] as f32;
+
+	((2.0 + r_mean / 256.0) * dr * dr + 4.0 * dg * dg + (2.0 + (255.0 - r_mean) / 256.0) * db * db).sqrt()
+}
+
+/// Index of the color in the list closest to the target.
+pub fn nearest<T>(target : [u8; 3], colors : &[(T, [u8; 3])]) -> Option<usize> {
+	colors.iter().enumerate().min_by(|(_, (_, a)), (_, (_, b))| {
+		distance(target, *a).total_cmp(&distance(target, *b))
+	}).map(|(i, _)| i)
+}
@@ -0,0 +1,505 @@
+use std::{collections::HashMap, fs::File, io::{BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use flate2::read::ZlibDecoder;
+
+use crate::error::{Error, Result};
+
+/// Archive flags:
+const INCLUDE_DIRECTORY_NAMES : u32 = 0x1;
+const INCLUDE_FILE_NAMES : u32 = 0x2;
+const COMPRESSED : u32 = 0x4;
+/// Each file's data starts with its full path. Only from version 104 on: Oblivion uses the flag for Xbox archives.
+const EMBED_FILE_NAMES : u32 = 0x100;
+
+/// Set in a file's size when it's compressed and the rest of the archive isn't, or the other way around.
+const TOGGLE_COMPRESSION : u32 = 0x4000_0000;
+
+/// Which kind of archive, and how its files are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// 103 for Oblivion, 104 for Skyrim, Fallout 3 and New Vegas, 105 for Skyrim Special Edition.
+    Bsa { version : u32, flags : u32 },
+    /// Fallout 4's general (not texture) archives.
+    Ba2,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FileEntry {
+    offset : u64,
+    /// As stored. For a BSA that includes the original size of compressed files and the embedded name.
+    size : u32,
+    compressed : bool,
+    /// Only known up front for BA2 files. BSAs store it with the data.
+    original_size : u32,
+}
+
+/// A Bethesda archive: a .bsa of Oblivion, Skyrim (Legendary and Special Edition), Fallout 3 or New Vegas, or a general .ba2 of Fallout 4.
+///
+/// Only the index is kept in memory. Files are read from disk when asked for, so the archive can be shared between threads.
+#[derive(Debug)]
+pub struct Archive {
+    path : PathBuf,
+    format : Format,
+    /// By path, as [`normalize`] makes it.
+    files : HashMap<String, FileEntry>,
+}
+
+impl Archive {
+    /// Reads the archive's index.
+    pub fn open(path : &Path) -> Result<Archive> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let truncated = |e : Error| e.truncated_file(&format!("{path:?}"));
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(|e| truncated(e.into()))?;
+
+        let (format, files) = match &magic {
+            b"BSA\0" => read_bsa_index(&mut reader, path).map_err(truncated)?,
+            b"BTDX" => (Format::Ba2, read_ba2_index(&mut reader, path).map_err(truncated)?),
+            _ => return Err(Error::invalid_data(format!("{path:?} is not a BSA or BA2 archive.")))
+        };
+
+        Ok(Archive {
+            path: path.into(),
+            format,
+            files
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn contains(&self, path : &str) -> bool {
+        self.files.contains_key(&normalize(path))
+    }
+
+    /// Every file in the archive, by its path (lowercase, with backslashes), in no particular order.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|k| k.as_str())
+    }
+
+    /// The contents of the file at `path` (like `meshes\clutter\bucket01.nif`), decompressed. `None` if the archive doesn't have it.
+    pub fn read(&self, path : &str) -> Result<Option<Vec<u8>>> {
+        let Some(entry) = self.files.get(&normalize(path)) else {
+            return Ok(None);
+        };
+        self.read_entry(path, entry).map(Some).map_err(|e| e.truncated_file(&format!("{path} in {:?}", self.path)))
+    }
+
+    fn read_entry(&self, path : &str, entry : &FileEntry) -> Result<Vec<u8>> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut data = (&mut reader).take(entry.size as u64);
+
+        if let Format::Bsa { version, flags } = self.format {
+            if version >= 104 && flags & EMBED_FILE_NAMES != 0 {
+                read_bstring(&mut data)?;
+            }
+        }
+
+        if !entry.compressed {
+            let mut contents = Vec::with_capacity(entry.size as usize);
+            data.read_to_end(&mut contents)?;
+            return Ok(contents);
+        }
+
+        let original_size = match self.format {
+            Format::Ba2 => entry.original_size as usize,
+            Format::Bsa { .. } => {
+                let mut original_size = [0; 4];
+                data.read_exact(&mut original_size)?;
+                u32::from_le_bytes(original_size) as usize
+            }
+        };
+
+        let mut contents = Vec::with_capacity(original_size);
+        // Special Edition switched from zlib to LZ4 frames:
+        let decompressed = if matches!(self.format, Format::Bsa { version: 105, .. }) {
+            lz4_flex::frame::FrameDecoder::new(data).read_to_end(&mut contents)
+        } else {
+            ZlibDecoder::new(data).read_to_end(&mut contents)
+        };
+
+        match decompressed {
+            Ok(size) if size == original_size => Ok(contents),
+            Ok(size) => Err(Error::invalid_data(format!("{path} in {:?} decompresses to {size} bytes instead of {original_size}.", self.path))),
+            Err(e) => Err(Error::invalid_data(format!("Could not decompress {path} in {:?}: {e}", self.path)))
+        }
+    }
+}
+
+/// Reads a BSA's index, from right after its magic.
+fn read_bsa_index(reader : &mut (impl Read + Seek), path : &Path) -> Result<(Format, HashMap<String, FileEntry>)> {
+    let mut header = [0; 32];
+    reader.read_exact(&mut header)?;
+    let u32_at = |i : usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+
+    let version = u32_at(0);
+    if !matches!(version, 103..=105) {
+        return Err(Error::invalid_data(format!("{path:?} is a version {version} BSA archive. Only Oblivion, Skyrim, Fallout 3 and New Vegas archives (103 to 105) can be read.")));
+    }
+
+    let folder_records_offset = u32_at(4) as u64;
+    let flags = u32_at(8);
+    let folder_count = u32_at(12);
+    let file_count = u32_at(16);
+    let file_names_length = u32_at(24);
+
+    if flags & INCLUDE_DIRECTORY_NAMES == 0 || flags & INCLUDE_FILE_NAMES == 0 {
+        return Err(Error::invalid_data(format!("{path:?} doesn't have the names of its files, so they can't be looked up.")));
+    }
+
+    // Folder records are a hash, how many files are in the folder, and where its file records are.
+    // The file records come right after them anyway, so only the count matters:
+    reader.seek(SeekFrom::Start(folder_records_offset))?;
+    let mut file_counts = Vec::with_capacity(folder_count as usize);
+    for _ in 0..folder_count {
+        let mut record = [0; 24];
+        let record = &mut record[..if version == 105 { 24 } else { 16 }];
+        reader.read_exact(record)?;
+        file_counts.push(u32::from_le_bytes(record[8..12].try_into().unwrap()));
+    }
+
+    // Each folder's name, then its file records:
+    let mut entries = Vec::with_capacity(file_count as usize);
+    for count in file_counts {
+        let folder = read_bstring(reader)?;
+
+        for _ in 0..count {
+            let mut record = [0; 16];
+            reader.read_exact(&mut record)?;
+            let size = u32::from_le_bytes(record[8..12].try_into().unwrap());
+            let offset = u32::from_le_bytes(record[12..16].try_into().unwrap());
+
+            entries.push((folder.clone(), FileEntry {
+                offset: offset as u64,
+                size: size & !(TOGGLE_COMPRESSION | 0x8000_0000),
+                compressed: (flags & COMPRESSED != 0) != (size & TOGGLE_COMPRESSION != 0),
+                original_size: 0
+            }));
+        }
+    }
+
+    // And the names of every file, in the same order:
+    let mut names = vec![0; file_names_length as usize];
+    reader.read_exact(&mut names)?;
+    let names = names.split(|b| *b == 0).map(|name| String::from_utf8_lossy(name));
+
+    let files = entries.into_iter().zip(names).map(|((folder, entry), name)| {
+        (normalize(&format!("{folder}\\{name}")), entry)
+    }).collect();
+
+    Ok((Format::Bsa { version, flags }, files))
+}
+
+/// Reads a BA2's index, from right after its magic.
+fn read_ba2_index(reader : &mut (impl Read + Seek), path : &Path) -> Result<HashMap<String, FileEntry>> {
+    let mut header = [0; 20];
+    reader.read_exact(&mut header)?;
+
+    // 1 for Fallout 4, and 7 and 8 for its next-gen update, which changed nothing we read:
+    let version = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    if !matches!(version, 1 | 7 | 8) {
+        return Err(Error::invalid_data(format!("{path:?} is a version {version} BA2 archive. Only Fallout 4's (1, 7 and 8) can be read.")));
+    }
+    if &header[4..8] != b"GNRL" {
+        return Err(Error::invalid_data(format!("{path:?} is a {} BA2 archive. Only general (GNRL) archives can be read, not textures.", String::from_utf8_lossy(&header[4..8]))));
+    }
+
+    let file_count = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    let name_table_offset = u64::from_le_bytes(header[12..20].try_into().unwrap());
+
+    // Records are hashes of the name and folder, the extension, flags, then where the data is and how big:
+    let mut entries = Vec::with_capacity(file_count as usize);
+    for _ in 0..file_count {
+        let mut record = [0; 36];
+        reader.read_exact(&mut record)?;
+        let offset = u64::from_le_bytes(record[16..24].try_into().unwrap());
+        let packed_size = u32::from_le_bytes(record[24..28].try_into().unwrap());
+        let unpacked_size = u32::from_le_bytes(record[28..32].try_into().unwrap());
+
+        entries.push(FileEntry {
+            offset,
+            size: if packed_size == 0 { unpacked_size } else { packed_size },
+            compressed: packed_size != 0,
+            original_size: unpacked_size
+        });
+    }
+
+    // The names are in the same order, each prefixed with its length:
+    reader.seek(SeekFrom::Start(name_table_offset))?;
+    let mut files = HashMap::with_capacity(entries.len());
+    for entry in entries {
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        let mut name = vec![0; u16::from_le_bytes(length) as usize];
+        reader.read_exact(&mut name)?;
+
+        files.insert(normalize(&String::from_utf8_lossy(&name)), entry);
+    }
+    Ok(files)
+}
+
+/// A game's Data folder: loose files, and the archives in it.
+///
+/// Like in-game, loose files win over archives, and later archives win over earlier ones.
+#[derive(Debug)]
+pub struct DataFiles {
+    folder : PathBuf,
+    /// In the order they're checked, so the last one wins.
+    archives : Vec<Archive>,
+    /// Why each archive we couldn't open was left out.
+    skipped : Vec<Error>,
+}
+
+impl DataFiles {
+    /// Opens every .bsa and .ba2 archive in the Data folder, in alphabetical order.
+    /// Archives we can't read (like those of other games, or Fallout 4's texture archives) are left out, see [`DataFiles::skipped`].
+    pub fn open(folder : &Path) -> Result<DataFiles> {
+        let mut paths : Vec<PathBuf> = std::fs::read_dir(folder)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not read Data folder {folder:?}: {e}")))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|e| e.eq_ignore_ascii_case("bsa") || e.eq_ignore_ascii_case("ba2")))
+            .collect();
+        paths.sort();
+
+        let mut archives = Vec::with_capacity(paths.len());
+        let mut skipped = Vec::new();
+        for path in paths {
+            match Archive::open(&path) {
+                Ok(archive) => archives.push(archive),
+                Err(e) => skipped.push(e)
+            }
+        }
+
+        Ok(DataFiles {
+            folder: folder.into(),
+            archives,
+            skipped
+        })
+    }
+
+    pub fn archives(&self) -> &[Archive] {
+        &self.archives
+    }
+
+    /// What went wrong opening each archive that was left out.
+    pub fn skipped(&self) -> &[Error] {
+        &self.skipped
+    }
+
+    /// The contents of the file at `path` from the Data folder (like `meshes\clutter\bucket01.nif`). `None` if it's nowhere.
+    pub fn read(&self, path : &str) -> Result<Option<Vec<u8>>> {
+        let loose = self.folder.join(normalize(path).replace('\\', "/"));
+        if loose.is_file() {
+            return Ok(Some(std::fs::read(loose)?));
+        }
+
+        for archive in self.archives.iter().rev() {
+            if let Some(data) = archive.read(path)? {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// How archives name their files: lowercase, with backslashes.
+pub fn normalize(path : &str) -> String {
+    path.to_lowercase().replace('/', "\\").trim_start_matches('\\').into()
+}
+
+/// A string prefixed with its length, which may or may not count a null terminator.
+fn read_bstring(reader : &mut impl Read) -> Result<String> {
+    let mut length = [0; 1];
+    reader.read_exact(&mut length)?;
+
+    let mut buf = vec![0; length[0] as usize];
+    reader.read_exact(&mut buf)?;
+
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..end]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// A file for [`write_bsa`]: its folder, name, contents, and whether its size has [`TOGGLE_COMPRESSION`] set.
+    struct TestFile<'a> {
+        folder : &'a str,
+        name : &'a str,
+        contents : &'a [u8],
+        toggle : bool,
+    }
+
+    fn zlib(data : &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn lz4(data : &[u8]) -> Vec<u8> {
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A BSA with each file in its own folder. Hashes are left at 0, since we look files up by name.
+    fn write_bsa(path : &Path, version : u32, flags : u32, files : &[TestFile]) {
+        let folder_record_size = if version == 105 { 24 } else { 16 };
+        let names : Vec<u8> = files.iter().flat_map(|file| file.name.bytes().chain([0])).collect();
+        let folder_names_length : usize = files.iter().map(|file| file.folder.len() + 2).sum();
+        let mut offset = 36 + files.len() * (folder_record_size + 16) + folder_names_length + names.len();
+
+        // What's stored for each file: its embedded name, then its original size and compressed data if it's compressed.
+        let stored : Vec<Vec<u8>> = files.iter().map(|file| {
+            let mut data = Vec::new();
+            if flags & EMBED_FILE_NAMES != 0 && version >= 104 {
+                let path = format!("{}\\{}", file.folder, file.name);
+                data.push(path.len() as u8);
+                data.extend(path.bytes());
+            }
+            if (flags & COMPRESSED != 0) != file.toggle {
+                data.extend((file.contents.len() as u32).to_le_bytes());
+                data.extend(if version == 105 { lz4(file.contents) } else { zlib(file.contents) });
+            } else {
+                data.extend(file.contents);
+            }
+            data
+        }).collect();
+
+        let mut bsa = b"BSA\0".to_vec();
+        for value in [version, 36, flags, files.len() as u32, files.len() as u32, folder_names_length as u32, names.len() as u32, 0] {
+            bsa.extend(value.to_le_bytes());
+        }
+        for _ in files {
+            bsa.extend([0; 8]);
+            bsa.extend(1u32.to_le_bytes());
+            bsa.extend(vec![0; folder_record_size - 12]);
+        }
+        for (file, data) in files.iter().zip(&stored) {
+            bsa.push(file.folder.len() as u8 + 1);
+            bsa.extend(file.folder.bytes().chain([0]));
+
+            bsa.extend([0; 8]);
+            bsa.extend((data.len() as u32 | if file.toggle { TOGGLE_COMPRESSION } else { 0 }).to_le_bytes());
+            bsa.extend((offset as u32).to_le_bytes());
+            offset += data.len();
+        }
+        bsa.extend(names);
+        bsa.extend(stored.concat());
+
+        std::fs::write(path, bsa).unwrap();
+    }
+
+    /// A general BA2 with its `(path, contents, compressed)` files.
+    fn write_ba2(path : &Path, kind : &[u8; 4], files : &[(&str, &[u8], bool)]) {
+        let stored : Vec<Vec<u8>> = files.iter().map(|(_, contents, compressed)| if *compressed { zlib(contents) } else { contents.to_vec() }).collect();
+        let mut offset = 24 + files.len() * 36;
+
+        let mut ba2 = b"BTDX".to_vec();
+        ba2.extend(1u32.to_le_bytes());
+        ba2.extend(kind);
+        ba2.extend((files.len() as u32).to_le_bytes());
+        ba2.extend(((offset + stored.iter().map(|data| data.len()).sum::<usize>()) as u64).to_le_bytes());
+
+        for ((_, contents, compressed), data) in files.iter().zip(&stored) {
+            ba2.extend([0; 16]);
+            ba2.extend((offset as u64).to_le_bytes());
+            ba2.extend((if *compressed { data.len() as u32 } else { 0 }).to_le_bytes());
+            ba2.extend((contents.len() as u32).to_le_bytes());
+            ba2.extend(0xBAAD_F00Du32.to_le_bytes());
+            offset += data.len();
+        }
+        ba2.extend(stored.concat());
+        for (name, _, _) in files {
+            ba2.extend((name.len() as u16).to_le_bytes());
+            ba2.extend(name.bytes());
+        }
+
+        std::fs::write(path, ba2).unwrap();
+    }
+
+    #[test]
+    fn bsa_versions() {
+        let dir = test_dir("bsa-versions");
+        let files = [
+            TestFile { folder: "Meshes\\Clutter", name: "Bucket01.nif", contents: b"a bucket", toggle: false },
+            TestFile { folder: "meshes\\rocks", name: "rock01.nif", contents: b"a rock, stored the other way", toggle: true },
+        ];
+
+        for version in [103, 104, 105] {
+            for compressed in [0, COMPRESSED] {
+                let path = dir.join(format!("{version}-{compressed}.bsa"));
+                write_bsa(&path, version, INCLUDE_DIRECTORY_NAMES | INCLUDE_FILE_NAMES | compressed, &files);
+
+                let archive = Archive::open(&path).unwrap();
+                let mut names : Vec<&str> = archive.files().collect();
+                names.sort();
+                assert_eq!(names, ["meshes\\clutter\\bucket01.nif", "meshes\\rocks\\rock01.nif"]);
+
+                assert_eq!(archive.read("meshes/clutter/BUCKET01.nif").unwrap().as_deref(), Some(&b"a bucket"[..]), "{version} {compressed}");
+                assert_eq!(archive.read("meshes\\rocks\\rock01.nif").unwrap().as_deref(), Some(&b"a rock, stored the other way"[..]), "{version} {compressed}");
+                assert_eq!(archive.read("meshes\\rocks\\rock02.nif").unwrap(), None);
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bsa_embedded_names() {
+        let dir = test_dir("bsa-embedded-names");
+        let files = [
+            TestFile { folder: "meshes", name: "a.nif", contents: b"compressed", toggle: false },
+            TestFile { folder: "meshes", name: "b.nif", contents: b"not compressed", toggle: true },
+        ];
+
+        // Oblivion's archives use the flag for something else, so nothing is embedded:
+        for version in [103, 104, 105] {
+            let path = dir.join(format!("{version}.bsa"));
+            write_bsa(&path, version, INCLUDE_DIRECTORY_NAMES | INCLUDE_FILE_NAMES | COMPRESSED | EMBED_FILE_NAMES, &files);
+
+            let archive = Archive::open(&path).unwrap();
+            assert_eq!(archive.read("meshes\\a.nif").unwrap().as_deref(), Some(&b"compressed"[..]), "{version}");
+            assert_eq!(archive.read("meshes\\b.nif").unwrap().as_deref(), Some(&b"not compressed"[..]), "{version}");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bsa_without_names() {
+        let dir = test_dir("bsa-without-names");
+        let path = dir.join("nameless.bsa");
+        write_bsa(&path, 104, INCLUDE_DIRECTORY_NAMES, &[]);
+        assert!(Archive::open(&path).unwrap_err().to_string().contains("doesn't have the names"));
+
+        let path = dir.join("morrowind.bsa");
+        std::fs::write(&path, [0x00, 0x01, 0x00, 0x00]).unwrap();
+        assert!(Archive::open(&path).unwrap_err().to_string().contains("not a BSA or BA2"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ba2_general() {
+        let dir = test_dir("ba2-general");
+        let path = dir.join("general.ba2");
+        write_ba2(&path, b"GNRL", &[("Meshes/SetDressing/Bucket.nif", b"a bucket", true), ("meshes/rock.nif", b"a rock", false)]);
+
+        let archive = Archive::open(&path).unwrap();
+        assert!(archive.contains("meshes\\setdressing\\bucket.nif"));
+        assert_eq!(archive.read("meshes\\setdressing\\bucket.nif").unwrap().as_deref(), Some(&b"a bucket"[..]));
+        assert_eq!(archive.read("Meshes/Rock.nif").unwrap().as_deref(), Some(&b"a rock"[..]));
+
+        let path = dir.join("textures.ba2");
+        write_ba2(&path, b"DX10", &[]);
+        assert!(Archive::open(&path).unwrap_err().to_string().contains("Only general (GNRL) archives"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
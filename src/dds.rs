@@ -0,0 +1,606 @@
+use crate::error::{Error, Result};
+
+/// "DDS ", then the 124 byte header.
+const HEADER_SIZE : usize = 128;
+
+/// Follows the header when the pixel format's FourCC is "DX10", with the format as a DXGI_FORMAT.
+const DX10_HEADER_SIZE : usize = 20;
+
+/// Bigger than any texture the games have, so a broken header doesn't make us allocate forever.
+const MAX_SIZE : u32 = 16384;
+
+/// DDS_PIXELFORMAT flags.
+const DDPF_ALPHAPIXELS : u32 = 0x1;
+const DDPF_FOURCC : u32 = 0x4;
+const DDPF_RGB : u32 = 0x40;
+const DDPF_LUMINANCE : u32 = 0x20000;
+
+/// The top mip level of a texture, as 8 bit RGBA.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width : u32,
+    pub height : u32,
+    /// Row by row, from the top left.
+    pub pixels : Vec<[u8; 4]>,
+}
+
+/// Decodes a block of 4 x 4 pixels, row by row.
+type DecodeBlock = fn(&[u8]) -> [[u8; 4]; 16];
+
+enum Format {
+    /// Compressed into 4 x 4 blocks of the given size.
+    Blocks(usize, DecodeBlock),
+    /// Uncompressed, with each channel (RGBA) picked out of a pixel by its bit mask. Without an alpha mask, pixels are opaque.
+    Masked { bytes : usize, masks : [u32; 4] },
+}
+
+/// Reads the top mip level of a DDS texture that's BC1 (DXT1), BC2 (DXT3), BC3 (DXT5), BC7 or uncompressed,
+/// which covers the diffuse textures of every game we read.
+pub fn read_image(data : &[u8]) -> Result<Image> {
+    if data.len() < HEADER_SIZE || &data[..4] != b"DDS " {
+        return Err(Error::invalid_data("Not a DDS texture"));
+    }
+    let u32_at = |offset : usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+
+    let height = u32_at(12);
+    let width = u32_at(16);
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(Error::invalid_data(format!("DDS texture has a size of {width} x {height}")));
+    }
+
+    let pixel_flags = u32_at(80);
+    let (format, start) = if pixel_flags & DDPF_FOURCC != 0 {
+        match &data[84..88] {
+            b"DXT1" => (Format::Blocks(8, decode_bc1), HEADER_SIZE),
+            b"DXT2" | b"DXT3" => (Format::Blocks(16, decode_bc2), HEADER_SIZE),
+            b"DXT4" | b"DXT5" => (Format::Blocks(16, decode_bc3), HEADER_SIZE),
+            b"DX10" => {
+                if data.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+                    return Err(Error::invalid_data("DDS texture ends in its DX10 header"));
+                }
+                let format = match u32_at(HEADER_SIZE) {
+                    71 | 72 => Format::Blocks(8, decode_bc1),
+                    74 | 75 => Format::Blocks(16, decode_bc2),
+                    77 | 78 => Format::Blocks(16, decode_bc3),
+                    98 | 99 => Format::Blocks(16, decode_bc7),
+                    // R8G8B8A8:
+                    28 | 29 => Format::Masked { bytes: 4, masks: [0xFF, 0xFF00, 0xFF0000, 0xFF000000] },
+                    // B8G8R8A8 and B8G8R8X8:
+                    87 | 91 => Format::Masked { bytes: 4, masks: [0xFF0000, 0xFF00, 0xFF, 0xFF000000] },
+                    88 | 93 => Format::Masked { bytes: 4, masks: [0xFF0000, 0xFF00, 0xFF, 0] },
+                    other => return Err(Error::invalid_data(format!("Unsupported DXGI format {other} in DDS texture")))
+                };
+                (format, HEADER_SIZE + DX10_HEADER_SIZE)
+            },
+            other => return Err(Error::invalid_data(format!("Unsupported DDS format {:?}", String::from_utf8_lossy(other))))
+        }
+    } else if pixel_flags & (DDPF_RGB | DDPF_LUMINANCE) != 0 {
+        let bits = u32_at(88);
+        if !matches!(bits, 8 | 16 | 24 | 32) {
+            return Err(Error::invalid_data(format!("Unsupported DDS pixel size of {bits} bits")));
+        }
+
+        let [red, green, blue] = [92, 96, 100].map(u32_at);
+        // Luminance only has the one mask, for every color channel:
+        let (green, blue) = if pixel_flags & DDPF_LUMINANCE != 0 { (red, red) } else { (green, blue) };
+        let alpha = if pixel_flags & DDPF_ALPHAPIXELS != 0 { u32_at(104) } else { 0 };
+        (Format::Masked { bytes: bits as usize / 8, masks: [red, green, blue, alpha] }, HEADER_SIZE)
+    } else {
+        return Err(Error::invalid_data(format!("Unsupported DDS pixel format (flags {pixel_flags:#X})")));
+    };
+
+    let (width_px, height_px) = (width as usize, height as usize);
+    let data = &data[start..];
+    let mut pixels = vec![[0, 0, 0, 255]; width_px * height_px];
+
+    match format {
+        Format::Masked { bytes, masks } => {
+            if data.len() < pixels.len() * bytes {
+                return Err(Error::invalid_data("DDS texture ends before its last pixel"));
+            }
+
+            for (pixel, value) in pixels.iter_mut().zip(data.chunks_exact(bytes)) {
+                let mut buf = [0; 4];
+                buf[..bytes].copy_from_slice(value);
+                let value = u32::from_le_bytes(buf);
+
+                *pixel = masks.map(|mask| channel(value, mask));
+                if masks[3] == 0 {
+                    pixel[3] = 255;
+                }
+            }
+        },
+        Format::Blocks(block_size, decode) => {
+            let (blocks_wide, blocks_high) = (width_px.div_ceil(4), height_px.div_ceil(4));
+            if data.len() < blocks_wide * blocks_high * block_size {
+                return Err(Error::invalid_data("DDS texture ends before its last block"));
+            }
+
+            for (i, block) in data.chunks_exact(block_size).take(blocks_wide * blocks_high).enumerate() {
+                let (block_x, block_y) = (i % blocks_wide * 4, i / blocks_wide * 4);
+
+                // Blocks along the edges of textures that aren't a multiple of 4 wide hang over them:
+                for (j, texel) in decode(block).into_iter().enumerate() {
+                    let (x, y) = (block_x + j % 4, block_y + j / 4);
+                    if x < width_px && y < height_px {
+                        pixels[y * width_px + x] = texel;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(Image { width, height, pixels })
+}
+
+/// Picks a channel out of an uncompressed pixel, scaled to 8 bits.
+fn channel(value : u32, mask : u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = (mask >> mask.trailing_zeros()) as u64;
+    let channel = ((value & mask) >> mask.trailing_zeros()) as u64;
+    ((channel * 255 + max / 2) / max) as u8
+}
+
+fn rgb565(color : u16) -> [u8; 4] {
+    let (r, g, b) = ((color >> 11) as u8, (color >> 5 & 0x3F) as u8, (color & 0x1F) as u8);
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 255]
+}
+
+/// The color half of BC1, BC2 and BC3 blocks. Only BC1 has the mode with 3 colors and transparent black.
+fn decode_colors(block : &[u8], has_transparent : bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+
+    let mix = |wa : u16, wb : u16| -> [u8; 4] {
+        [0, 1, 2, 3].map(|i| ((a[i] as u16 * wa + b[i] as u16 * wb) / (wa + wb)) as u8)
+    };
+    let palette = if c0 > c1 || !has_transparent {
+        [a, b, mix(2, 1), mix(1, 2)]
+    } else {
+        [a, b, mix(1, 1), [0, 0, 0, 0]]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    std::array::from_fn(|i| palette[(indices >> (2 * i)) as usize & 3])
+}
+
+fn decode_bc1(block : &[u8]) -> [[u8; 4]; 16] {
+    decode_colors(block, true)
+}
+
+/// BC1 colors, after 4 bits of alpha for each pixel.
+fn decode_bc2(block : &[u8]) -> [[u8; 4]; 16] {
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    let mut texels = decode_colors(&block[8..], false);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = (alpha >> (4 * i) & 0xF) as u8 * 17;
+    }
+    texels
+}
+
+/// BC1 colors, after alpha interpolated between two endpoints.
+fn decode_bc3(block : &[u8]) -> [[u8; 4]; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let palette : [u8; 8] = std::array::from_fn(|i| match i {
+        0 => a0 as u8,
+        1 => a1 as u8,
+        _ if a0 > a1 => ((a0 * (8 - i as u32) + a1 * (i as u32 - 1)) / 7) as u8,
+        6 => 0,
+        7 => 255,
+        _ => ((a0 * (6 - i as u32) + a1 * (i as u32 - 1)) / 5) as u8
+    });
+
+    let mut indices = [0; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+
+    let mut texels = decode_colors(&block[8..], false);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = palette[(indices >> (3 * i)) as usize & 7];
+    }
+    texels
+}
+
+/// How the bits of a BC7 block are laid out, for each of its 8 modes.
+struct Bc7Mode {
+    subsets : usize,
+    partition_bits : u32,
+    rotation_bits : u32,
+    index_selection_bits : u32,
+    color_bits : u32,
+    alpha_bits : u32,
+    /// A P-bit for each endpoint.
+    endpoint_p_bits : bool,
+    /// A P-bit for each subset, shared by both its endpoints.
+    shared_p_bits : bool,
+    index_bits : u32,
+    /// Modes 4 and 5 have a second set of indices, for alpha (or color, with the index selection bit).
+    second_index_bits : u32,
+}
+
+const BC7_MODES : [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 3, second_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: true, index_bits: 3, second_index_bits: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, second_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, second_index_bits: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, second_index_bits: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, second_index_bits: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_p_bits: true, shared_p_bits: false, index_bits: 4, second_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, second_index_bits: 0 },
+];
+
+/// Which subset each pixel of a 2 subset partition is in, as a bit per pixel.
+const BC7_PARTITIONS_2 : [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE, 0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// Which subset each pixel of a 3 subset partition is in.
+const BC7_PARTITIONS_3 : [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2], [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1], [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2], [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1], [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2], [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2], [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2], [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2], [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2], [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2], [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2], [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2], [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0], [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0], [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2], [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1], [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2], [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2], [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0], [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0], [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1], [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1], [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1], [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1], [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2], [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2], [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2], [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2], [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2], [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1], [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2], [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+/// The pixel whose index is a bit shorter (its top bit is always 0), for the second subset of each 2 subset partition.
+/// The first subset's is always pixel 0.
+const BC7_ANCHORS_2 : [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor pixels of the second and third subsets of each 3 subset partition.
+const BC7_ANCHORS_3 : [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+        8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+        15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+/// How far (out of 64) each index is from the first endpoint to the second, by how many bits indices have.
+const BC7_WEIGHTS_2 : [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3 : [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4 : [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Reads a BC7 block from its lowest bit up.
+struct Bits {
+    value : u128,
+    position : u32,
+}
+
+impl Bits {
+    fn read(&mut self, count : u32) -> u32 {
+        let value = (self.value >> self.position) as u32 & ((1 << count) - 1);
+        self.position += count;
+        value
+    }
+}
+
+fn decode_bc7(block : &[u8]) -> [[u8; 4]; 16] {
+    // The mode is how many 0 bits come before the first 1. Blocks without one are reserved, and decode to transparent black:
+    let Some(mode_index) = (0..8).find(|i| block[0] & (1 << i) != 0) else {
+        return [[0; 4]; 16];
+    };
+    let mode = &BC7_MODES[mode_index];
+    let mut bits = Bits {
+        value: u128::from_le_bytes(block.try_into().unwrap()),
+        position: mode_index as u32 + 1
+    };
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // Every endpoint's red, then every endpoint's green, and so on:
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..4 {
+        let channel_bits = if channel == 3 { mode.alpha_bits } else { mode.color_bits };
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[channel] = bits.read(channel_bits);
+        }
+    }
+
+    // P-bits are the lowest bit of every channel of an endpoint:
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        let p_bits : Vec<u32> = if mode.endpoint_p_bits {
+            (0..endpoint_count).map(|_| bits.read(1)).collect()
+        } else {
+            (0..mode.subsets).flat_map(|_| [bits.read(1); 2]).collect()
+        };
+        for (endpoint, p) in endpoints.iter_mut().zip(p_bits) {
+            for value in endpoint.iter_mut() {
+                *value = *value << 1 | p;
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+
+    let expand = |value : u32, bits : u32| -> u8 {
+        let value = value << (8 - bits);
+        (value | value >> bits) as u8
+    };
+    let endpoints : [[u8; 4]; 6] = endpoints.map(|e| [
+        expand(e[0], color_bits),
+        expand(e[1], color_bits),
+        expand(e[2], color_bits),
+        if alpha_bits > 0 { expand(e[3], alpha_bits) } else { 255 }
+    ]);
+
+    let subset_of = |pixel : usize| -> usize {
+        match mode.subsets {
+            1 => 0,
+            2 => (BC7_PARTITIONS_2[partition] >> pixel) as usize & 1,
+            _ => BC7_PARTITIONS_3[partition][pixel] as usize
+        }
+    };
+    let is_anchor = |pixel : usize| -> bool {
+        pixel == 0 || match mode.subsets {
+            2 => pixel == BC7_ANCHORS_2[partition] as usize,
+            3 => BC7_ANCHORS_3.iter().any(|anchors| pixel == anchors[partition] as usize),
+            _ => false
+        }
+    };
+
+    let mut indices = [0u32; 16];
+    for (pixel, index) in indices.iter_mut().enumerate() {
+        *index = bits.read(mode.index_bits - is_anchor(pixel) as u32);
+    }
+    let mut second_indices = [0u32; 16];
+    if mode.second_index_bits > 0 {
+        for (pixel, index) in second_indices.iter_mut().enumerate() {
+            *index = bits.read(mode.second_index_bits - (pixel == 0) as u32);
+        }
+    }
+
+    let interpolate = |e0 : u8, e1 : u8, index : u32, bits : u32| -> u8 {
+        let weight = match bits {
+            2 => BC7_WEIGHTS_2[index as usize],
+            3 => BC7_WEIGHTS_3[index as usize],
+            _ => BC7_WEIGHTS_4[index as usize]
+        };
+        (((64 - weight) * e0 as u32 + weight * e1 as u32 + 32) >> 6) as u8
+    };
+
+    std::array::from_fn(|pixel| {
+        let subset = subset_of(pixel);
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+
+        let primary = (indices[pixel], mode.index_bits);
+        let secondary = (second_indices[pixel], mode.second_index_bits);
+        let ((color_index, color_index_bits), (alpha_index, alpha_index_bits)) = match (mode.second_index_bits, index_selection) {
+            (0, _) => (primary, primary),
+            (_, 0) => (primary, secondary),
+            _ => (secondary, primary)
+        };
+
+        let mut texel = [0, 1, 2, 3].map(|c| match c {
+            3 => interpolate(e0[c], e1[c], alpha_index, alpha_index_bits),
+            _ => interpolate(e0[c], e1[c], color_index, color_index_bits)
+        });
+        // Modes 4 and 5 can swap alpha with a color channel:
+        if rotation > 0 {
+            texel.swap(rotation as usize - 1, 3);
+        }
+        texel
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED : [u8; 4] = [255, 0, 0, 255];
+    const GREEN : [u8; 4] = [0, 255, 0, 255];
+    const BLUE : [u8; 4] = [0, 0, 255, 255];
+
+    /// Writes a BC7 block from its lowest bit up, the way [`Bits`] reads it.
+    #[derive(Default)]
+    struct BitWriter {
+        value : u128,
+        position : u32,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, count : u32, value : u32) -> &mut Self {
+            assert!(value < 1 << count);
+            self.value |= (value as u128) << self.position;
+            self.position += count;
+            self
+        }
+
+        /// The block, with the bits after the last ones written left at 0.
+        fn block(&self) -> [u8; 16] {
+            assert!(self.position <= 128);
+            self.value.to_le_bytes()
+        }
+    }
+
+    /// A compressed DDS texture with the given FourCC.
+    fn dds(width : u32, height : u32, four_cc : &[u8; 4], blocks : &[u8]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[..4].copy_from_slice(b"DDS ");
+        data[4..8].copy_from_slice(&124u32.to_le_bytes());
+        data[12..16].copy_from_slice(&height.to_le_bytes());
+        data[16..20].copy_from_slice(&width.to_le_bytes());
+        data[76..80].copy_from_slice(&32u32.to_le_bytes());
+        data[80..84].copy_from_slice(&DDPF_FOURCC.to_le_bytes());
+        data[84..88].copy_from_slice(four_cc);
+        data.extend(blocks);
+        data
+    }
+
+    /// A BC1 block's colors, with the index of pixel `i` at `indices[i]`.
+    fn bc1(c0 : u16, c1 : u16, indices : [u32; 16]) -> [u8; 8] {
+        let indices = indices.iter().enumerate().fold(0u32, |all, (i, index)| all | index << (2 * i));
+        let mut block = [0; 8];
+        block[..2].copy_from_slice(&c0.to_le_bytes());
+        block[2..4].copy_from_slice(&c1.to_le_bytes());
+        block[4..].copy_from_slice(&indices.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn bc1_colors() {
+        let indices = std::array::from_fn(|i| i as u32 % 4);
+
+        // Red over blue has 4 colors:
+        let texels = decode_bc1(&bc1(0xF800, 0x001F, indices));
+        assert_eq!(texels[..4], [RED, BLUE, [170, 0, 85, 255], [85, 0, 170, 255]]);
+
+        // Blue under red has 3, and transparent black:
+        let texels = decode_bc1(&bc1(0x001F, 0xF800, indices));
+        assert_eq!(texels[..4], [BLUE, RED, [127, 0, 127, 255], [0, 0, 0, 0]]);
+
+        // Which BC3 doesn't have:
+        let mut block = [0; 16];
+        block[8..].copy_from_slice(&bc1(0x001F, 0xF800, indices));
+        assert_eq!(decode_bc3(&block)[3], [170, 0, 85, 0]);
+    }
+
+    #[test]
+    fn bc3_alpha() {
+        let alpha_block = |a0 : u8, a1 : u8| {
+            let indices = (0..16u64).fold(0u64, |all, i| all | (i % 8) << (3 * i));
+            let mut block = [0; 16];
+            block[0] = a0;
+            block[1] = a1;
+            block[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+            block[8..].copy_from_slice(&bc1(0xF800, 0x001F, [0; 16]));
+            decode_bc3(&block).map(|texel| texel[3])
+        };
+
+        // 8 alphas between the endpoints:
+        assert_eq!(alpha_block(255, 0)[..8], [255, 0, 218, 182, 145, 109, 72, 36]);
+        // Or 6, then 0 and 255:
+        assert_eq!(alpha_block(0, 255)[..8], [0, 255, 51, 102, 153, 204, 0, 255]);
+    }
+
+    #[test]
+    fn bc7_mode_6_p_bits() {
+        // Mode 6: one subset, 7 bit RGBA endpoints, a P-bit for each, and 4 bit indices:
+        let mut block = BitWriter::default();
+        block.write(7, 1 << 6);
+        for _ in 0..4 {
+            block.write(7, 0).write(7, 127);
+        }
+        block.write(1, 0).write(1, 1);
+        block.write(3, 0).write(4, 15).write(4, 8);
+        for _ in 3..16 {
+            block.write(4, 15);
+        }
+
+        let texels = decode_bc7(&block.block());
+        assert_eq!(texels[0], [0; 4]);
+        assert_eq!(texels[1], [255; 4]);
+        assert_eq!(texels[2], [135; 4]);
+
+        // Without the second P-bit, the second endpoint is a step short of white:
+        let without = (block.value & !(1 << 64)).to_le_bytes();
+        assert_eq!(decode_bc7(&without)[1], [254; 4]);
+    }
+
+    #[test]
+    fn bc7_mode_1_partition_and_anchor() {
+        // Mode 1: two subsets, 6 bit RGB endpoints with a P-bit shared by each subset, and 3 bit indices.
+        // Partition 17 has pixels 1, 2, 3 and 7 in the second subset, which is anchored at pixel 2:
+        let mut block = BitWriter::default();
+        block.write(2, 1 << 1).write(6, 17);
+        // Red and red, then black and blue:
+        for channel in [[63, 63, 0, 0], [0; 4], [0, 0, 0, 63]] {
+            for value in channel {
+                block.write(6, value);
+            }
+        }
+        block.write(1, 1).write(1, 1);
+        // The anchors' indices are a bit shorter, pixel 0 for the first subset included:
+        for pixel in 0..16 {
+            match pixel {
+                0 => block.write(2, 1),
+                2 => block.write(2, 3),
+                1 | 3 | 7 => block.write(3, 7),
+                _ => block.write(3, 5)
+            };
+        }
+
+        // The P-bits are the lowest bit of every channel, so 0 comes out as 2:
+        let (red, blue) = ([255, 2, 2, 255], [2, 2, 255, 255]);
+        let texels = decode_bc7(&block.block());
+        assert_eq!(texels[..8], [red, blue, [2, 2, 109, 255], blue, red, red, red, blue]);
+        assert!(texels[8..].iter().all(|texel| *texel == red), "{texels:?}");
+    }
+
+    #[test]
+    fn bc7_mode_2_three_subsets() {
+        // Mode 2: three subsets, 5 bit RGB endpoints, and 2 bit indices, all 0 so each pixel is its subset's first endpoint:
+        let mut block = BitWriter::default();
+        block.write(3, 1 << 2).write(6, 0);
+        for channel in [[31, 31, 0, 0, 0, 0], [0, 0, 31, 31, 0, 0], [0, 0, 0, 0, 31, 31]] {
+            for value in channel {
+                block.write(5, value);
+            }
+        }
+
+        let colors = [RED, GREEN, BLUE];
+        let expected = BC7_PARTITIONS_3[0].map(|subset| colors[subset as usize]);
+        assert_eq!(decode_bc7(&block.block()), expected);
+    }
+
+    #[test]
+    fn bc7_reserved_mode() {
+        assert_eq!(decode_bc7(&[0; 16]), [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn textures_that_are_not_a_multiple_of_4() {
+        let blocks = [bc1(0xF800, 0xF800, [0; 16]), bc1(0x001F, 0x001F, [0; 16])].concat();
+        let image = read_image(&dds(6, 2, b"DXT1", &blocks)).unwrap();
+        assert_eq!((image.width, image.height), (6, 2));
+        assert_eq!(image.pixels, [&[RED; 4][..], &[BLUE; 2], &[RED; 4], &[BLUE; 2]].concat());
+
+        assert!(read_image(&dds(8, 8, b"DXT1", &blocks)).unwrap_err().to_string().contains("ends before its last block"));
+        assert!(read_image(&dds(4, 4, b"ATI2", &blocks)).unwrap_err().to_string().contains("Unsupported DDS format"));
+    }
+}
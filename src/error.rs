@@ -0,0 +1,198 @@
+use std::fmt;
+
+use crate::esm::DataVersion;
+
+/// Where in the plugin something went wrong, as far as we know.
+#[derive(Debug, Default, Clone)]
+pub struct Location {
+	/// Offset of the record (or group) from the start of the file.
+	pub offset : Option<u64>,
+	pub form_id : Option<u32>,
+	pub record_type : Option<String>,
+	/// File name of the plugin, when reading more than one.
+	pub plugin : Option<String>,
+}
+
+impl fmt::Display for Location {
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut parts = Vec::new();
+		if let Some(ty) = &self.record_type {
+			parts.push(ty.clone());
+		}
+		if let Some(id) = self.form_id {
+			parts.push(format!("{id:08X}"));
+		}
+		if let Some(offset) = self.offset {
+			parts.push(format!("at offset {offset:#X}"));
+		}
+		if let Some(plugin) = &self.plugin {
+			parts.push(format!("in {plugin}"));
+		}
+		write!(f, "{}", parts.join(" "))
+	}
+}
+
+#[derive(Debug)]
+pub enum Error {
+	Io {
+		source : std::io::Error,
+		at : Location
+	},
+	/// A record, group or field type that doesn't belong where we found it.
+	BadSignature {
+		expected : String,
+		found : String,
+		at : Location
+	},
+	/// A record or group ends before its header (or the data in it) says it should.
+	TruncatedRecord {
+		at : Location
+	},
+	/// A compressed record's zlib data is broken, or doesn't decompress to the size it claims.
+	Decompression {
+		source : std::io::Error,
+		at : Location
+	},
+	MissingSubrecord {
+		subrecord : &'static str,
+		at : Location
+	},
+	/// The TES4 header's version isn't one we can read with the data version we were given.
+	UnsupportedVersion {
+		version : f32,
+		data_version : DataVersion,
+		at : Location
+	},
+	/// The TES4 header's version isn't one of any game we know, so we can't tell how to read the plugin.
+	UnknownVersion {
+		version : f32,
+		at : Location
+	},
+	/// A plugin's master isn't loaded before it, so its FormIDs can't be resolved.
+	MissingMaster {
+		master : String,
+		at : Location
+	},
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+	/// For archives, meshes, textures and mappings that aren't what we expect. Those aren't in a plugin, so there's no location.
+	pub fn invalid_data(message : impl Into<String>) -> Self {
+		Error::Io {
+			source: std::io::Error::new(std::io::ErrorKind::InvalidData, message.into()),
+			at: Location::default()
+		}
+	}
+
+	/// Running out of bytes in an archive, mesh or texture isn't a record cut short, so this says which `file` ended early instead.
+	pub fn truncated_file(self, file : &str) -> Self {
+		match self {
+			Error::TruncatedRecord { .. } => Error::invalid_data(format!("{file} ends early.")),
+			e => e
+		}
+	}
+
+	pub fn location(&self) -> &Location {
+		match self {
+			Error::Io { at, .. } | Error::BadSignature { at, .. } | Error::TruncatedRecord { at } | Error::Decompression { at, .. }
+			| Error::MissingSubrecord { at, .. } | Error::UnsupportedVersion { at, .. } | Error::UnknownVersion { at, .. } | Error::MissingMaster { at, .. } => at
+		}
+	}
+
+	fn location_mut(&mut self) -> &mut Location {
+		match self {
+			Error::Io { at, .. } | Error::BadSignature { at, .. } | Error::TruncatedRecord { at } | Error::Decompression { at, .. }
+			| Error::MissingSubrecord { at, .. } | Error::UnsupportedVersion { at, .. } | Error::UnknownVersion { at, .. } | Error::MissingMaster { at, .. } => at
+		}
+	}
+
+	/// Fills in whatever we don't know yet about where this happened.
+	/// The innermost record wins, so a broken LAND is reported as the LAND and not as the CELL it's in.
+	pub fn in_record(mut self, offset : u64, form_id : u32, record_type : &str) -> Self {
+		let at = self.location_mut();
+		at.offset.get_or_insert(offset);
+		at.form_id.get_or_insert(form_id);
+		at.record_type.get_or_insert_with(|| record_type.into());
+		self
+	}
+
+	/// Like `in_record`, for Morrowind records, which don't have FormIDs.
+	pub fn in_morrowind_record(mut self, offset : u64, record_type : &str) -> Self {
+		let at = self.location_mut();
+		at.offset.get_or_insert(offset);
+		at.record_type.get_or_insert_with(|| record_type.into());
+		self
+	}
+
+	/// Fills in where this happened, if we don't know already.
+	pub fn at_offset(mut self, offset : u64) -> Self {
+		self.location_mut().offset.get_or_insert(offset);
+		self
+	}
+
+	/// Fills in which plugin this happened in, if we don't know already.
+	pub fn in_plugin(mut self, plugin : &str) -> Self {
+		self.location_mut().plugin.get_or_insert_with(|| plugin.into());
+		self
+	}
+
+	/// For errors found in a buffer we read out of the file, whose offsets start at `base` instead of the start of the file.
+	pub fn offset_by(mut self, base : u64) -> Self {
+		if let Some(offset) = &mut self.location_mut().offset {
+			*offset += base;
+		}
+		self
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Io { source, .. } => write!(f, "{source}")?,
+			Error::BadSignature { expected, found, .. } => write!(f, "Expected {expected}, found {found:?}")?,
+			Error::TruncatedRecord { .. } => write!(f, "Record is shorter than its header says")?,
+			Error::Decompression { source, .. } => write!(f, "Could not decompress record: {source}")?,
+			Error::MissingSubrecord { subrecord, .. } => write!(f, "Missing {subrecord} subrecord")?,
+			Error::UnsupportedVersion { version, data_version, .. } => {
+				write!(f, "Plugin version {version} can't be read as {data_version:?} data. Is the data version right?")?
+			},
+			Error::UnknownVersion { version, .. } => {
+				write!(f, "Can't tell which game plugin version {version} is from. Pass the data version to read it as that game's data")?
+			},
+			Error::MissingMaster { master, .. } => write!(f, "Master {master} has to be loaded before this plugin")?,
+		}
+
+		let at = self.location();
+		if at.offset.is_some() || at.form_id.is_some() || at.record_type.is_some() || at.plugin.is_some() {
+			write!(f, " ({at})")?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io { source, .. } | Error::Decompression { source, .. } => Some(source),
+			_ => None
+		}
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(source : std::io::Error) -> Self {
+		// Running out of bytes partway through something means whatever we were reading was cut short:
+		if source.kind() == std::io::ErrorKind::UnexpectedEof {
+			Error::TruncatedRecord {
+				at: Location::default()
+			}
+		} else {
+			Error::Io {
+				source,
+				at: Location::default()
+			}
+		}
+	}
+}
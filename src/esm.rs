@@ -1,500 +1,1468 @@
 use core::str;
-use std::{fs::File, io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom}, path::Path};
+use std::{collections::HashMap, fs::File, io::{BufReader, Cursor, Read, Seek, SeekFrom}, path::Path, sync::Arc};
 
 use clap::ValueEnum;
 use flate2::read::ZlibDecoder;
-use indicatif::{ProgressBar, ProgressStyle};
-use rayon::ThreadPoolBuilder;
 
-use crate::world_gen::parse_land;
+use crate::{error::{Error, Location, Result}, load_order::{convert, read_worldspace}, mapping::Mapping, meshes::Meshes, sink::LandSink, tes3, world_gen::{Interpolation, Scale}};
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum DataVersion {
     Skyrim,
-    Oblivion
+    Oblivion,
+    Fallout3,
+    FalloutNV,
+    Fallout4,
+    Morrowind,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct ESMInfo<'a> {
-    version : DataVersion,
-    out_folder : &'a Path
+impl DataVersion {
+    /// Whether a plugin with this TES4 header version is one of ours.
+    fn supports(self, header_version : f32) -> bool {
+        let versions : &[f32] = match self {
+            DataVersion::Skyrim => &[0.94, 1.7, 1.71],
+            DataVersion::Oblivion => &[0.8, 1.0],
+            DataVersion::Fallout3 => &[0.94],
+            DataVersion::FalloutNV => &[1.32, 1.33, 1.34],
+            DataVersion::Fallout4 => &[0.95, 1.0],
+            DataVersion::Morrowind => &[1.2, 1.3],
+        };
+        versions.iter().any(|v| (v - header_version).abs() < 0.001)
+    }
+
+    /// Record and group headers are 20 bytes in Oblivion, and 24 from Fallout 3 on.
+    /// Morrowind's records have 16, and it has no groups.
+    fn header_size(self) -> u32 {
+        match self {
+            DataVersion::Morrowind => 16,
+            DataVersion::Oblivion => 20,
+            DataVersion::Skyrim | DataVersion::Fallout3 | DataVersion::FalloutNV | DataVersion::Fallout4 => 24
+        }
+    }
+
+    /// Editor ID of the game's main worldspace.
+    pub fn default_worldspace(self) -> &'static str {
+        match self {
+            DataVersion::Skyrim | DataVersion::Oblivion => "Tamriel",
+            DataVersion::Fallout3 => "Wasteland",
+            DataVersion::FalloutNV => "WastelandNV",
+            DataVersion::Fallout4 => "Commonwealth",
+            // Morrowind's exterior has no editor ID (or any other worldspace), so we name it after its island:
+            DataVersion::Morrowind => tes3::EXTERIOR,
+        }
+    }
 }
 
-pub struct ESMReader<'a> {
-    info : ESMInfo<'a>,
-    reader : &'a mut BufReader<File>,
+/// The game a plugin was made for, as far as its header tells.
+/// Some of them share a data version, like Skyrim's original and Special Editions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Game {
+    Morrowind,
+    /// Oblivion Remastered's plugins are laid out just the same, so this is either.
+    Oblivion,
+    SkyrimLE,
+    SkyrimSE,
+    Fallout3,
+    FalloutNV,
+    Fallout4,
 }
 
-impl<'a> ESMReader<'a> {
+impl Game {
+    /// Works out the game from the start of a plugin: TES3 or TES4, how big its record headers are, the version in HEDR and the form version.
+    pub fn detect(path : &Path) -> Result<Game> {
+        let file = File::open(path).map_err(|e| std::io::Error::new(e.kind(), format!("Could not open {path:?}: {e}")))?;
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
 
-    fn grab_world_children(&mut self) -> Result<GroupHeader, std::io::Error> {
-        let tes4 = RecordHeader::read(self.reader, self.info.version)?;
-    
-        assert_eq!(tes4.ty, "TES4");
-    
-        self.reader.seek(SeekFrom::Current(tes4.data_size.into()))?;
-    
-        let mut group : GroupHeader;
-    
-        loop {
-            group = GroupHeader::read(self.reader, self.info.version)?;
-            let label = str::from_utf8(&group.label).unwrap();
-            if label == "WRLD" {
-                break;
+        Game::detect_header(&mut BufReader::new(file)).map_err(|e| e.in_plugin(&name))
+    }
+
+    fn detect_header(reader : &mut impl Read) -> Result<Game> {
+        // Enough for the header record's own header and the start of its HEDR field, in every game:
+        let mut buf : [u8; 34] = [0; 34];
+        read_header_bytes(reader, &mut buf[..16], 0)?;
+
+        let signature : [u8; 4] = buf[0..4].try_into().unwrap();
+        if signature == *b"TES3" {
+            return Ok(Game::Morrowind);
+        }
+        if signature != *b"TES4" {
+            return Err(Error::BadSignature {
+                expected: "a TES3 or TES4 record (is this an .esm/.esp file?)".into(),
+                found: String::from_utf8_lossy(&signature).into_owned(),
+                at: Location {
+                    offset: Some(0),
+                    ..Location::default()
+                }
+            });
+        }
+        read_header_bytes(reader, &mut buf[16..], 0)?;
+
+        // HEDR is the first field, so it comes right after the header: 20 bytes in Oblivion, and 24 from Fallout 3 on.
+        let version_at = |start : usize| f32::from_le_bytes(buf[start + 6..start + 10].try_into().unwrap());
+        let is_version = |version : f32, expected : f32| (version - expected).abs() < 0.001;
+
+        let game = if buf[20..24] == *b"HEDR" {
+            let version = version_at(20);
+            DataVersion::Oblivion.supports(version).then_some(Game::Oblivion).ok_or(version)
+        } else if buf[24..28] == *b"HEDR" {
+            let version = version_at(24);
+            // Which version of the record format the header was saved with. Skyrim's are in the 40s, Fallout 3's in the teens:
+            let form_version = u16::from_le_bytes([buf[20], buf[21]]);
+
+            if DataVersion::FalloutNV.supports(version) {
+                Ok(Game::FalloutNV)
+            } else if DataVersion::Fallout4.supports(version) {
+                Ok(Game::Fallout4)
+            } else if is_version(version, 0.94) && form_version < 40 {
+                Ok(Game::Fallout3)
+            } else if DataVersion::Skyrim.supports(version) {
+                // Special Edition saves with form version 44, and Skyrim's original release with 43 (or older):
+                Ok(if form_version >= 44 { Game::SkyrimSE } else { Game::SkyrimLE })
+            } else {
+                Err(version)
             }
-            group.skip_data(self.reader)?;
+        } else {
+            return Err(Error::MissingSubrecord {
+                subrecord: "HEDR",
+                at: Location {
+                    offset: Some(0),
+                    record_type: Some("TES4".into()),
+                    ..Location::default()
+                }
+            });
+        };
+
+        game.map_err(|version| Error::UnknownVersion {
+            version,
+            at: Location {
+                offset: Some(0),
+                record_type: Some("TES4".into()),
+                ..Location::default()
+            }
+        })
+    }
+
+    /// How the game's plugins are laid out.
+    pub fn data_version(self) -> DataVersion {
+        match self {
+            Game::Morrowind => DataVersion::Morrowind,
+            Game::Oblivion => DataVersion::Oblivion,
+            Game::SkyrimLE | Game::SkyrimSE => DataVersion::Skyrim,
+            Game::Fallout3 => DataVersion::Fallout3,
+            Game::FalloutNV => DataVersion::FalloutNV,
+            Game::Fallout4 => DataVersion::Fallout4,
         }
-        
-        let world_record = RecordHeader::read(self.reader, self.info.version)?;
-    
-        assert_eq!(world_record.ty, "WRLD");
-    
-        // We know this is EDID
-        let edid = FieldHeader::read(self.reader, self.info.version)?;
-    
-        let mut world_string_buf : Vec<u8> = Vec::new();
-        self.reader.read_until(b'\0', &mut world_string_buf)?;
-        let world_string = String::from_utf8(world_string_buf).unwrap();
-        assert_eq!(world_string, String::from("Tamriel\0"));
-    
-        self.reader.seek_relative((world_record.data_size - u32::from(edid.size) - FieldHeader::header_size(self.info.version)).into())?;
-        
-        // World Children group:
-        let group = GroupHeader::read(self.reader, self.info.version)?;
-    
-        Ok(group)
     }
+}
+
+impl std::fmt::Display for Game {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Game::Morrowind => "Morrowind",
+            Game::Oblivion => "Oblivion (or Oblivion Remastered)",
+            Game::SkyrimLE => "Skyrim",
+            Game::SkyrimSE => "Skyrim Special Edition",
+            Game::Fallout3 => "Fallout 3",
+            Game::FalloutNV => "Fallout: New Vegas",
+            Game::Fallout4 => "Fallout 4",
+        })
+    }
+}
 
-    pub fn read(version : DataVersion, reader : &'a mut BufReader<File>, out_dir : &'a Path) {
-        let info = ESMInfo {
+/// How to convert a worldspace, other than which one and the block mapping.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    pub scale : Scale,
+    pub interpolation : Interpolation,
+    /// Skip cells we can't read, instead of stopping at the first one.
+    pub keep_going : bool,
+    /// Meshes to turn placed objects into blocks with. Without them, only trees are placed.
+    pub meshes : Option<Arc<Meshes>>,
+}
+
+/// A plugin (.esm or .esp) opened for reading. This is where to start when using this as a library.
+#[derive(Debug)]
+pub struct ESMFile {
+    pub(crate) version : DataVersion,
+    pub(crate) reader : BufReader<File>,
+    header : PluginHeader,
+    /// Where the first top group starts, right after the TES4 header.
+    pub(crate) data_start : u64,
+    /// File name, which is how other plugins name it as their master.
+    pub(crate) name : String,
+    /// Set by [`LoadOrder`](crate::load_order::LoadOrder) when the plugin is loaded with others.
+    pub(crate) form_ids : FormIds,
+}
+
+impl ESMFile {
+    /// Opens a plugin and reads its TES4 header.
+    pub fn open(path : &Path, version : DataVersion) -> Result<Self> {
+        let file = File::open(path).map_err(|e| std::io::Error::new(e.kind(), format!("Could not open {path:?}: {e}")))?;
+        let mut reader = BufReader::new(file);
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+        let header = PluginHeader::read(&mut reader, version).map_err(|e| e.in_plugin(&name))?;
+        let data_start = reader.stream_position()?;
+
+        Ok(ESMFile {
             version,
-            out_folder: out_dir
+            reader,
+            header,
+            data_start,
+            name,
+            form_ids: FormIds::default()
+        })
+    }
+
+    pub fn version(&self) -> DataVersion {
+        self.version
+    }
+
+    pub fn header(&self) -> &PluginHeader {
+        &self.header
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether this is a light plugin (flagged as one, or an .esl), whose FormIDs go in the 0xFE slot of the load order.
+    pub fn is_light(&self) -> bool {
+        let is_esl = Path::new(&self.name).extension().is_some_and(|e| e.eq_ignore_ascii_case("esl"));
+        matches!(self.version, DataVersion::Skyrim | DataVersion::Fallout4) && (self.header.is_light() || is_esl)
+    }
+
+    /// Summarizes every worldspace in the plugin, without converting anything.
+    pub fn worldspaces(&mut self) -> Result<Vec<WorldspaceInfo>> {
+        self.reader.seek(SeekFrom::Start(self.data_start))?;
+        if matches!(self.version, DataVersion::Morrowind) {
+            return tes3::list_worldspaces(&mut self.reader);
+        }
+        list_worldspaces(self.version, &mut self.reader)
+    }
+
+    /// The land textures and water types that cells refer to, which `parse_land` needs.
+    pub fn world_records(&mut self) -> Result<WorldRecords> {
+        self.reader.seek(SeekFrom::Start(self.data_start))?;
+        if matches!(self.version, DataVersion::Morrowind) {
+            return tes3::read_world_records(std::slice::from_mut(self));
+        }
+        let (_, mut records) = WorldRecords::read(&mut self.reader, self.version, &self.form_ids)?;
+        records.link_texture_sets();
+        Ok(records)
+    }
+
+    /// Every cell in the worldspace with the given editor ID, along with its LAND (if it has one).
+    /// Morrowind's cells are each split into 2 x 2 of ours, which come one after another, from the bottom left. Its exterior is read all at once first.
+    pub fn cells(&mut self, worldspace : &str) -> Result<Cells<'_>> {
+        if matches!(self.version, DataVersion::Morrowind) {
+            if !worldspace.eq_ignore_ascii_case(tes3::EXTERIOR) {
+                return Err(worldspace_not_found(worldspace, &[tes3::EXTERIOR.into()]));
+            }
+            let exterior = tes3::ExteriorCells::read(self)?;
+            return Ok(Cells {
+                reader: &mut self.reader,
+                version: self.version,
+                defaults: WorldspaceDefaults::default(),
+                form_ids: &self.form_ids,
+                group_ends: Vec::new(),
+                morrowind: Some(exterior),
+                finished: false
+            });
+        }
+        self.reader.seek(SeekFrom::Start(self.data_start))?;
+
+        let wrld_group = find_top_group(&mut self.reader, self.version, b"WRLD")?;
+        let mut available = Vec::new();
+        let world = find_world_children(&mut self.reader, self.version, wrld_group, &self.form_ids, |edid, _| edid.eq_ignore_ascii_case(worldspace), &mut available)?
+            .ok_or_else(|| worldspace_not_found(worldspace, &available))?;
+        let Some(children) = world.children else {
+            return Err(no_cells(&world.edid));
         };
-        
-        let mut esm_reader = Self {
-            info,
-            reader
+
+        Ok(Cells {
+            reader: &mut self.reader,
+            version: self.version,
+            defaults: world.defaults,
+            form_ids: &self.form_ids,
+            group_ends: vec![children.offset + children.total_size as u64],
+            morrowind: None,
+            finished: false
+        })
+    }
+
+    /// Hands everything in the worldspace with the given editor ID to `sink`.
+    /// With `keep_going`, cells that can't be read are skipped instead of stopping there, and what went wrong with each is returned.
+    pub fn read(&mut self, worldspace : &str, sink : &mut dyn LandSink, keep_going : bool) -> Result<Vec<Error>> {
+        read_worldspace(std::slice::from_mut(self), worldspace, sink, keep_going)
+    }
+
+    /// Converts the worldspace with the given editor ID into .mca files in `out_dir`, replacing any that are there already.
+    /// Returns the cells skipped with [`ConvertOptions::keep_going`].
+    pub fn convert(&mut self, out_dir : &Path, worldspace : &str, mapping : &Mapping, options : ConvertOptions) -> Result<Vec<Error>> {
+        convert(std::slice::from_mut(self), out_dir, worldspace, mapping, options)
+    }
+}
+
+/// Every cell in a worldspace with what's in it, in the order they're in the file. Made by [`ESMFile::cells`].
+///
+/// Skips anything that isn't a cell, and stops after the first error.
+#[derive(Debug)]
+pub struct Cells<'a> {
+    reader : &'a mut BufReader<File>,
+    version : DataVersion,
+    defaults : WorldspaceDefaults,
+    form_ids : &'a FormIds,
+    /// Where each group we're in ends, innermost last.
+    group_ends : Vec<u64>,
+    /// Morrowind's exterior, which is read up front instead.
+    morrowind : Option<tes3::ExteriorCells>,
+    /// After the last cell, or the first error.
+    finished : bool,
+}
+
+impl Iterator for Cells<'_> {
+    type Item = Result<CellContents>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let next = self.read_next().transpose();
+        if !matches!(next, Some(Ok(_))) {
+            self.finished = true;
+        }
+        next
+    }
+}
+
+impl Cells<'_> {
+    fn read_next(&mut self) -> Result<Option<CellContents>> {
+        if let Some(exterior) = &mut self.morrowind {
+            return exterior.next_cell();
+        }
+
+        loop {
+            let position = self.reader.stream_position()?;
+            while self.group_ends.last().is_some_and(|end| position >= *end) {
+                self.group_ends.pop();
+            }
+            if self.group_ends.is_empty() {
+                return Ok(None);
+            }
+
+            if peek_signature(self.reader)? == *b"GRUP" {
+                let group = GroupHeader::read(self.reader, self.version)?;
+                match group.group_ty {
+                    // Exterior cell block and sub-block:
+                    4 | 5 => self.group_ends.push(group.offset + group.total_size as u64),
+                    // Cell children of a cell we skipped, and everything else:
+                    _ => group.skip_data(self.reader)?
+                }
+                continue;
+            }
+
+            let record = RecordHeader::read(self.reader, self.version)?;
+            if record.ty != "CELL" {
+                record.skip_data(self.reader)?;
+                continue;
+            }
+
+            // Only the worldspace's persistent cell is right in the World Children group, outside of any block:
+            let persistent = self.group_ends.len() == 1;
+            return read_cell(self.reader, self.version, self.defaults, &record, persistent, self.form_ids, &|_| true).map(Some);
+        }
+    }
+}
+
+/// Where a plugin is in the load order, which becomes the top bits of its FormIDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LoadIndex {
+    /// 0x00 to 0xFD, counting only full plugins.
+    Full(u8),
+    /// Light plugins all share 0xFE, followed by 12 bits of their own (0x000 to 0xFFF), which leaves them 12 bits of object ID.
+    Light(u16),
+}
+
+impl LoadIndex {
+    fn form_id(self, id : u32) -> u32 {
+        match self {
+            LoadIndex::Full(index) => (index as u32) << 24 | (id & 0x00FFFFFF),
+            LoadIndex::Light(index) => 0xFE000000 | (index as u32) << 12 | (id & 0x00000FFF)
+        }
+    }
+}
+
+/// Turns the FormIDs in a plugin into FormIDs in the load order.
+/// The top byte of a FormID says which of the plugin's masters it comes from, or (past the last one) the plugin itself.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FormIds {
+    /// Load order index of each master, then of the plugin itself.
+    /// Empty for a plugin read on its own, whose FormIDs we leave as they are.
+    indices : Vec<LoadIndex>,
+}
+
+impl FormIds {
+    pub(crate) fn new(indices : Vec<LoadIndex>) -> Self {
+        FormIds { indices }
+    }
+
+    /// The plugin's own load order index, unless it's read on its own.
+    pub(crate) fn own(&self) -> Option<LoadIndex> {
+        self.indices.last().copied()
+    }
+
+    pub(crate) fn resolve(&self, id : u32) -> u32 {
+        let Some(own) = self.indices.last() else {
+            return id;
         };
+        self.indices.get((id >> 24) as usize).unwrap_or(own).form_id(id)
+    }
+}
 
-        let world_group = esm_reader.grab_world_children().expect("Could not grab world children.");
+/// What a worldspace sets for its cells that don't set it themselves.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct WorldspaceDefaults {
+    /// For cells with water that don't set their own height.
+    water_height : Option<f32>,
+    /// WATR FormID.
+    water_type : Option<u32>,
+    /// CLMT FormID.
+    climate : Option<u32>,
+}
 
-        // If we're in Oblivion, the ROAD record is first:
-        let road_read = if matches!(esm_reader.info.version, DataVersion::Oblivion) {
-            let road = RecordHeader::read(esm_reader.reader, esm_reader.info.version).expect("Could not read road header.");
-            road.skip_data(esm_reader.reader).expect("Could not skip ROAD record data.");
-            road.data_size + RecordHeader::header_size(esm_reader.info.version)
-        } else {
-            0
+/// The WRLD record of the worldspace we're after.
+pub(crate) struct FoundWorldspace {
+    pub(crate) edid : String,
+    pub(crate) form_id : u32,
+    pub(crate) defaults : WorldspaceDefaults,
+    /// Header of its World Children group, which the reader is now right after.
+    /// Worldspaces without any cells (and plugins that only change the WRLD record) have none.
+    pub(crate) children : Option<GroupHeader>,
+}
+
+/// Walks the WRLD records in the given WRLD top group until `matches` picks one by its editor ID and (resolved) FormID.
+/// Adds the editor IDs of the ones it passes over to `available`.
+pub(crate) fn find_world_children(reader : &mut (impl Read + Seek), version : DataVersion, group : GroupHeader, form_ids : &FormIds,
+    matches : impl Fn(&str, u32) -> bool, available : &mut Vec<String>) -> Result<Option<FoundWorldspace>> {
+    let mut wrld_left_to_read = group.total_size.checked_sub(GroupHeader::header_size(version)).ok_or_else(|| group.truncated())?;
+
+    while wrld_left_to_read > 0 {
+        let world_record = RecordHeader::read(reader, version)?;
+
+        if world_record.ty != "WRLD" {
+            return Err(world_record.unexpected("a WRLD record"));
+        }
+
+        let world_data = world_record.read_data(reader)?;
+        let edid = read_edid(&mut Cursor::new(world_data.clone()), version).map_err(|e| world_record.locate(e))?.unwrap_or_default();
+
+        wrld_left_to_read = wrld_left_to_read.checked_sub(world_record.data_size + RecordHeader::header_size(version)).ok_or_else(|| world_record.truncated())?;
+
+        // Worldspaces without any cells (or the last record in the group) have no World Children group after them:
+        let has_children = wrld_left_to_read > 0 && peek_signature(reader)? == *b"GRUP";
+
+        let form_id = form_ids.resolve(world_record.id);
+        if matches(&edid, form_id) {
+            let defaults = WorldspaceDefaults {
+                water_height: read_default_water_height(&mut Cursor::new(world_data.clone()), version).map_err(|e| world_record.locate(e))?,
+                water_type: read_form_id_field(&mut Cursor::new(world_data.clone()), version, "NAM2").map_err(|e| world_record.locate(e))?.map(|id| form_ids.resolve(id)),
+                climate: read_form_id_field(&mut Cursor::new(world_data), version, "CNAM").map_err(|e| world_record.locate(e))?.map(|id| form_ids.resolve(id))
+            };
+            return Ok(Some(FoundWorldspace {
+                edid,
+                form_id,
+                defaults,
+                children: if has_children { Some(GroupHeader::read(reader, version)?) } else { None }
+            }));
+        }
+
+        if has_children {
+            let children = GroupHeader::read(reader, version)?;
+            children.skip_data(reader)?;
+            wrld_left_to_read = wrld_left_to_read.checked_sub(children.total_size).ok_or_else(|| children.truncated())?;
+        }
+
+        if !available.contains(&edid) {
+            available.push(edid);
+        }
+    }
+
+    Ok(None)
+}
+
+pub(crate) fn worldspace_not_found(worldspace : &str, available : &[String]) -> Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, format!("Could not find worldspace {worldspace}. Available worldspaces: {}", available.join(", "))).into()
+}
+
+pub(crate) fn no_cells(edid : &str) -> Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, format!("Worldspace {edid} does not have any cells.")).into()
+}
+
+/// Walks the WRLD top group and summarizes every worldspace in it.
+fn list_worldspaces(version : DataVersion, reader : &mut BufReader<File>) -> Result<Vec<WorldspaceInfo>> {
+    let group = find_top_group(reader, version, b"WRLD")?;
+
+    let mut wrld_left_to_read = group.total_size.checked_sub(GroupHeader::header_size(version)).ok_or_else(|| group.truncated())?;
+
+    let mut worldspaces = Vec::new();
+
+    while wrld_left_to_read > 0 {
+        let world_record = RecordHeader::read(reader, version)?;
+
+        if world_record.ty != "WRLD" {
+            return Err(world_record.unexpected("a WRLD record"));
+        }
+
+        let world_data = world_record.read_data(reader)?;
+        let mut world = WorldspaceInfo {
+            edid: read_edid(&mut Cursor::new(world_data.clone()), version).map_err(|e| world_record.locate(e))?.unwrap_or_default(),
+            form_id: world_record.id,
+            parent: read_form_id_field(&mut Cursor::new(world_data), version, "WNAM").map_err(|e| world_record.locate(e))?,
+            cell_count: 0,
+            bounds: None
         };
-    
-        // Read the first cell and its children:
-        let first_world_cell = RecordHeader::read(esm_reader.reader, esm_reader.info.version).expect("Could not read record header.");
-    
-        let (cell_total_read, _) = ESMReader::read_cell(esm_reader.reader, esm_reader.info, first_world_cell).expect("Could not read cell.");
-    
-        let mut world_bytes_left = world_group.total_size - (GroupHeader::header_size(esm_reader.info.version) + cell_total_read + road_read);
-    
-        let bar = ProgressBar::new(world_bytes_left as u64);
-        bar.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:100} {msg}").unwrap());
 
-        let pool = ThreadPoolBuilder::new().thread_name(|i| {
-            format!("Subgroup Reader {i}")
-        }).build().unwrap();
+        wrld_left_to_read = wrld_left_to_read.checked_sub(world_record.data_size + RecordHeader::header_size(version)).ok_or_else(|| world_record.truncated())?;
+
+        if wrld_left_to_read > 0 && peek_signature(reader)? == *b"GRUP" {
+            let children = GroupHeader::read(reader, version)?;
+            wrld_left_to_read = wrld_left_to_read.checked_sub(children.total_size).ok_or_else(|| children.truncated())?;
+
+            let children_size = children.total_size.checked_sub(GroupHeader::header_size(version)).ok_or_else(|| children.truncated())?;
+            world.scan_cells(reader, version, children_size, false)?;
+        }
+
+        worldspaces.push(world);
+    }
+
+    Ok(worldspaces)
+}
+
+/// Reads a CELL record and its children, resolving the FormIDs in them with `form_ids`.
+/// `persistent` is for the worldspace's persistent cell, the one outside of the exterior cell blocks.
+/// Only the LAND and REFR records that `keep` picks are read; the rest are skipped.
+pub(crate) fn read_cell(reader : &mut (impl Read + Seek), version : DataVersion, defaults : WorldspaceDefaults, cell : &RecordHeader, persistent : bool,
+    form_ids : &FormIds, keep : &dyn Fn(&RecordHeader) -> bool) -> Result<CellContents> {
+    let c = read_cell_data(reader, version, defaults, cell, persistent, form_ids).map_err(|e| cell.locate(e))?;
+
+    let (land, references) = read_cell_refs(reader, version, &c, form_ids, keep).map_err(|e| cell.locate(e))?;
+
+    Ok(CellContents {
+        cell: c,
+        land,
+        references
+    })
+}
+
+fn read_cell_data(reader : &mut (impl Read + Seek), version : DataVersion, defaults : WorldspaceDefaults, cell : &RecordHeader, persistent : bool, form_ids : &FormIds) -> Result<Cell> {
+    let cell_data = cell.read_data(reader)?;
+    let size = cell_data.len() as u64;
+    let mut r = Cursor::new(cell_data);
     
-        // Because the only dependency for spawning threads is that we have to read the next chunk of bytes,
-        // we can only demand that threads finish once we're done reading the whole file. Hence the scope call:
-        pool.scope(|scope| {
-            while world_bytes_left > 0 {
-                let block = GroupHeader::read(esm_reader.reader, esm_reader.info.version).expect("Could not read group header.");
-                
-                let mut block_left_to_read = block.total_size - GroupHeader::header_size(esm_reader.info.version);
-                
-                while block_left_to_read > 0 {
-                    let subblock = GroupHeader::read(esm_reader.reader, esm_reader.info.version).expect("Could not read group header.");
-                    
-                    block_left_to_read -= subblock.total_size;
+    let mut x : i32 = i32::MAX;
+    let mut y : i32 = i32::MAX;
+
+    let mut water_height : Option<f32> = None;
+
+    let mut has_water : bool = false;
+
+    let mut water_type : Option<u32> = None;
+
+    let mut regions : Vec<u32> = Vec::new();
+
+    while r.position() < size {
+        let field = FieldHeader::read(&mut r, version)?;
+        // Cell location:
+        match field.ty.as_str() {
+            "XCLC" => {
+                let mut buf : [u8; 4] = [0; 4];
+
+                r.read_exact(&mut buf)?;
+                x = i32::from_le_bytes(buf);
+
+                r.read_exact(&mut buf)?;
+                y = i32::from_le_bytes(buf);
+
+                // Past Oblivion, flags about hiding quads that we will probably never care about:
+                r.seek_relative(field.size as i64 - 8)?;
+            },
+            "DATA" => {
+                match version {
+                    DataVersion::Oblivion | DataVersion::Fallout3 | DataVersion::FalloutNV | DataVersion::Morrowind => {
+                        let mut flags : [u8; 1] = [0];
+                        r.read_exact(&mut flags)?;
+
+                        has_water = (flags[0] & 0x02) == 0x02;
+                    },
+                    DataVersion::Skyrim | DataVersion::Fallout4 => {
+                        let mut buf : [u8; 2] = [0; 2];
+
+                        r.read_exact(&mut buf)?;
+                        
+                        let flags = u16::from_le_bytes(buf);
+                        has_water = (flags & 0x0002) == 0x0002;
+                    }
+                }
 
-                    let mut subblock_buf = vec![0; (subblock.total_size - GroupHeader::header_size(esm_reader.info.version)) as usize];
+                // Default water height:
+                if has_water && matches!(version, DataVersion::Oblivion) {
+                    water_height = Some(0.0);
+                }
+            },
+            "XCLW" => {
+                if has_water {
+                    let mut buf : [u8; 4] = [0; 4];
+
+                    r.read_exact(&mut buf)?;
+
+                    let h = f32::from_le_bytes(buf);
+                    let h_bytes = u32::from_le_bytes(buf);
+                    
+                    // Games after Oblivion use these to mean "use the worldspace's default", which we fall back to below:
+                    let height = if !matches!(version, DataVersion::Oblivion) {
+                        if h_bytes == 0x7F7FFFFF || h_bytes == 0x4F7FFFC9 || h_bytes == 0xCF000000 {
+                            None
+                        } else {
+                            Some(h)
+                        }
+                    } else {
+                        Some(h)
+                    };
+                    
+                    water_height = height;
+                }
+            },
+            "XCWT" => {
+                let mut buf : [u8; 4] = [0; 4];
 
-                    esm_reader.reader.read_exact(&mut subblock_buf).expect("Could not read subblock.");
+                r.read_exact(&mut buf)?;
+                water_type = Some(form_ids.resolve(u32::from_le_bytes(buf)));
+            },
+            "XCLR" => {
+                let mut buf : [u8; 4] = [0; 4];
 
-                    let bar = bar.clone();
-                    // Then, because each CELL record is of indeterminate size, we just treat each subblock read as its own thread:
-                    scope.spawn(move |_| {
-                        ESMReader::read_subblock(subblock_buf, esm_reader.info, subblock, bar);
-                    });
+                for _ in 0..field.size / 4 {
+                    r.read_exact(&mut buf)?;
+                    regions.push(form_ids.resolve(u32::from_le_bytes(buf)));
                 }
-        
-                world_bytes_left -= block.total_size;
+                r.seek_relative((field.size % 4) as i64)?;
+            },
+            _ => {
+                field.skip_data(&mut r)?;
             }
+        }
+    }
+    // The persistent cell isn't anywhere in particular, so it doesn't always have a position:
+    if persistent && (x == i32::MAX || y == i32::MAX) {
+        x = 0;
+        y = 0;
+    }
+    if x == i32::MAX || y == i32::MAX {
+        return Err(Error::MissingSubrecord {
+            subrecord: "XCLC",
+            at: Location::default()
         });
-        bar.finish();
     }
 
-    fn read_subblock(buf : Vec<u8>, info : ESMInfo, subblock : GroupHeader, bar : ProgressBar) {
-        let mut reader = Cursor::new(buf);
+    if has_water && water_height.is_none() {
+        water_height = defaults.water_height;
+    }
+
+    let water_type = water_type.or(defaults.water_type);
+
+    Ok(Cell {x, y, water_height, water_type, persistent, regions, climate: defaults.climate})
+}
+
+/// Reads the Cell Children group right after a CELL record, if it has one. Returns the cell's LAND and placed objects.
+fn read_cell_refs(reader : &mut (impl Read + Seek), version : DataVersion, cell : &Cell, form_ids : &FormIds,
+    keep : &dyn Fn(&RecordHeader) -> bool) -> Result<(Option<Land>, Vec<Reference>)> {
+    let mut land = None;
+    let mut references = Vec::new();
+
+    // Cells without any children are followed straight away by the next cell (or nothing at all):
+    let Ok(signature) = peek_signature(reader) else {
+        return Ok((land, references));
+    };
+    if signature != *b"GRUP" {
+        return Ok((land, references));
+    }
+
+    let cell_child_grp = GroupHeader::read(reader, version)?;
+    if cell_child_grp.group_ty != 6 {
+        reader.seek_relative(-(GroupHeader::header_size(version) as i64))?;
+        return Ok((land, references));
+    }
+
+
+    let mut left_to_read = cell_child_grp.total_size.checked_sub(GroupHeader::header_size(version)).ok_or_else(|| cell_child_grp.truncated())?;
+
+    while left_to_read > 0 {
+        let child = GroupHeader::read(reader, version)?;
+        if child.ty != "GRUP" {
+            return Err(child.unexpected("a Cell Children subgroup"));
+        }
+        left_to_read = left_to_read.checked_sub(child.total_size).ok_or_else(|| child.truncated())?;
+
+        // LAND is always in the temporary children (9), and REFR can be in there or the persistent children (8).
+        // Oblivion also has visible distant children (10), which are temporary references seen from afar:
+        if !matches!(child.group_ty, 8..=10) {
+            child.skip_data(reader)?;
+            continue;
+        }
+        let persistent = child.group_ty == 8;
+
+        let mut temp_left_to_read = child.total_size.checked_sub(GroupHeader::header_size(version)).ok_or_else(|| child.truncated())?;
+
+        while temp_left_to_read > 0 {
+            let record_header = RecordHeader::read(reader, version)?;
+            temp_left_to_read = temp_left_to_read.checked_sub(record_header.data_size + RecordHeader::header_size(version)).ok_or_else(|| record_header.truncated())?;
+
+            match record_header.ty.as_str() {
+                "LAND" if keep(&record_header) => {
+                    land = Land::read(reader, version, cell.clone(), &record_header, form_ids).map_err(|e| record_header.locate(e))?;
+                },
+                "REFR" if keep(&record_header) => {
+                    references.push(Reference::read(reader, version, &record_header, persistent, form_ids).map_err(|e| record_header.locate(e))?);
+                },
+                _ => {
+                    record_header.skip_data(reader)?;
+                }
+            }
+        }
+    }
+    Ok((land, references))
+}
+
+/// Skips the Cell Children group after a CELL record, if there is one.
+pub(crate) fn skip_cell_children(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<()> {
+    if peek_signature(reader).is_ok_and(|s| s == *b"GRUP") {
+        let children = GroupHeader::read(reader, version)?;
+        if children.group_ty == 6 {
+            children.skip_data(reader)?;
+        } else {
+            reader.seek_relative(-(GroupHeader::header_size(version) as i64))?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) trait DataHeader : Sized {
+    fn header_size(version : DataVersion) -> u32;
+    fn read(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<Self>;
+    fn skip_data(&self, reader : &mut (impl Read + Seek)) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RecordHeader {
+    /// Where the record starts in whatever we read it from.
+    pub offset : u64,
+    pub ty : String,
+    pub data_size : u32,
+    pub flags : u32,
+    pub id : u32,
+}
+
+impl DataHeader for RecordHeader {
+    fn header_size(version : DataVersion) -> u32 {
+        version.header_size()
+    }
+
+    fn read(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<Self> {
+        let mut buf : [u8; 4] = [0; 4];
+
+        let offset = reader.stream_position()?;
+
+        read_header_bytes(reader, &mut buf, offset)?;
+        let ty = read_signature(buf, "a record type", offset)?;
 
-        let mut subblock_left_to_read = subblock.total_size - GroupHeader::header_size(info.version);
+        read_header_bytes(reader, &mut buf, offset)?;
+        let data_size = u32::from_le_bytes(buf);
 
-        while subblock_left_to_read > 0 {
-            let cell = RecordHeader::read(&mut reader, info.version).expect("Could not read record header.");
+        read_header_bytes(reader, &mut buf, offset)?;
+        let mut flags = u32::from_le_bytes(buf);
 
-            let (left, c) = ESMReader::read_cell(&mut reader, info, cell).expect("Could not read cell.");
-            
-            bar.set_message(format!("Cell {},{}", c.x, c.y));
-            // Not exactly accurate for going up to 100% (we miss counting a lot of the headers for groups), but the files are so big I think this is okay:
-            bar.inc(left as u64);
+        read_header_bytes(reader, &mut buf, offset)?;
+        let mut id = u32::from_le_bytes(buf);
 
-            subblock_left_to_read -= left;
+        // Morrowind has an unused field where the flags go in other games, and its flags where they have the FormID:
+        if matches!(version, DataVersion::Morrowind) {
+            flags = id;
+            id = 0;
         }
+
+        // The timestamp, version control info and (from Fallout 3 on) internal version, which we have no use for:
+        let mut rest = [0; 8];
+        read_header_bytes(reader, &mut rest[..version.header_size() as usize - 16], offset)?;
+
+        Ok(RecordHeader {
+            offset,
+            ty,
+            data_size,
+            flags,
+            id
+        })
+    }
+
+    fn skip_data(&self, reader : &mut (impl Read + Seek)) -> Result<()> {
+        Ok(reader.seek_relative(self.data_size.into())?)
     }
+}
 
-    /// Returns bytes read.
-    fn read_cell(reader : &mut (impl Read + Seek), info : ESMInfo, cell : RecordHeader) -> std::io::Result<(u32, Cell)> {
-        let mut chunk = reader.take(cell.data_size as u64);
+impl RecordHeader {
+    pub const COMPRESSED_FLAG : u32 = 0x00040000;
+    pub const DELETED_FLAG : u32 = 0x00000020;
 
-        // If the cell is compressed:
-        let (mut r, size) = if cell.flags & 0x00040000 == 0x00040000 {
+    pub fn is_deleted(&self) -> bool {
+        self.flags & Self::DELETED_FLAG == Self::DELETED_FLAG
+    }
+
+    /// Reads the data of this record, decompressing it if needed.
+    pub(crate) fn read_data(&self, reader : &mut impl Read) -> Result<Vec<u8>> {
+        let mut chunk = reader.take(self.data_size as u64);
+
+        let out = if self.flags & Self::COMPRESSED_FLAG == Self::COMPRESSED_FLAG {
             let mut buf : [u8; 4] = [0; 4];
-            chunk.read_exact(&mut buf)?;
+            chunk.read_exact(&mut buf).map_err(|e| self.locate(e.into()))?;
 
             let decrypted_size = u32::from_le_bytes(buf);
         
-            let mut out_cell = vec![0; decrypted_size as usize];
+            let mut out = vec![0; decrypted_size as usize];
         
-            ZlibDecoder::new(chunk).read_exact(&mut out_cell)?;
+            ZlibDecoder::new(&mut chunk).read_exact(&mut out).map_err(|source| self.locate(Error::Decompression {
+                source,
+                at: Location::default()
+            }))?;
+
+            // The decoder can stop before the zlib checksum, so make sure we end up at the next record:
+            std::io::copy(&mut chunk, &mut std::io::sink())?;
         
-            (Cursor::new(out_cell), decrypted_size)
+            out
         } else {
-            let mut out = Vec::with_capacity(cell.data_size as usize);
+            let mut out = Vec::with_capacity(self.data_size as usize);
             chunk.read_to_end(&mut out)?;
-            (Cursor::new(out), cell.data_size)
+            out
         };
-        
-        let mut x : i32 = i32::MAX;
-        let mut y : i32 = i32::MAX;
 
-        let mut water_height : Option<f32> = None;
+        if chunk.limit() > 0 || (self.flags & Self::COMPRESSED_FLAG == 0 && out.len() < self.data_size as usize) {
+            return Err(self.truncated());
+        }
+        Ok(out)
+    }
+
+    /// Adds this record to where an error happened, if we don't know already.
+    pub(crate) fn locate(&self, e : Error) -> Error {
+        e.in_record(self.offset, self.id, &self.ty)
+    }
 
-        let mut has_water : bool = false;
+    fn truncated(&self) -> Error {
+        self.locate(Error::TruncatedRecord {
+            at: Location::default()
+        })
+    }
 
-        let mut bytes_to_read = size;
+    fn unexpected(&self, expected : &str) -> Error {
+        self.locate(Error::BadSignature {
+            expected: expected.into(),
+            found: self.ty.clone(),
+            at: Location::default()
+        })
+    }
+}
 
-        while bytes_to_read > 0 {
-            let field = FieldHeader::read(&mut r, info.version)?;
-            // Cell location:
-            match field.ty.as_str() {
-                "XCLC" => {
-                    let mut buf : [u8; 4] = [0; 4];
+impl GroupHeader {
+    fn location(&self) -> Location {
+        Location {
+            offset: Some(self.offset),
+            record_type: Some(format!("GRUP (type {})", self.group_ty)),
+            ..Location::default()
+        }
+    }
 
-                    r.read_exact(&mut buf)?;
-                    x = i32::from_le_bytes(buf);
-    
-                    r.read_exact(&mut buf)?;
-                    y = i32::from_le_bytes(buf);
+    /// Adds this group to where an error happened, if we don't know already.
+    pub(crate) fn locate(&self, mut e : Error) -> Error {
+        let at = self.location();
+        if let Error::Io { at: e_at, .. } | Error::TruncatedRecord { at: e_at } = &mut e {
+            if e_at.offset.is_none() {
+                *e_at = at;
+            }
+        }
+        e
+    }
 
-                    if matches!(info.version, DataVersion::Skyrim) {
-                        // Read flags about hiding quads that we will probably never care about:
-                        r.read_exact(&mut buf)?;
-                    }
-                },
-                "DATA" => {
-                    match info.version {
-                        DataVersion::Oblivion => {
-                            let mut flags : [u8; 1] = [0];
-                            r.read_exact(&mut flags)?;
-
-                            has_water = (flags[0] & 0x02) == 0x02;
-                        },
-                        DataVersion::Skyrim => {
-                            let mut buf : [u8; 2] = [0; 2];
-
-                            r.read_exact(&mut buf)?;
-                            
-                            let flags = u16::from_le_bytes(buf);
-                            has_water = (flags & 0x0002) == 0x0002;
-                        }
-                    }
+    pub(crate) fn truncated(&self) -> Error {
+        Error::TruncatedRecord {
+            at: self.location()
+        }
+    }
 
-                    // Default water height:
-                    if has_water && matches!(info.version, DataVersion::Oblivion) {
-                        water_height = Some(0.0);
-                    }
-                },
-                "XCLW" => {
-                    if has_water {
-                        let mut buf : [u8; 4] = [0; 4];
+    fn unexpected(&self, expected : &str) -> Error {
+        Error::BadSignature {
+            expected: expected.into(),
+            found: self.ty.clone(),
+            at: self.location()
+        }
+    }
+}
 
-                        r.read_exact(&mut buf)?;
+/// Reads part of a record or group header starting at `offset`, so we know where it was if the file ends partway through.
+fn read_header_bytes(reader : &mut impl Read, buf : &mut [u8], offset : u64) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| Error::from(e).at_offset(offset))
+}
 
-                        let h = f32::from_le_bytes(buf);
-                        let h_bytes = u32::from_le_bytes(buf);
-                        
-                        let height = if matches!(info.version, DataVersion::Skyrim) {
-                            if h_bytes == 0x7F7FFFFF || h_bytes == 0x4F7FFFC9 || h_bytes == 0xCF000000 {
-                                None
-                            } else {
-                                Some(h)
+/// Record and group types are four letters, digits or underscores.
+fn read_signature(buf : [u8; 4], expected : &str, offset : u64) -> Result<String> {
+    match str::from_utf8(&buf) {
+        Ok(ty) if ty.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') => Ok(ty.into()),
+        _ => Err(Error::BadSignature {
+            expected: expected.into(),
+            found: String::from_utf8_lossy(&buf).into_owned(),
+            at: Location {
+                offset: Some(offset),
+                ..Location::default()
+            }
+        })
+    }
+}
+
+/// Looks at the next four bytes (a record or group type) without consuming them.
+pub(crate) fn peek_signature(reader : &mut (impl Read + Seek)) -> std::io::Result<[u8; 4]> {
+    let mut buf : [u8; 4] = [0; 4];
+    reader.read_exact(&mut buf)?;
+    reader.seek_relative(-4)?;
+    Ok(buf)
+}
+
+/// Skips top-level groups until we find the one holding records of the given type.
+pub(crate) fn find_top_group(reader : &mut (impl Read + Seek), version : DataVersion, label : &[u8; 4]) -> Result<GroupHeader> {
+    loop {
+        let group = GroupHeader::read(reader, version).map_err(|e| match e {
+            Error::TruncatedRecord { .. } => std::io::Error::new(std::io::ErrorKind::NotFound, format!("Could not find a {} group.", String::from_utf8_lossy(label))).into(),
+            e => e
+        })?;
+        if &group.label == label {
+            return Ok(group);
+        }
+        group.skip_data(reader)?;
+    }
+}
+
+/// Records outside of the worldspace that we need to convert it.
+#[derive(Debug, Default, Clone)]
+pub struct WorldRecords {
+    /// By LTEX FormID.
+    pub land_textures : HashMap<u32, LandTexture>,
+    /// Editor IDs of WATR records, by FormID.
+    pub water_types : HashMap<u32, String>,
+    /// Diffuse textures of TXST records, by FormID.
+    pub texture_sets : HashMap<u32, String>,
+    /// The TREE, STAT and FLOR records that placed objects can be trees (or meshes) of, by FormID.
+    pub objects : HashMap<u32, BaseObject>,
+    /// REGN records, by FormID.
+    pub regions : HashMap<u32, WeatherChances>,
+    /// CLMT records, by FormID.
+    pub climates : HashMap<u32, WeatherChances>,
+    /// Editor IDs of WTHR records, by FormID.
+    pub weathers : HashMap<u32, String>,
+}
+
+/// A region (REGN) or climate (CLMT), and the weathers it has.
+#[derive(Debug, Clone, Default)]
+pub struct WeatherChances {
+    pub edid : String,
+    /// WTHR FormIDs, and how likely each one is (out of 100).
+    pub weathers : Vec<(u32, u32)>,
+}
+
+/// A record that objects are placed from.
+#[derive(Debug, Clone)]
+pub struct BaseObject {
+    /// TREE, STAT or FLOR.
+    pub ty : String,
+    pub edid : String,
+    /// Path of its mesh (NIF), from the Data folder's `meshes` folder.
+    pub model : Option<String>,
+}
+
+impl WorldRecords {
+    /// Skips ahead to the WRLD top group, reading the TXST, LTEX, WATR, TREE, STAT, FLOR, WTHR, CLMT and REGN records on the way there.
+    /// Returns the WRLD group header (if the plugin has one), and the records we read with their FormIDs resolved.
+    pub(crate) fn read(reader : &mut (impl Read + Seek), version : DataVersion, form_ids : &FormIds) -> Result<(Option<GroupHeader>, WorldRecords)> {
+        let mut records = WorldRecords::default();
+
+        let wrld_group = loop {
+            // Plugins that don't touch any worldspace end without one:
+            match peek_signature(reader) {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break None,
+                r => r?
+            };
+            let group = GroupHeader::read(reader, version)?;
+
+            match &group.label {
+                b"WRLD" => break Some(group),
+                b"TXST" | b"LTEX" | b"WATR" | b"TREE" | b"STAT" | b"FLOR" | b"WTHR" | b"CLMT" | b"REGN" => {},
+                _ => {
+                    group.skip_data(reader)?;
+                    continue;
+                }
+            }
+
+            let mut left_to_read = group.total_size.checked_sub(GroupHeader::header_size(version)).ok_or_else(|| group.truncated())?;
+
+            while left_to_read > 0 {
+                let record = RecordHeader::read(reader, version)?;
+                left_to_read = left_to_read.checked_sub(record.data_size + RecordHeader::header_size(version)).ok_or_else(|| record.truncated())?;
+
+                let data = record.read_data(reader)?;
+                let size = data.len() as u64;
+                let mut r = Cursor::new(data);
+
+                let mut edid = String::new();
+                let mut texture : Option<String> = None;
+                let mut texture_set : Option<u32> = None;
+                let mut model : Option<String> = None;
+                let mut weathers : Vec<(u32, u32)> = Vec::new();
+
+                let mut read_fields = || -> Result<()> {
+                    while r.position() < size {
+                        let field = FieldHeader::read(&mut r, version)?;
+                        match (record.ty.as_str(), field.ty.as_str()) {
+                            (_, "EDID") => {
+                                edid = read_zstring(&mut r, field.size)?;
+                            },
+                            ("TXST", "TX00") | ("LTEX", "ICON") => {
+                                texture = Some(read_zstring(&mut r, field.size)?);
+                            },
+                            ("TREE" | "STAT" | "FLOR", "MODL") => {
+                                model = Some(read_zstring(&mut r, field.size)?);
+                            },
+                            ("LTEX", "TNAM") => {
+                                let mut buf : [u8; 4] = [0; 4];
+                                r.read_exact(&mut buf)?;
+                                texture_set = Some(form_ids.resolve(u32::from_le_bytes(buf)));
+                            },
+                            // A weather and its chance, and past Oblivion, a global that can stand in for the chance:
+                            ("CLMT", "WLST") | ("REGN", "RDWT") => {
+                                let entry_size = if matches!(version, DataVersion::Oblivion) { 8 } else { 12 };
+                                let mut buf : [u8; 4] = [0; 4];
+
+                                for _ in 0..field.size / entry_size {
+                                    r.read_exact(&mut buf)?;
+                                    let weather = form_ids.resolve(u32::from_le_bytes(buf));
+                                    r.read_exact(&mut buf)?;
+                                    weathers.push((weather, i32::from_le_bytes(buf).max(0) as u32));
+                                    r.seek_relative(entry_size as i64 - 8)?;
+                                }
+                                r.seek_relative((field.size % entry_size) as i64)?;
+                            },
+                            _ => {
+                                field.skip_data(&mut r)?;
                             }
-                        } else {
-                            Some(h)
-                        };
-                        
-                        water_height = height;
+                        }
+                    }
+                    Ok(())
+                };
+                read_fields().map_err(|e| record.locate(e))?;
+
+                let id = form_ids.resolve(record.id);
+                match record.ty.as_str() {
+                    "TXST" => {
+                        if let Some(texture) = texture {
+                            records.texture_sets.insert(id, texture);
+                        }
+                    },
+                    "LTEX" => {
+                        records.land_textures.insert(id, LandTexture { edid, texture, texture_set });
+                    },
+                    "WATR" => {
+                        records.water_types.insert(id, edid);
+                    },
+                    "WTHR" => {
+                        records.weathers.insert(id, edid);
+                    },
+                    "CLMT" => {
+                        records.climates.insert(id, WeatherChances { edid, weathers });
+                    },
+                    "REGN" => {
+                        records.regions.insert(id, WeatherChances { edid, weathers });
+                    },
+                    ty => {
+                        records.objects.insert(id, BaseObject { ty: ty.into(), edid, model });
                     }
-                },
-                _ => {
-                    field.skip_data(&mut r)?;
                 }
             }
-            
-            bytes_to_read -= FieldHeader::header_size(info.version) + (field.size as u32);
-        }
-        if x == i32::MAX || y == i32::MAX {
-            panic!("Could not find Cell x or y.");
-        }
+        };
 
-        let total_read = ESMReader::read_cell_refs(reader, info, Cell {x, y, water_height}).expect("Could not read cell refs.") + cell.data_size + RecordHeader::header_size(info.version);
-        
-        Ok((total_read, Cell{x, y, water_height}))
+        Ok((wrld_group, records))
+    }
+
+    /// Adds the records of a plugin loaded after this one, which override ours.
+    pub(crate) fn merge(&mut self, later : WorldRecords) {
+        self.land_textures.extend(later.land_textures);
+        self.water_types.extend(later.water_types);
+        self.texture_sets.extend(later.texture_sets);
+        self.objects.extend(later.objects);
+        self.regions.extend(later.regions);
+        self.climates.extend(later.climates);
+        self.weathers.extend(later.weathers);
     }
 
-    /// Returns bytes read.
-    fn read_cell_refs(reader : &mut (impl Read + Seek), info : ESMInfo, cell : Cell) -> std::io::Result<u32> {
-        let cell_child_grp = GroupHeader::read(reader, info.version)?;
-        assert_eq!(cell_child_grp.ty, "GRUP");
+    /// Skyrim's LTEX points at a texture set for its diffuse texture, while Oblivion's names the texture directly.
+    /// The set can be in another plugin, so this waits until every plugin is read.
+    pub(crate) fn link_texture_sets(&mut self) {
+        for land_texture in self.land_textures.values_mut() {
+            if let Some(set) = land_texture.texture_set {
+                land_texture.texture = self.texture_sets.get(&set).cloned();
+            }
+        }
+    }
+}
+
+/// Reads a null-terminated string field of the given size.
+pub(crate) fn read_zstring(data : &mut impl Read, size : u32) -> std::io::Result<String> {
+    let mut buf = vec![0; size as usize];
+    data.read_exact(&mut buf)?;
 
-        // Now we find the temporary children group (where LAND is always stored)
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..end]).into_owned())
+}
 
-        let mut temp_child = GroupHeader::read(reader, info.version)?;
-        if temp_child.group_ty != 9 {
-            temp_child.skip_data(reader)?;
+/// Searches a record's (decompressed) data for its EDID field.
+fn read_edid(data : &mut Cursor<Vec<u8>>, version : DataVersion) -> Result<Option<String>> {
+    while (data.position() as usize) < data.get_ref().len() {
+        let field = FieldHeader::read(data, version)?;
+        if field.ty == "EDID" {
+            return Ok(Some(read_zstring(data, field.size)?));
+        }
+        field.skip_data(data)?;
+    }
+    Ok(None)
+}
 
-            temp_child = GroupHeader::read(reader, info.version)?;
+/// Searches a record's (decompressed) data for a field holding a single FormID.
+fn read_form_id_field(data : &mut Cursor<Vec<u8>>, version : DataVersion, ty : &str) -> Result<Option<u32>> {
+    while (data.position() as usize) < data.get_ref().len() {
+        let field = FieldHeader::read(data, version)?;
+        if field.ty == ty {
+            let mut buf : [u8; 4] = [0; 4];
+            data.read_exact(&mut buf)?;
+            return Ok(Some(u32::from_le_bytes(buf)));
         }
-        
-        assert_eq!(temp_child.ty, "GRUP");
+        field.skip_data(data)?;
+    }
+    Ok(None)
+}
 
-        let mut left_to_read = temp_child.total_size - GroupHeader::header_size(info.version);
+/// Searches a WRLD record's (decompressed) data for its default water height (the second float of DNAM).
+/// Oblivion worldspaces don't have one.
+fn read_default_water_height(data : &mut Cursor<Vec<u8>>, version : DataVersion) -> Result<Option<f32>> {
+    if matches!(version, DataVersion::Oblivion) {
+        return Ok(None);
+    }
+
+    while (data.position() as usize) < data.get_ref().len() {
+        let field = FieldHeader::read(data, version)?;
+        if field.ty == "DNAM" {
+            let mut buf : [u8; 4] = [0; 4];
 
-        while left_to_read > 0 {
-            let record_header = RecordHeader::read(reader, info.version)?;
-            if record_header.ty == "LAND" {
-                Land::read(reader, info, cell.clone(), &record_header)?;
-            } else {
-                record_header.skip_data(reader)?;
-            }
+            // Default land height, which we don't need:
+            data.read_exact(&mut buf)?;
 
-            left_to_read -= (record_header.data_size as u32) + RecordHeader::header_size(info.version);
+            data.read_exact(&mut buf)?;
+            return Ok(Some(f32::from_le_bytes(buf)));
         }
-        Ok(cell_child_grp.total_size)
+        field.skip_data(data)?;
     }
+    Ok(None)
 }
 
-pub trait DataHeader : Sized {
-    fn header_size(version : DataVersion) -> u32;
-    fn read(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<Self, std::io::Error>;
-    fn skip_data(&self, reader : &mut (impl Read + Seek)) -> std::io::Result<()>;
+/// Searches a CELL record's (decompressed) data for its grid position. Interior and persistent cells have none.
+pub(crate) fn read_cell_grid(data : &mut Cursor<Vec<u8>>, version : DataVersion) -> Result<Option<(i32, i32)>> {
+    while (data.position() as usize) < data.get_ref().len() {
+        let field = FieldHeader::read(data, version)?;
+        if field.ty == "XCLC" {
+            let mut buf : [u8; 4] = [0; 4];
+
+            data.read_exact(&mut buf)?;
+            let x = i32::from_le_bytes(buf);
+
+            data.read_exact(&mut buf)?;
+            let y = i32::from_le_bytes(buf);
+
+            return Ok(Some((x, y)));
+        }
+        field.skip_data(data)?;
+    }
+    Ok(None)
 }
 
+/// The TES4 record at the start of every plugin.
 #[derive(Debug, Default)]
-struct RecordHeader {
-    pub ty : String,
-    pub data_size : u32,
+pub struct PluginHeader {
+    /// The TES4 record's flags.
     pub flags : u32,
-    pub id : u32,
-    pub timestamp: Option<u16>,
-    pub version_control: u32,
-    pub internal_version : Option<u16>,
-    pub misc : Option<u16>,
+    pub version : f32,
+    pub record_count : u32,
+    pub next_object_id : u32,
+    pub author : Option<String>,
+    pub description : Option<String>,
+    pub masters : Vec<String>,
 }
 
-impl DataHeader for RecordHeader {
-    fn header_size(version : DataVersion) -> u32 {
-        match version {
-            DataVersion::Skyrim => 24,
-            DataVersion::Oblivion => 20
-        }
+impl PluginHeader {
+    pub const MASTER_FLAG : u32 = 0x00000001;
+    pub const LOCALIZED_FLAG : u32 = 0x00000080;
+    /// Skyrim Special Edition only.
+    pub const LIGHT_FLAG : u32 = 0x00000200;
+
+    pub fn is_master(&self) -> bool {
+        self.flags & Self::MASTER_FLAG == Self::MASTER_FLAG
     }
 
-    fn read(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<Self, std::io::Error> {
-        let mut buf : [u8; 4] = [0; 4];
-        let mut buf16 : [u8; 2] = [0; 2];
+    pub fn is_localized(&self) -> bool {
+        self.flags & Self::LOCALIZED_FLAG == Self::LOCALIZED_FLAG
+    }
 
-        let ty : String;
-        reader.read_exact(&mut buf)?;
-        ty = str::from_utf8(&buf).unwrap().into();
+    pub fn is_light(&self) -> bool {
+        self.flags & Self::LIGHT_FLAG == Self::LIGHT_FLAG
+    }
 
-        let data_size : u32;
-        reader.read_exact(&mut buf)?;
-        data_size = u32::from_le_bytes(buf);
+    fn read(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<Self> {
+        let tes4 = RecordHeader::read(reader, version)?;
 
-        let flags : u32;
-        reader.read_exact(&mut buf)?;
-        flags = u32::from_le_bytes(buf);
+        let header_type = if matches!(version, DataVersion::Morrowind) { "TES3" } else { "TES4" };
+        if tes4.ty != header_type {
+            return Err(tes4.unexpected(&format!("a {header_type} record (is this an .esm/.esp file, and is the data version right?)")));
+        }
 
-        let id : u32;
-        reader.read_exact(&mut buf)?;
-        id = u32::from_le_bytes(buf);
+        let data = tes4.read_data(reader)?;
+        let size = data.len() as u64;
+        let mut r = Cursor::new(data);
 
-        
-        let timestamp : Option<u16>;
-        let version_control : u32;
-        let internal_version : Option<u16>;
-        let misc : Option<u16>;
+        let mut header = PluginHeader {
+            flags: tes4.flags,
+            ..PluginHeader::default()
+        };
 
-        match version {
-            DataVersion::Skyrim => {
-                reader.read_exact(&mut buf16)?;
-                timestamp = Some(u16::from_le_bytes(buf16));
-                
-                reader.read_exact(&mut buf16)?;
-                version_control = u16::from_le_bytes(buf16) as u32;
-        
-                reader.read_exact(&mut buf16)?;
-                internal_version = Some(u16::from_le_bytes(buf16));
-        
-                reader.read_exact(&mut buf16)?;
-                misc = Some(u16::from_le_bytes(buf16));
-            },
-            DataVersion::Oblivion => {
-                reader.read_exact(&mut buf)?;
-                version_control = u32::from_le_bytes(buf);
+        let mut read_fields = || -> Result<()> {
+            while r.position() < size {
+                let field = FieldHeader::read(&mut r, version)?;
+                match field.ty.as_str() {
+                    "HEDR" if matches!(version, DataVersion::Morrowind) => {
+                        let mut buf : [u8; 4] = [0; 4];
+
+                        r.read_exact(&mut buf)?;
+                        header.version = f32::from_le_bytes(buf);
+
+                        // Morrowind marks masters with a file type (1) instead of a flag:
+                        r.read_exact(&mut buf)?;
+                        if u32::from_le_bytes(buf) == 1 {
+                            header.flags |= Self::MASTER_FLAG;
+                        }
+
+                        header.author = Some(read_zstring(&mut r, 32)?).filter(|a| !a.is_empty());
+                        header.description = Some(read_zstring(&mut r, 256)?).filter(|d| !d.is_empty());
 
-                timestamp = None;
-                internal_version = None;
-                misc = None;
+                        r.read_exact(&mut buf)?;
+                        header.record_count = u32::from_le_bytes(buf);
+                    },
+                    "HEDR" => {
+                        let mut buf : [u8; 4] = [0; 4];
+
+                        r.read_exact(&mut buf)?;
+                        header.version = f32::from_le_bytes(buf);
+
+                        r.read_exact(&mut buf)?;
+                        header.record_count = u32::from_le_bytes(buf);
+
+                        r.read_exact(&mut buf)?;
+                        header.next_object_id = u32::from_le_bytes(buf);
+                    },
+                    "CNAM" => {
+                        header.author = Some(read_zstring(&mut r, field.size)?);
+                    },
+                    "SNAM" => {
+                        header.description = Some(read_zstring(&mut r, field.size)?);
+                    },
+                    "MAST" => {
+                        header.masters.push(read_zstring(&mut r, field.size)?);
+                    },
+                    _ => {
+                        field.skip_data(&mut r)?;
+                    }
+                }
             }
+            Ok(())
         };
+        read_fields().map_err(|e| tes4.locate(e))?;
+
+        if !version.supports(header.version) {
+            return Err(tes4.locate(Error::UnsupportedVersion {
+                version: header.version,
+                data_version: version,
+                at: Location::default()
+            }));
+        }
 
-        Ok(RecordHeader {
-            ty,
-            data_size,
-            flags,
-            id,
-            timestamp,
-            version_control,
-            internal_version,
-            misc
-        })
+        Ok(header)
     }
+}
+
+/// Summary of a WRLD record and its exterior cells.
+#[derive(Debug)]
+pub struct WorldspaceInfo {
+    pub edid : String,
+    pub form_id : u32,
+    /// FormID of the worldspace this one inherits land data from, if any.
+    pub parent : Option<u32>,
+    pub cell_count : u32,
+    /// Inclusive (min, max) of the exterior cell grid positions.
+    pub bounds : Option<((i32, i32), (i32, i32))>,
+}
+
+impl WorldspaceInfo {
+    /// Counts the exterior cells in (part of) a World Children group, descending into the block and sub-block groups.
+    /// Cells outside of those groups (the persistent cell) are skipped.
+    fn scan_cells(&mut self, reader : &mut (impl Read + Seek), version : DataVersion, mut left_to_read : u32, in_block : bool) -> Result<()> {
+        while left_to_read > 0 {
+            if peek_signature(reader)? == *b"GRUP" {
+                let group = GroupHeader::read(reader, version)?;
+                left_to_read = left_to_read.checked_sub(group.total_size).ok_or_else(|| group.truncated())?;
+
+                match group.group_ty {
+                    // Exterior cell block and sub-block:
+                    4 | 5 => {
+                        let size = group.total_size.checked_sub(GroupHeader::header_size(version)).ok_or_else(|| group.truncated())?;
+                        self.scan_cells(reader, version, size, true)?
+                    },
+                    // Cell children and everything else:
+                    _ => group.skip_data(reader)?
+                }
+                continue;
+            }
 
-    fn skip_data(&self, reader : &mut (impl Read + Seek)) -> std::io::Result<()> {
-        reader.seek_relative(self.data_size.into())
+            let record = RecordHeader::read(reader, version)?;
+            left_to_read = left_to_read.checked_sub(record.data_size + RecordHeader::header_size(version)).ok_or_else(|| record.truncated())?;
+
+            if !in_block || record.ty != "CELL" {
+                record.skip_data(reader)?;
+                continue;
+            }
+
+            if let Some((x, y)) = read_cell_grid(&mut Cursor::new(record.read_data(reader)?), version).map_err(|e| record.locate(e))? {
+                self.cell_count += 1;
+                self.bounds = Some(match self.bounds {
+                    Some(((min_x, min_y), (max_x, max_y))) => ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y))),
+                    None => ((x, y), (x, y))
+                });
+            }
+        }
+        Ok(())
     }
 }
 
 #[derive(Debug)]
-struct GroupHeader {
+pub(crate) struct GroupHeader {
+    /// Where the group starts in whatever we read it from.
+    pub offset : u64,
     pub ty : String,
     pub header_size : u32,
     pub total_size : u32,
     pub label : [u8; 4],
     pub group_ty : i32,
-    pub timestamp : Option<u16>,
-    pub version_control : u32,
-    pub misc : Option<u32>
 }
 
 impl DataHeader for GroupHeader {
     fn header_size(version : DataVersion) -> u32 {
-        match version {
-            DataVersion::Skyrim => 24,
-            DataVersion::Oblivion => 20
-        }
+        version.header_size()
     }
 
-    fn read(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<Self, std::io::Error> {
+    fn read(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<Self> {
         let mut buf : [u8; 4] = [0; 4];
-        let mut buf16 : [u8; 2] = [0; 2];
 
-        let header_size = match version {
-            DataVersion::Oblivion => 20,
-            DataVersion::Skyrim => 24,
-        };
+        let header_size = version.header_size();
 
-        let ty : String;
-        reader.read_exact(&mut buf)?;
-        ty = str::from_utf8(&buf).unwrap().into();
+        let offset = reader.stream_position()?;
 
-        let total_size : u32;
-        reader.read_exact(&mut buf)?;
-        total_size = u32::from_le_bytes(buf);
+        read_header_bytes(reader, &mut buf, offset)?;
+        let ty = read_signature(buf, "a group", offset)?;
 
-        let label : [u8; 4];
-        reader.read_exact(&mut buf)?;
-        label = buf.clone();
+        if matches!(version, DataVersion::Morrowind) {
+            return Err(Error::BadSignature {
+                expected: "a record (Morrowind plugins don't have groups)".into(),
+                found: ty,
+                at: Location {
+                    offset: Some(offset),
+                    ..Location::default()
+                }
+            });
+        }
 
-        let group_ty : i32;
-        reader.read_exact(&mut buf)?;
-        group_ty = i32::from_le_bytes(buf);
+        read_header_bytes(reader, &mut buf, offset)?;
+        let total_size = u32::from_le_bytes(buf);
 
-        
-        let timestamp : Option<u16>;
-        let version_control : u32;
-        let misc : Option<u32>;
+        read_header_bytes(reader, &mut buf, offset)?;
+        let label : [u8; 4] = buf;
 
-        match version {
-            DataVersion::Skyrim => {
-                reader.read_exact(&mut buf16)?;
-                timestamp = Some(u16::from_le_bytes(buf16));
-                
-                reader.read_exact(&mut buf16)?;
-                version_control = u16::from_le_bytes(buf16) as u32;
-        
-                reader.read_exact(&mut buf)?;
-                misc = Some(u32::from_le_bytes(buf));
-            },
-            DataVersion::Oblivion => {
-                reader.read_exact(&mut buf)?;
-                version_control = u32::from_le_bytes(buf);
+        read_header_bytes(reader, &mut buf, offset)?;
+        let group_ty = i32::from_le_bytes(buf);
 
-                timestamp = None;
-                misc = None;
-            }
-        }
+        // The timestamp and version control info:
+        let mut rest = [0; 8];
+        read_header_bytes(reader, &mut rest[..header_size as usize - 16], offset)?;
 
         Ok(GroupHeader {
+            offset,
             ty,
             total_size,
             header_size,
             label,
-            group_ty,
-            timestamp,
-            version_control,
-            misc
+            group_ty
         })
     }
 
-    fn skip_data(&self, reader : &mut (impl Read + Seek)) -> std::io::Result<()> {
-        reader.seek_relative((self.total_size - self.header_size).into())
+    fn skip_data(&self, reader : &mut (impl Read + Seek)) -> Result<()> {
+        let data_size = self.total_size.checked_sub(self.header_size).ok_or_else(|| self.truncated())?;
+        Ok(reader.seek_relative(data_size.into())?)
     }
 }
 
 #[derive(Debug)]
-struct FieldHeader {
+pub(crate) struct FieldHeader {
     pub ty : String,
-    pub size : u16
+    pub size : u32
 }
 
 impl DataHeader for FieldHeader {
-    fn header_size(_version : DataVersion) -> u32 {
-        6
+    fn header_size(version : DataVersion) -> u32 {
+        match version {
+            DataVersion::Morrowind => 8,
+            _ => 6
+        }
     }
 
-    // Data version does not matter for fields.
-    fn read(reader : &mut (impl Read + Seek), _version : DataVersion) -> Result<Self, std::io::Error> {
+    // Only Morrowind's fields are different, with a 4 byte size.
+    fn read(reader : &mut (impl Read + Seek), version : DataVersion) -> Result<Self> {
         let mut buf : [u8; 4] = [0; 4];
         let mut buf16 : [u8; 2] = [0; 2];
 
-        let ty : String;
         reader.read_exact(&mut buf)?;
-        ty = str::from_utf8(&buf).unwrap().into();
-
-        let size : u16;
-        reader.read_exact(&mut buf16)?;
-        size = u16::from_le_bytes(buf16);
+        // Offsets are only worth reporting for records, so we leave this to the record it's in:
+        let ty = str::from_utf8(&buf).ok().filter(|t| t.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')).ok_or_else(|| Error::BadSignature {
+            expected: "a field type".into(),
+            found: String::from_utf8_lossy(&buf).into_owned(),
+            at: Location::default()
+        })?.into();
+
+        let size = if matches!(version, DataVersion::Morrowind) {
+            reader.read_exact(&mut buf)?;
+            u32::from_le_bytes(buf)
+        } else {
+            reader.read_exact(&mut buf16)?;
+            u16::from_le_bytes(buf16) as u32
+        };
 
         Ok(FieldHeader {
             ty,
@@ -502,76 +1470,517 @@ impl DataHeader for FieldHeader {
         })
     }
 
-    fn skip_data(&self, reader : &mut (impl Read + Seek)) -> std::io::Result<()> {
-        reader.seek_relative(self.size.into())
+    fn skip_data(&self, reader : &mut (impl Read + Seek)) -> Result<()> {
+        Ok(reader.seek_relative(self.size.into())?)
+    }
+}
+/// A cell and what's in it.
+#[derive(Debug)]
+pub struct CellContents {
+    pub cell : Cell,
+    pub land : Option<Land>,
+    /// Objects placed in the cell (REFR).
+    pub references : Vec<Reference>,
+}
+
+/// An object placed in a cell (REFR).
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub form_id : u32,
+    /// FormID of what's placed (a STAT, TREE and so on).
+    pub base : u32,
+    /// In Skyrim Units.
+    pub position : [f32; 3],
+    /// Around the X, Y and Z axes, in radians.
+    pub rotation : [f32; 3],
+    pub scale : f32,
+    /// From the cell's persistent children (map markers, doors, and whatever else scripts and quests need loaded all the time)
+    /// rather than its temporary ones.
+    pub persistent : bool,
+}
+
+impl Reference {
+    fn read(reader : &mut (impl Read + Seek), version : DataVersion, record : &RecordHeader, persistent : bool, form_ids : &FormIds) -> Result<Reference> {
+        let data = record.read_data(reader)?;
+        let size = data.len() as u64;
+        let mut r = Cursor::new(data);
+
+        let mut buf : [u8; 4] = [0; 4];
+
+        let mut base : Option<u32> = None;
+        let mut position = [0.0; 3];
+        let mut rotation = [0.0; 3];
+        let mut scale = 1.0;
+
+        while r.position() < size {
+            let field = FieldHeader::read(&mut r, version)?;
+            match field.ty.as_str() {
+                "NAME" => {
+                    r.read_exact(&mut buf)?;
+                    base = Some(form_ids.resolve(u32::from_le_bytes(buf)));
+                    r.seek_relative(field.size as i64 - 4)?;
+                },
+                "XSCL" => {
+                    r.read_exact(&mut buf)?;
+                    scale = f32::from_le_bytes(buf);
+                    r.seek_relative(field.size as i64 - 4)?;
+                },
+                "DATA" => {
+                    for value in position.iter_mut().chain(rotation.iter_mut()) {
+                        r.read_exact(&mut buf)?;
+                        *value = f32::from_le_bytes(buf);
+                    }
+                    r.seek_relative(field.size as i64 - 24)?;
+                },
+                _ => {
+                    field.skip_data(&mut r)?;
+                }
+            }
+        }
+
+        let Some(base) = base else {
+            return Err(Error::MissingSubrecord {
+                subrecord: "NAME",
+                at: Location::default()
+            });
+        };
+
+        Ok(Reference {
+            form_id: form_ids.resolve(record.id),
+            base,
+            position,
+            rotation,
+            scale,
+            persistent
+        })
     }
 }
+
 #[derive(Clone, Debug)]
 pub struct Cell {
 	pub x : i32,
 	pub y : i32,
-    pub water_height : Option<f32>
+    pub water_height : Option<f32>,
+    /// WATR FormID.
+    pub water_type : Option<u32>,
+    /// Whether this is the worldspace's persistent cell, which isn't really at (x, y).
+    /// It has no LAND, and holds persistent references from all over the worldspace, at their own positions.
+    pub persistent : bool,
+    /// REGN FormIDs of the regions the cell is in.
+    pub regions : Vec<u32>,
+    /// CLMT FormID of its worldspace's climate.
+    pub climate : Option<u32>,
+}
+
+/// A land texture (LTEX), and the path of its diffuse texture.
+#[derive(Debug, Default, Clone)]
+pub struct LandTexture {
+    pub edid : String,
+    pub texture : Option<String>,
+    /// FormID of the TXST the texture comes from, in Skyrim.
+    pub texture_set : Option<u32>,
+}
+
+/// An alpha-blended texture layer (ATXT and its VTXT) over one quadrant of a LAND record.
+#[derive(Debug)]
+struct TextureLayer {
+    texture : u32,
+    quadrant : usize,
+    layer : u16,
+    /// Opacity for each of the quadrant's 17 x 17 vertices.
+    opacity : Vec<f32>,
 }
 
 #[derive(Debug)]
 pub struct Land {
     pub cell : Cell,
-	pub offset_height : f32,
-	pub height_gradient : Vec<i8>,
+    /// Height of each of the 33 x 33 vertices in Skyrim Units, row by row from the south west corner.
+    pub heights : Vec<f32>,
+    /// The most visible LTEX FormID at each of the 33 x 33 vertices, if any.
+    pub textures : Vec<Option<u32>>,
+    /// RGB color of each of the 33 x 33 vertices.
+    pub vertex_colors : Option<Vec<[u8; 3]>>,
+    /// Normal (X, Y, Z) of each of the 33 x 33 vertices, scaled to -127 to 127.
+    pub normals : Option<Vec<[i8; 3]>>,
 }
 
 impl Land {
-    pub(self) fn read(reader : &mut (impl Read + Seek), info : ESMInfo, cell : Cell, land : &RecordHeader) -> std::io::Result<()> {
-        let mut buf : [u8; 4] = [0; 4];
-
-        reader.read_exact(&mut buf)?;
-        let decrypted_size = u32::from_le_bytes(buf);
-    
-        // Subtract the 4 bytes we just read:
-        let compressed_chunk = reader.take((land.data_size as u64) - (4 as u64));
-    
-        let mut out_land = Vec::with_capacity(decrypted_size.try_into().unwrap());
-    
-        ZlibDecoder::new(compressed_chunk).read_to_end(&mut out_land)?;
+    /// Returns `None` for LAND records without any heights.
+    fn read(reader : &mut (impl Read + Seek), version : DataVersion, cell : Cell, land : &RecordHeader, form_ids : &FormIds) -> Result<Option<Land>> {
+        let out_land = land.read_data(reader)?;
     
+        let left_to_read = out_land.len() as u64;
+
         let mut land_cursor = Cursor::new(out_land);
+
+        let mut buf : [u8; 4] = [0; 4];
+        let mut buf16 : [u8; 2] = [0; 2];
+        let mut byte : [u8; 1] = [0; 1];
+
+        let mut heights : Option<Vec<f32>> = None;
+        let mut vertex_colors : Option<Vec<[u8; 3]>> = None;
+        let mut normals : Option<Vec<[i8; 3]>> = None;
+
+        // The quadrants are (in order) bottom left, bottom right, top left and top right:
+        let mut base_textures : [Option<u32>; 4] = [None; 4];
+        let mut layers : Vec<TextureLayer> = Vec::new();
     
-        let mut left_to_read = decrypted_size;
-    
-        while left_to_read > 0 {
-            let field = FieldHeader::read(&mut land_cursor, info.version)?;
-    
-            if field.ty == "VHGT" {
-                land_cursor.read_exact(&mut buf)?;
-    
-                // Based on https://en.uesp.net/wiki/Skyrim_Mod:Mod_File_Format/LAND
-                let offset_height = f32::from_le_bytes(buf);
-    
-                let mut height_gradient = Vec::with_capacity(1089);
-    
-                let mut byte : [u8; 1] = [0; 1];
+        while land_cursor.position() < left_to_read {
+            let field = FieldHeader::read(&mut land_cursor, version)?;
     
-                for _ in 0..1089 {
+            match field.ty.as_str() {
+                "VHGT" => {
+                    land_cursor.read_exact(&mut buf)?;
+        
+                    // Based on https://en.uesp.net/wiki/Skyrim_Mod:Mod_File_Format/LAND
+                    let offset_height = f32::from_le_bytes(buf);
+        
+                    let mut height_gradient = Vec::with_capacity(1089);
+        
+                    for _ in 0..1089 {
+                        land_cursor.read_exact(&mut byte)?;
+        
+                        let height_byte = i8::from_le_bytes(byte);
+        
+                        height_gradient.push(height_byte);
+                    }
+
+                    // Skip the unused bytes at the end:
+                    land_cursor.seek_relative(field.size as i64 - 1093)?;
+
+                    heights = Some(Land::decode_heights(offset_height, &height_gradient, 33));
+                },
+                "VCLR" => {
+                    let mut colors = Vec::with_capacity(1089);
+                    let mut rgb : [u8; 3] = [0; 3];
+
+                    for _ in 0..1089 {
+                        land_cursor.read_exact(&mut rgb)?;
+                        colors.push(rgb);
+                    }
+
+                    land_cursor.seek_relative(field.size as i64 - 3267)?;
+
+                    vertex_colors = Some(colors);
+                },
+                "VNML" => {
+                    let mut vertex_normals = Vec::with_capacity(1089);
+                    let mut xyz : [u8; 3] = [0; 3];
+
+                    for _ in 0..1089 {
+                        land_cursor.read_exact(&mut xyz)?;
+                        vertex_normals.push(xyz.map(|n| n as i8));
+                    }
+
+                    land_cursor.seek_relative(field.size as i64 - 3267)?;
+
+                    normals = Some(vertex_normals);
+                },
+                "BTXT" | "ATXT" => {
+                    land_cursor.read_exact(&mut buf)?;
+                    let texture = form_ids.resolve(u32::from_le_bytes(buf));
+
                     land_cursor.read_exact(&mut byte)?;
-    
-                    let height_byte = i8::from_le_bytes(byte);
-    
-                    height_gradient.push(height_byte);
+                    let quadrant = (byte[0] as usize).min(3);
+
+                    // Unused byte:
+                    land_cursor.read_exact(&mut byte)?;
+
+                    land_cursor.read_exact(&mut buf16)?;
+                    let layer = u16::from_le_bytes(buf16);
+
+                    if field.ty == "BTXT" {
+                        base_textures[quadrant] = Some(texture);
+                    } else {
+                        layers.push(TextureLayer {
+                            texture,
+                            quadrant,
+                            layer,
+                            opacity: vec![0.0; 289]
+                        });
+                    }
+                },
+                "VTXT" => {
+                    for _ in 0..(field.size / 8) {
+                        land_cursor.read_exact(&mut buf16)?;
+                        let position = u16::from_le_bytes(buf16) as usize;
+
+                        // Unused:
+                        land_cursor.read_exact(&mut buf16)?;
+
+                        land_cursor.read_exact(&mut buf)?;
+                        let opacity = f32::from_le_bytes(buf);
+
+                        // Always belongs to the ATXT right before it:
+                        if let Some(o) = layers.last_mut().and_then(|l| l.opacity.get_mut(position)) {
+                            *o = opacity;
+                        }
+                    }
+                },
+                _ => {
+                    field.skip_data(&mut land_cursor)?;
                 }
-    
-                parse_land(Land {
-                    cell,
-                    offset_height,
-                    height_gradient
-                }, info.out_folder);
-                break;
+            }
+        }
+
+        let Some(heights) = heights else {
+            return Ok(None);
+        };
+        layers.sort_by_key(|l| l.layer);
+
+        Ok(Some(Land {
+            cell,
+            heights,
+            textures: Land::blend_textures(&base_textures, &layers),
+            vertex_colors,
+            normals
+        }))
+    }
+
+    /// A LAND with nothing but heights (33 x 33 of them, in Skyrim Units), for drawing a heightmap from somewhere other than a plugin.
+    pub fn from_heights(cell : Cell, heights : Vec<f32>) -> Self {
+        Land {
+            cell,
+            textures: vec![None; heights.len()],
+            heights,
+            vertex_colors: None,
+            normals: None
+        }
+    }
+
+    /// Vertex heights are stored as a gradient: each row starts relative to the start of the last row,
+    /// and every other vertex is relative to the one before it in its row. Rows are `width` vertices long.
+    pub(crate) fn decode_heights(offset_height : f32, height_gradient : &[i8], width : usize) -> Vec<f32> {
+        let mut heights = Vec::with_capacity(height_gradient.len());
+        let mut row_offset : f32 = 0.0;
+        let mut curr_offset = offset_height;
+
+        for (i, v) in height_gradient.iter().enumerate() {
+            let vert_height = *v as f32;
+
+            if i % width == 0 {
+                row_offset = 0.0;
+                curr_offset += vert_height;
             } else {
-                field.skip_data(&mut land_cursor)?;
+                row_offset += vert_height;
             }
-    
-            left_to_read -= field.size as u32 + FieldHeader::header_size(info.version);
+
+            // Vertex heights are in units of 8 Skyrim Units.
+            heights.push((row_offset + curr_offset) * 8.0);
         }
-    
-        Ok(())
+        heights
+    }
+
+    /// Alpha blends each quadrant's layers over its base texture, and picks whichever texture ends up most visible at each vertex.
+    fn blend_textures(base_textures : &[Option<u32>; 4], layers : &[TextureLayer]) -> Vec<Option<u32>> {
+        let mut textures = Vec::with_capacity(1089);
+
+        for r in 0..33 {
+            for c in 0..33 {
+                // Quadrants share the vertices along their edges, so each is 17 x 17:
+                let quad_row = (r / 16).min(1);
+                let quad_col = (c / 16).min(1);
+                let quadrant = quad_col + quad_row * 2;
+                let position = (c - quad_col * 16) + (r - quad_row * 16) * 17;
+
+                let mut weights : Vec<(Option<u32>, f32)> = vec![(base_textures[quadrant], 1.0)];
+
+                for layer in layers.iter().filter(|l| l.quadrant == quadrant) {
+                    let opacity = layer.opacity[position].clamp(0.0, 1.0);
+                    if opacity <= 0.0 {
+                        continue;
+                    }
+
+                    for (_, w) in weights.iter_mut() {
+                        *w *= 1.0 - opacity;
+                    }
+                    weights.push((Some(layer.texture), opacity));
+                }
+
+                let most_visible = weights.iter().fold((None, f32::MIN), |best, (texture, w)| {
+                    if *w > best.1 { (*texture, *w) } else { best }
+                });
+                textures.push(most_visible.0);
+            }
+        }
+
+        textures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(texture : u32, quadrant : usize, layer : u16, opacities : &[(usize, f32)]) -> TextureLayer {
+        let mut opacity = vec![0.0; 289];
+        for (position, o) in opacities {
+            opacity[*position] = *o;
+        }
+        TextureLayer { texture, quadrant, layer, opacity }
+    }
+
+    fn at(textures : &[Option<u32>], row : usize, col : usize) -> Option<u32> {
+        textures[col + row * 33]
+    }
+
+    #[test]
+    fn base_textures_by_quadrant() {
+        let textures = Land::blend_textures(&[Some(0), Some(1), Some(2), Some(3)], &[]);
+        assert_eq!(textures.len(), 1089);
+
+        // Quadrants go south west, south east, north west, north east, and the middle row and column belong to the north and east ones:
+        for (row, col, quadrant) in [(0, 0, 0), (15, 15, 0), (0, 16, 1), (0, 32, 1), (16, 0, 2), (32, 15, 2), (16, 16, 3), (32, 32, 3)] {
+            assert_eq!(at(&textures, row, col), Some(quadrant), "vertex ({col}, {row})");
+        }
+    }
+
+    #[test]
+    fn layer_positions_within_quadrants() {
+        // The last position of the north east quadrant is the cell's corner, and the first of the south east one is the middle of the south edge.
+        // The north west quadrant's last column is the middle one, which goes by the north east quadrant instead:
+        let layers = [layer(10, 3, 0, &[(288, 1.0)]), layer(11, 1, 0, &[(0, 1.0)]), layer(12, 2, 0, &[(15 + 17, 1.0), (16 + 17, 1.0)])];
+        let textures = Land::blend_textures(&[Some(0); 4], &layers);
+
+        assert_eq!(at(&textures, 32, 32), Some(10));
+        assert_eq!(at(&textures, 0, 16), Some(11));
+        assert_eq!(at(&textures, 17, 15), Some(12));
+        assert_eq!(at(&textures, 17, 16), Some(0));
+        assert_eq!(textures.iter().filter(|t| **t != Some(0)).count(), 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn layers_blend_over_each_other() {
+        let blend = |opacities : &[f32]| {
+            let layers : Vec<TextureLayer> = opacities.iter().enumerate().map(|(i, o)| layer(10 + i as u32, 0, i as u16, &[(0, *o)])).collect();
+            Land::blend_textures(&[Some(1), None, None, None], &layers)[0]
+        };
+
+        // 0.6 over the base, then 0.5 over that: 0.2 base, 0.3 first layer, 0.5 second layer:
+        assert_eq!(blend(&[0.6, 0.5]), Some(11));
+        // With 0.3 on top instead: 0.28, 0.42, 0.3:
+        assert_eq!(blend(&[0.6, 0.3]), Some(10));
+        // Not enough to cover the base:
+        assert_eq!(blend(&[0.4]), Some(1));
+        // Invisible layers don't count, and too much opacity is as good as 1:
+        assert_eq!(blend(&[0.0]), Some(1));
+        assert_eq!(blend(&[0.9, 1.5]), Some(11));
+    }
+
+    #[test]
+    fn layers_over_no_base_texture() {
+        let textures = Land::blend_textures(&[None; 4], &[layer(10, 0, 0, &[(0, 0.4), (1, 0.6)])]);
+        assert_eq!(at(&textures, 0, 0), None);
+        assert_eq!(at(&textures, 0, 1), Some(10));
+    }
+
+    #[test]
+    fn load_index_form_ids() {
+        assert_eq!(LoadIndex::Full(0x00).form_id(0x12345678), 0x00345678);
+        assert_eq!(LoadIndex::Full(0xFD).form_id(0x00000ABC), 0xFD000ABC);
+
+        // Light plugins only keep the bottom 12 bits of the ID, under 0xFE and their own 12 bits:
+        assert_eq!(LoadIndex::Light(0x000).form_id(0x01000801), 0xFE000801);
+        assert_eq!(LoadIndex::Light(0x123).form_id(0x00000FFF), 0xFE123FFF);
+        assert_eq!(LoadIndex::Light(0xFFF).form_id(0x00123456), 0xFEFFF456);
+    }
+
+    #[test]
+    fn form_ids_resolve_through_masters() {
+        // Two masters at 0x03 and light plugin 0x005, then the plugin itself at 0x07:
+        let form_ids = FormIds::new(vec![LoadIndex::Full(0x03), LoadIndex::Light(0x005), LoadIndex::Full(0x07)]);
+        assert_eq!(form_ids.own(), Some(LoadIndex::Full(0x07)));
+
+        assert_eq!(form_ids.resolve(0x00000D62), 0x03000D62);
+        assert_eq!(form_ids.resolve(0x01000D62), 0xFE005D62);
+        assert_eq!(form_ids.resolve(0x02000D62), 0x07000D62);
+        // Indices past the plugin's own shouldn't come up, but count as its own too:
+        assert_eq!(form_ids.resolve(0x05000D62), 0x07000D62);
+
+        // A light plugin's own records:
+        let light = FormIds::new(vec![LoadIndex::Full(0x00), LoadIndex::Light(0x002)]);
+        assert_eq!(light.resolve(0x01000800), 0xFE002800);
+        assert_eq!(light.resolve(0x00000014), 0x00000014);
+    }
+
+    /// The start of a TES4 header record: `header_size` bytes of record header, then HEDR with the version.
+    fn tes4(header_size : usize, version : f32, form_version : u16) -> Vec<u8> {
+        let mut header = [b"TES4".as_slice(), &[0; 12]].concat();
+        header.resize(header_size, 0);
+        if header_size == 24 {
+            header[20..22].copy_from_slice(&form_version.to_le_bytes());
+        }
+        [header.as_slice(), b"HEDR", &12u16.to_le_bytes(), &version.to_le_bytes(), &[0; 8]].concat()
+    }
+
+    #[test]
+    fn detect_games() {
+        let tes3 = [b"TES3".as_slice(), &[0; 12], b"HEDR", &300u32.to_le_bytes(), &1.3f32.to_le_bytes()].concat();
+        let cases = [
+            (tes3, Game::Morrowind),
+            (tes4(20, 1.0, 0), Game::Oblivion),
+            (tes4(20, 0.8, 0), Game::Oblivion),
+            // Fallout 3 and Skyrim both save version 0.94, with form versions in the teens and the 40s:
+            (tes4(24, 0.94, 15), Game::Fallout3),
+            (tes4(24, 0.94, 40), Game::SkyrimLE),
+            (tes4(24, 1.7, 43), Game::SkyrimLE),
+            (tes4(24, 1.71, 44), Game::SkyrimSE),
+            (tes4(24, 1.34, 15), Game::FalloutNV),
+            (tes4(24, 1.0, 131), Game::Fallout4),
+        ];
+
+        for (header, game) in cases {
+            assert_eq!(Game::detect_header(&mut Cursor::new(&header)).unwrap(), game, "{header:02x?}");
+        }
+    }
+
+    #[test]
+    fn detect_unknown_version() {
+        // Oblivion never saved 0.94, whatever the games after it did:
+        for (header, expected) in [(tes4(24, 2.5, 44), 2.5), (tes4(20, 0.94, 0), 0.94)] {
+            match Game::detect_header(&mut Cursor::new(&header)) {
+                Err(Error::UnknownVersion { version, .. }) => assert_eq!(version, expected),
+                other => panic!("expected unknown version {expected}, got {other:?}")
+            }
+        }
+
+        assert!(matches!(Game::detect_header(&mut Cursor::new(b"PK\x03\x04 not a plugin")), Err(Error::BadSignature { .. })));
+    }
+
+    /// A Skyrim GRUP header.
+    fn group(label : &[u8; 4], group_ty : i32, total_size : u32) -> Vec<u8> {
+        [b"GRUP".as_slice(), &total_size.to_le_bytes(), label, &group_ty.to_le_bytes(), &[0; 8]].concat()
+    }
+
+    #[test]
+    fn groups_smaller_than_their_header() {
+        let is_truncated = |result : Result<()>, offset : u64| match result {
+            Err(Error::TruncatedRecord { at }) => assert_eq!(at.offset, Some(offset)),
+            other => panic!("expected a truncated group at {offset}, got {other:?}")
+        };
+
+        let mut reader = Cursor::new(group(b"WRLD", 0, 10));
+        let header = GroupHeader::read(&mut reader, DataVersion::Skyrim).unwrap();
+        is_truncated(header.skip_data(&mut reader), 0);
+        is_truncated(find_world_children(&mut reader, DataVersion::Skyrim, header, &FormIds::default(), |_, _| true, &mut Vec::new()).map(|_| ()), 0);
+
+        // An exterior cell block inside a worldspace's World Children group:
+        let mut reader = Cursor::new(group(&[0; 4], 4, 23));
+        let mut world = WorldspaceInfo {
+            edid: String::new(),
+            form_id: 0,
+            parent: None,
+            cell_count: 0,
+            bounds: None
+        };
+        is_truncated(world.scan_cells(&mut reader, DataVersion::Skyrim, 24, false), 0);
+    }
+
+    #[test]
+    fn form_ids_of_a_plugin_on_its_own() {
+        let form_ids = FormIds::default();
+        assert_eq!(form_ids.own(), None);
+        assert_eq!(form_ids.resolve(0x01000D62), 0x01000D62);
+        assert_eq!(form_ids.resolve(0xFE001800), 0xFE001800);
+    }
+}
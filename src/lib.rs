@@ -0,0 +1,33 @@
+//! Reads the worldspaces of Skyrim, Oblivion, Fallout 3, New Vegas, Fallout 4 and Morrowind plugins, and draws their land into Minecraft regions.
+//!
+//! Open a plugin with [`ESMFile::open`], and the data version [`Game::detect`] finds for it. From there, [`ESMFile::convert`] converts a whole worldspace like the command line does,
+//! [`ESMFile::read`] hands a worldspace's cells, LAND and placed objects to a [`LandSink`] of your own,
+//! and [`ESMFile::cells`] goes through them one cell at a time.
+//! [`LoadOrder`] reads and converts a master along with plugins that override its cells.
+//! Hand a [`esm::Land`] (from a plugin, or [`esm::Land::from_heights`]) to [`world_gen::parse_land`] to draw it into a [`regions::RegionWriter`].
+//! [`bsa::Archive`] reads files out of .bsa and .ba2 archives, and [`bsa::DataFiles`] out of a Data folder's loose files and archives together.
+//! [`meshes::Meshes`] finds placed objects' meshes there (read with [`nif::read_triangles`]), which [`ConvertOptions::meshes`] turns into blocks.
+//! [`texture_sampling::generate_mapping`] picks blocks for land textures by the color of their textures (read with [`dds::read_image`]).
+
+mod block_colors;
+pub mod bsa;
+pub mod dds;
+pub mod error;
+pub mod esm;
+pub mod load_order;
+pub mod mapping;
+pub mod meshes;
+pub mod nif;
+pub mod regions;
+pub mod sink;
+mod tes3;
+#[cfg(test)]
+mod test_util;
+pub mod texture_sampling;
+pub mod trees;
+pub mod world_gen;
+
+pub use error::{Error, Result};
+pub use esm::{ConvertOptions, DataVersion, ESMFile, Game};
+pub use load_order::LoadOrder;
+pub use sink::{AnvilSink, LandSink};
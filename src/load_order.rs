@@ -0,0 +1,677 @@
+use std::{collections::HashMap, fs, io::{Cursor, Read, Seek, SeekFrom}, path::{Path, PathBuf}, sync::Mutex};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::ThreadPoolBuilder;
+
+use crate::{error::{Error, Location, Result}, esm::{find_world_children, no_cells, peek_signature, read_cell, read_cell_grid, skip_cell_children, worldspace_not_found,
+    Cell, ConvertOptions, DataHeader, DataVersion, ESMFile, FormIds, FoundWorldspace, GroupHeader, Land, LoadIndex, RecordHeader, Reference, WorldRecords, WorldspaceDefaults},
+    mapping::Mapping, regions::clear_regions, sink::{AnvilSink, LandSink, Worldspace}, tes3, world_gen::Pos};
+
+/// Masters and plugins read together the way the game loads them: a record in a later file overrides the same record in an earlier one.
+#[derive(Debug)]
+pub struct LoadOrder {
+    files : Vec<ESMFile>,
+}
+
+impl LoadOrder {
+    /// Opens every plugin in `paths`, in load order. Each plugin's masters have to come before it. There has to be at least one.
+    pub fn open(paths : &[PathBuf], version : DataVersion) -> Result<Self> {
+        if paths.is_empty() {
+            return Err(Error::invalid_data("The load order is empty: there are no plugins to read."));
+        }
+
+        let mut files : Vec<ESMFile> = Vec::with_capacity(paths.len());
+
+        for path in paths {
+            let mut file = ESMFile::open(path, version)?;
+            // A plugin read on its own keeps its FormIDs as they are, whether or not its masters are around:
+            if paths.len() > 1 {
+                file.form_ids = resolve_masters(&file, &files)?;
+            }
+            files.push(file);
+        }
+
+        Ok(LoadOrder { files })
+    }
+
+    /// Opens `main` with the plugins loaded along with it, in the order [`load_order_paths`] works out.
+    pub fn for_plugin(main : &Path, plugins_txt : Option<&Path>, plugins : &[PathBuf], version : DataVersion) -> Result<Self> {
+        Self::open(&load_order_paths(main, plugins_txt, plugins, version)?, version)
+    }
+
+    pub fn files(&self) -> &[ESMFile] {
+        &self.files
+    }
+
+    /// Hands everything in the worldspace with the given editor ID to `sink`, with the last-loaded version of each cell, LAND and placed object.
+    /// With `keep_going`, cells that can't be read are skipped instead of stopping there, and what went wrong with each is returned.
+    pub fn read(&mut self, worldspace : &str, sink : &mut dyn LandSink, keep_going : bool) -> Result<Vec<Error>> {
+        read_worldspace(&mut self.files, worldspace, sink, keep_going)
+    }
+
+    /// The land textures, water types and base objects of every file, with later files overriding earlier ones.
+    pub fn world_records(&mut self) -> Result<WorldRecords> {
+        if matches!(self.files[0].version, DataVersion::Morrowind) {
+            return tes3::read_world_records(&mut self.files);
+        }
+
+        let mut records = WorldRecords::default();
+        for file in self.files.iter_mut() {
+            file.reader.seek(SeekFrom::Start(file.data_start))?;
+            let (_, file_records) = WorldRecords::read(&mut file.reader, file.version, &file.form_ids).map_err(|e| e.in_plugin(&file.name))?;
+            records.merge(file_records);
+        }
+        records.link_texture_sets();
+        Ok(records)
+    }
+
+    /// Converts the worldspace with the given editor ID into .mca files in `out_dir`, replacing any that are there already.
+    /// Returns the cells skipped with [`ConvertOptions::keep_going`].
+    pub fn convert(&mut self, out_dir : &Path, worldspace : &str, mapping : &Mapping, options : ConvertOptions) -> Result<Vec<Error>> {
+        convert(&mut self.files, out_dir, worldspace, mapping, options)
+    }
+}
+
+/// Converts a worldspace into .mca files in `out_dir`, like [`LoadOrder::convert`] and [`ESMFile::convert`].
+pub(crate) fn convert(files : &mut [ESMFile], out_dir : &Path, worldspace : &str, mapping : &Mapping, options : ConvertOptions) -> Result<Vec<Error>> {
+    if options.meshes.is_some() && matches!(files[0].version, DataVersion::Morrowind) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Morrowind's meshes can't be read, so its objects can't be turned into blocks. Convert it without a Data folder.").into());
+    }
+
+    clear_regions(out_dir)?;
+    let mut sink = AnvilSink::new(out_dir, mapping, options.scale, options.interpolation);
+    if let Some(meshes) = options.meshes {
+        sink = sink.with_meshes(meshes);
+    }
+    read_worldspace(files, worldspace, &mut sink, options.keep_going)
+}
+
+/// Maps the masters of `file` to where they are in the load order so far, which has to have all of them.
+fn resolve_masters(file : &ESMFile, loaded : &[ESMFile]) -> Result<FormIds> {
+    let plugin_location = || Location {
+        plugin: Some(file.name().into()),
+        ..Location::default()
+    };
+
+    let too_many = |limit : &str| Error::Io {
+        source: std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Can't load more than {limit}")),
+        at: plugin_location()
+    };
+
+    // Full and light plugins are counted separately:
+    let light_count = loaded.iter().filter(|f| f.is_light()).count();
+    let own = if file.is_light() {
+        LoadIndex::Light(u16::try_from(light_count).ok().filter(|i| *i <= 0xFFF).ok_or_else(|| too_many("4096 light plugins"))?)
+    } else {
+        LoadIndex::Full(u8::try_from(loaded.len() - light_count).ok().filter(|i| *i <= 0xFD).ok_or_else(|| too_many("254 full plugins"))?)
+    };
+
+    let mut indices = file.header().masters.iter().map(|master| {
+        loaded.iter().find(|f| f.name().eq_ignore_ascii_case(master)).and_then(|f| f.form_ids.own()).ok_or_else(|| Error::MissingMaster {
+            master: master.clone(),
+            at: plugin_location()
+        })
+    }).collect::<Result<Vec<LoadIndex>>>()?;
+    indices.push(own);
+
+    Ok(FormIds::new(indices))
+}
+
+/// The masters the game loads before anything in plugins.txt, whether it lists them or not.
+fn implicit_masters(version : DataVersion) -> &'static [&'static str] {
+    match version {
+        DataVersion::Skyrim => &["Skyrim.esm", "Update.esm", "Dawnguard.esm", "HearthFires.esm", "Dragonborn.esm"],
+        DataVersion::Oblivion => &["Oblivion.esm"],
+        DataVersion::Fallout3 => &["Fallout3.esm"],
+        DataVersion::FalloutNV => &["FalloutNV.esm"],
+        DataVersion::Fallout4 => &["Fallout4.esm", "DLCRobot.esm", "DLCworkshop01.esm", "DLCCoast.esm", "DLCworkshop02.esm", "DLCworkshop03.esm", "DLCNukaWorld.esm"],
+        DataVersion::Morrowind => &["Morrowind.esm", "Tribunal.esm", "Bloodmoon.esm"]
+    }
+}
+
+/// Reads the plugins a plugins.txt turns on, in order.
+/// Newer games mark the ones that are on with a `*` and keep the rest listed; older ones only list the ones that are on.
+pub fn read_plugins_txt(path : &Path) -> Result<Vec<String>> {
+    let text = fs::read_to_string(path).map_err(|e| std::io::Error::new(e.kind(), format!("Could not read {path:?}: {e}")))?;
+
+    let lines : Vec<&str> = text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')).collect();
+    let marks_active = lines.iter().any(|l| l.starts_with('*'));
+
+    Ok(lines.into_iter().filter_map(|l| match l.strip_prefix('*') {
+        Some(name) => Some(name.into()),
+        None if !marks_active => Some(l.into()),
+        None => None
+    }).collect())
+}
+
+/// Works out the load order for converting `main`: with a plugins.txt, the masters the game always loads (if they're next to `main`)
+/// and then what it turns on, looked for next to `main` too; then `plugins`, in the order given.
+/// `main` goes first unless it's already in there.
+pub fn load_order_paths(main : &Path, plugins_txt : Option<&Path>, plugins : &[PathBuf], version : DataVersion) -> Result<Vec<PathBuf>> {
+    let data_dir = main.parent().unwrap_or(Path::new(""));
+    let mut paths : Vec<PathBuf> = Vec::new();
+
+    if let Some(plugins_txt) = plugins_txt {
+        paths.extend(implicit_masters(version).iter().map(|name| data_dir.join(name)).filter(|path| path.is_file()));
+        paths.extend(read_plugins_txt(plugins_txt)?.into_iter().map(|name| data_dir.join(name)));
+    }
+
+    let same_file = |a : &Path, b : &Path| a.file_name().zip(b.file_name()).is_some_and(|(a, b)| a.eq_ignore_ascii_case(b));
+    if !paths.iter().any(|path| same_file(path, main)) {
+        paths.insert(0, main.to_path_buf());
+    }
+    paths.extend(plugins.iter().cloned());
+
+    // Anything listed twice loads where it's first listed:
+    let mut unique : Vec<PathBuf> = Vec::with_capacity(paths.len());
+    for path in paths {
+        if !unique.iter().any(|seen| same_file(seen, &path)) {
+            unique.push(path);
+        }
+    }
+    Ok(unique)
+}
+
+/// CELL records (each followed by its Cell Children, if it has any) that sit next to each other in one file.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    file : usize,
+    offset : u64,
+    size : u64,
+}
+
+/// What gets read and handed over together: one exterior sub-block, or the persistent cell, from every file that has it.
+#[derive(Debug)]
+struct Batch {
+    /// The sub-block's group label, or `None` for cells outside of any sub-block.
+    subblock : Option<[u8; 4]>,
+    /// The first and last cell of the sub-block, grown to fit any of its cells that aren't where its label says.
+    cells : Option<(Pos, Pos)>,
+    spans : Vec<Span>,
+}
+
+#[derive(Clone, Copy)]
+struct ReadContext<'a> {
+    version : DataVersion,
+    defaults : WorldspaceDefaults,
+    sink : &'a dyn LandSink,
+    /// Where cells we couldn't read go, with `keep_going`.
+    skipped : Option<&'a Mutex<Vec<Error>>>,
+    /// Of each file, in load order.
+    form_ids : &'a [FormIds],
+    names : &'a [String],
+    /// The file with the last say on each CELL, LAND and REFR, by FormID.
+    /// `None` for a single file, which has the last say on everything.
+    winners : Option<&'a HashMap<u32, usize>>,
+}
+
+impl ReadContext<'_> {
+    /// Whether this is the version of the record we go with.
+    fn wins(&self, record : &RecordHeader, file : usize) -> bool {
+        !record.is_deleted() && self.winners.is_none_or(|w| w.get(&self.form_ids[file].resolve(record.id)) == Some(&file))
+    }
+
+    /// For errors found in a span we read out of a file.
+    fn locate(&self, e : Error, span : Span) -> Error {
+        e.offset_by(span.offset).in_plugin(&self.names[span.file])
+    }
+}
+
+/// A cell put together from every file that has a say on it.
+#[derive(Default)]
+struct MergedCell {
+    cell : Option<Cell>,
+    land : Option<Land>,
+    references : Vec<Reference>,
+}
+
+/// Hands a worldspace to `sink`, with the last-loaded version of every cell, LAND and placed object in `files`.
+/// Returns the errors of the cells skipped with `keep_going`.
+pub(crate) fn read_worldspace(files : &mut [ESMFile], worldspace : &str, sink : &mut dyn LandSink, keep_going : bool) -> Result<Vec<Error>> {
+    let version = files[0].version;
+    if matches!(version, DataVersion::Morrowind) {
+        return tes3::read_worldspace(files, worldspace, sink, keep_going);
+    }
+
+    let mut records = WorldRecords::default();
+    let mut wrld_groups = Vec::with_capacity(files.len());
+
+    for file in files.iter_mut() {
+        file.reader.seek(SeekFrom::Start(file.data_start))?;
+        let (wrld_group, file_records) = WorldRecords::read(&mut file.reader, version, &file.form_ids).map_err(|e| e.in_plugin(&file.name))?;
+        records.merge(file_records);
+        wrld_groups.push(wrld_group);
+    }
+    records.link_texture_sets();
+
+    // The first file with the worldspace finds it by editor ID, and the ones after by its FormID:
+    let mut world : Option<FoundWorldspace> = None;
+    let mut children : Vec<Option<GroupHeader>> = Vec::with_capacity(files.len());
+    let mut available : Vec<String> = Vec::new();
+
+    for (file, wrld_group) in files.iter_mut().zip(wrld_groups) {
+        let Some(wrld_group) = wrld_group else {
+            children.push(None);
+            continue;
+        };
+
+        let form_id = world.as_ref().map(|w| w.form_id);
+        let found = find_world_children(&mut file.reader, version, wrld_group, &file.form_ids, |edid, id| match form_id {
+            Some(form_id) => id == form_id,
+            None => edid.eq_ignore_ascii_case(worldspace)
+        }, &mut available).map_err(|e| e.in_plugin(&file.name))?;
+
+        let Some(mut found) = found else {
+            children.push(None);
+            continue;
+        };
+        children.push(found.children.take());
+
+        match &mut world {
+            // An override of the WRLD record changes its defaults, but it's still the worldspace we found:
+            Some(world) => world.defaults = found.defaults,
+            None => world = Some(found)
+        }
+    }
+
+    let world = world.ok_or_else(|| worldspace_not_found(worldspace, &available))?;
+
+    // Where each sub-block is in every file, and which file has the last say on each record:
+    let mut batches : Vec<Batch> = Vec::new();
+    let mut winners : Option<HashMap<u32, usize>> = (files.len() > 1).then(HashMap::new);
+
+    for (i, (file, group)) in files.iter_mut().zip(&children).enumerate() {
+        let Some(group) = group else {
+            continue;
+        };
+
+        let spans = find_spans(&mut file.reader, version, group, i).map_err(|e| group.locate(e).in_plugin(&file.name))?;
+
+        for (subblock, span) in spans {
+            if let Some(winners) = &mut winners {
+                index_records(&mut file.reader, version, span, &file.form_ids, winners).map_err(|e| e.in_plugin(&file.name))?;
+            }
+
+            let batch = match batches.iter_mut().position(|b| b.subblock == subblock) {
+                Some(i) => &mut batches[i],
+                None => {
+                    batches.push(Batch {
+                        subblock,
+                        cells: subblock.map(subblock_cells),
+                        spans: Vec::new()
+                    });
+                    batches.last_mut().unwrap()
+                }
+            };
+            if let Some(cells) = &mut batch.cells {
+                fit_cells(&mut file.reader, version, span, cells).map_err(|e| e.in_plugin(&file.name))?;
+            }
+            batch.spans.push(span);
+        }
+    }
+
+    if batches.is_empty() {
+        return Err(no_cells(&world.edid));
+    }
+
+    let subblocks : Vec<(Pos, Pos)> = batches.iter().filter_map(|b| b.cells).collect();
+    sink.on_worldspace(Worldspace {
+        edid: &world.edid,
+        form_id: world.form_id,
+        records: &records,
+        subblocks: &subblocks,
+        persistent_cell: batches.iter().any(|b| b.cells.is_none())
+    })?;
+
+    // The files are still being read from while the batches are, so those get their own copies:
+    let form_ids : Vec<FormIds> = files.iter().map(|f| f.form_ids.clone()).collect();
+    let names : Vec<String> = files.iter().map(|f| f.name.clone()).collect();
+    let skipped : Mutex<Vec<Error>> = Mutex::new(Vec::new());
+
+    let context = ReadContext {
+        version,
+        defaults: world.defaults,
+        sink,
+        skipped: keep_going.then_some(&skipped),
+        form_ids: &form_ids,
+        names: &names,
+        winners: winners.as_ref()
+    };
+
+    let bar = ProgressBar::new(batches.iter().flat_map(|b| &b.spans).map(|s| s.size).sum());
+    bar.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:100} {msg}").unwrap());
+
+    let pool = ThreadPoolBuilder::new().thread_name(|i| {
+        format!("Subgroup Reader {i}")
+    }).build().map_err(std::io::Error::other)?;
+
+    // The first thing that went wrong in a subgroup reader, so we can stop reading and report it:
+    let failure : Mutex<Option<Error>> = Mutex::new(None);
+    let failure = &failure;
+
+    // Reading from the files happens here, one batch after another, while the batches themselves are parsed on the pool.
+    // Hence the scope call, which waits for all of them once we're done reading:
+    pool.scope(|scope| -> Result<()> {
+        for batch in batches {
+            if failure.lock().unwrap().is_some() {
+                break;
+            }
+
+            let mut buffers = Vec::with_capacity(batch.spans.len());
+            for span in batch.spans {
+                let file = &mut files[span.file];
+                let mut buf = vec![0; span.size as usize];
+
+                file.reader.seek(SeekFrom::Start(span.offset))?;
+                file.reader.read_exact(&mut buf).map_err(|e| Error::from(e).at_offset(span.offset).in_plugin(&file.name))?;
+                buffers.push((span, buf));
+            }
+
+            let bar = bar.clone();
+            scope.spawn(move |_| {
+                let result = read_batch(buffers, batch.cells.is_none(), context, &bar).and_then(|_| match batch.cells {
+                    Some((min, max)) => context.sink.on_subblock_done(min, max),
+                    None => context.sink.on_persistent_cell_done()
+                });
+
+                if let Err(e) = result {
+                    failure.lock().unwrap().get_or_insert(e);
+                }
+            });
+        }
+        Ok(())
+    })?;
+
+    if let Some(e) = failure.lock().unwrap().take() {
+        bar.abandon();
+        return Err(e);
+    }
+    bar.finish();
+
+    sink.finish()?;
+    Ok(sorted_skips(skipped.into_inner().unwrap()))
+}
+
+/// Skipped cells come from several threads at once. Puts them back in the order they're in the files, so the same plugins skip the same way every time.
+pub(crate) fn sorted_skips(mut skipped : Vec<Error>) -> Vec<Error> {
+    skipped.sort_by(|a, b| (&a.location().plugin, a.location().offset).cmp(&(&b.location().plugin, b.location().offset)));
+    skipped
+}
+
+/// Finds the sub-blocks in a World Children group (whose header `reader` is right after), and the persistent cell before them.
+fn find_spans(reader : &mut (impl Read + Seek), version : DataVersion, group : &GroupHeader, file : usize) -> Result<Vec<(Option<[u8; 4]>, Span)>> {
+    let end = group.offset + group.total_size as u64;
+    let mut spans = Vec::new();
+
+    loop {
+        let start = reader.stream_position()?;
+        if start >= end {
+            break;
+        }
+
+        // Before the exterior cell blocks, there can be records directly in the World Children group
+        // (Oblivion's ROAD record, and the worldspace's persistent cell):
+        if peek_signature(reader)? != *b"GRUP" {
+            let record = RecordHeader::read(reader, version)?;
+            record.skip_data(reader)?;
+
+            if record.ty == "CELL" {
+                skip_cell_children(reader, version)?;
+                spans.push((None, Span {
+                    file,
+                    offset: start,
+                    size: reader.stream_position()? - start
+                }));
+            }
+            continue;
+        }
+
+        let block = GroupHeader::read(reader, version)?;
+        if block.group_ty != 4 {
+            block.skip_data(reader)?;
+            continue;
+        }
+
+        let block_end = block.offset + block.total_size as u64;
+        while reader.stream_position()? < block_end {
+            let subblock = GroupHeader::read(reader, version)?;
+            spans.push((Some(subblock.label), Span {
+                file,
+                offset: reader.stream_position()?,
+                size: subblock.total_size.checked_sub(GroupHeader::header_size(version)).ok_or_else(|| subblock.truncated())? as u64
+            }));
+            subblock.skip_data(reader)?;
+        }
+    }
+
+    Ok(spans)
+}
+
+/// Notes down that the file the span is in has the last say (so far) on every CELL, LAND and REFR in it.
+fn index_records(reader : &mut (impl Read + Seek), version : DataVersion, span : Span, form_ids : &FormIds, winners : &mut HashMap<u32, usize>) -> Result<()> {
+    reader.seek(SeekFrom::Start(span.offset))?;
+    let end = span.offset + span.size;
+
+    while reader.stream_position()? < end {
+        // Groups in here are all Cell Children and their subgroups, whose records come right after their headers:
+        if peek_signature(reader)? == *b"GRUP" {
+            GroupHeader::read(reader, version)?;
+            continue;
+        }
+
+        let record = RecordHeader::read(reader, version)?;
+        if matches!(record.ty.as_str(), "CELL" | "LAND" | "REFR") {
+            winners.insert(form_ids.resolve(record.id), span.file);
+        }
+        record.skip_data(reader)?;
+    }
+    Ok(())
+}
+
+/// Grows `cells` to fit every exterior CELL in a sub-block's span, since cells are drawn where their grid position says, whichever sub-block they're in.
+/// Cells that can't be read are left for `read_batch` to report (or skip).
+fn fit_cells(reader : &mut (impl Read + Seek), version : DataVersion, span : Span, cells : &mut (Pos, Pos)) -> Result<()> {
+    reader.seek(SeekFrom::Start(span.offset))?;
+    let end = span.offset + span.size;
+
+    while reader.stream_position()? < end {
+        // The Cell Children groups after each CELL:
+        if peek_signature(reader)? == *b"GRUP" {
+            GroupHeader::read(reader, version)?.skip_data(reader)?;
+            continue;
+        }
+
+        let record = RecordHeader::read(reader, version)?;
+        if record.ty != "CELL" {
+            record.skip_data(reader)?;
+            continue;
+        }
+
+        let next = reader.stream_position()? + record.data_size as u64;
+        let data = record.read_data(reader);
+        if let Ok(Some((x, y))) = data.and_then(|data| read_cell_grid(&mut Cursor::new(data), version)) {
+            let ((min_x, min_y), (max_x, max_y)) = *cells;
+            *cells = ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)));
+        }
+        reader.seek(SeekFrom::Start(next))?;
+    }
+    Ok(())
+}
+
+/// The first and last cell of a sub-block, from its group label.
+fn subblock_cells(label : [u8; 4]) -> (Pos, Pos) {
+    // The label is the sub-block's grid position, Y first. Each sub-block is 8 x 8 cells:
+    let y = i16::from_le_bytes([label[0], label[1]]) as i32;
+    let x = i16::from_le_bytes([label[2], label[3]]) as i32;
+
+    ((x * 8, y * 8), (x * 8 + 7, y * 8 + 7))
+}
+
+/// Reads the same sub-block (or the `persistent` cell) from every file that has it, and hands the winning version of each record to the sink.
+fn read_batch(buffers : Vec<(Span, Vec<u8>)>, persistent : bool, context : ReadContext, bar : &ProgressBar) -> Result<()> {
+    // In the order we first come across them:
+    let mut cells : Vec<MergedCell> = Vec::new();
+    let mut cell_indices : HashMap<u32, usize> = HashMap::new();
+
+    for (span, buf) in buffers {
+        let end = buf.len() as u64;
+        let mut reader = Cursor::new(buf);
+        let form_ids = &context.form_ids[span.file];
+        let wins = |record : &RecordHeader| context.wins(record, span.file);
+
+        while reader.position() < end {
+            let start = reader.position();
+            let cell = RecordHeader::read(&mut reader, context.version).map_err(|e| context.locate(e, span))?;
+
+            match read_cell(&mut reader, context.version, context.defaults, &cell, persistent, form_ids, &wins) {
+                Ok(contents) => {
+                    let index = *cell_indices.entry(form_ids.resolve(cell.id)).or_insert_with(|| {
+                        cells.push(MergedCell::default());
+                        cells.len() - 1
+                    });
+                    let merged = &mut cells[index];
+
+                    if wins(&cell) {
+                        merged.cell = Some(contents.cell);
+                    }
+                    if contents.land.is_some() {
+                        merged.land = contents.land;
+                    }
+                    merged.references.extend(contents.references);
+                },
+                Err(e) => {
+                    let Some(skipped) = context.skipped else {
+                        return Err(context.locate(e, span));
+                    };
+                    skipped.lock().unwrap().push(context.locate(e, span));
+
+                    // Pick up again after the cell and its children:
+                    reader.seek(SeekFrom::Start(cell.offset + RecordHeader::header_size(context.version) as u64 + cell.data_size as u64))?;
+                    skip_cell_children(&mut reader, context.version).map_err(|e| context.locate(e, span))?;
+                }
+            }
+
+            // Not exactly accurate for going up to 100% (we miss counting a lot of the headers for groups), but the files are so big I think this is okay:
+            bar.inc(reader.position() - start);
+        }
+    }
+
+    for merged in cells {
+        // Deleted, or only in a file we couldn't read it from:
+        let Some(cell) = merged.cell else {
+            continue;
+        };
+        bar.set_message(format!("Cell {},{}", cell.x, cell.y));
+
+        context.sink.on_cell(&cell)?;
+        if let Some(mut land) = merged.land {
+            // The LAND can be from an earlier file than the CELL:
+            land.cell = cell.clone();
+            context.sink.on_land(land)?;
+        }
+        for reference in &merged.references {
+            context.sink.on_reference(&cell, reference)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// A Skyrim plugin with nothing but its TES4 header.
+    fn write_plugin(dir : &Path, name : &str, masters : &[&str]) -> PathBuf {
+        let field = |ty : &[u8; 4], data : &[u8]| [ty.as_slice(), &(data.len() as u16).to_le_bytes(), data].concat();
+
+        let mut fields = field(b"HEDR", &[1.71f32.to_le_bytes(), 0u32.to_le_bytes(), 0x800u32.to_le_bytes()].concat());
+        for master in masters {
+            fields.extend(field(b"MAST", &[master.as_bytes(), &[0]].concat()));
+            fields.extend(field(b"DATA", &0u64.to_le_bytes()));
+        }
+
+        let header = [b"TES4".as_slice(), &(fields.len() as u32).to_le_bytes(), &[0; 16]].concat();
+        let path = dir.join(name);
+        fs::write(&path, [header, fields].concat()).unwrap();
+        path
+    }
+
+    fn names(paths : &[PathBuf]) -> Vec<String> {
+        paths.iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn plugins_txt_with_and_without_stars() {
+        let dir = test_dir("plugins-txt");
+
+        // Newer games list every plugin, and star the ones that are on:
+        let starred = dir.join("starred.txt");
+        fs::write(&starred, "# This file is used by the game to keep track of your downloaded content.\r\n*Unofficial Patch.esp\r\nOff.esp\r\n\r\n  *Mod.esm  \r\n#*Commented.esp\r\n").unwrap();
+        assert_eq!(read_plugins_txt(&starred).unwrap(), ["Unofficial Patch.esp", "Mod.esm"]);
+
+        // Older ones only list the ones that are on:
+        let plain = dir.join("plain.txt");
+        fs::write(&plain, "# Oblivion\nOn.esp\n\nAlso On.esm\n").unwrap();
+        assert_eq!(read_plugins_txt(&plain).unwrap(), ["On.esp", "Also On.esm"]);
+
+        assert!(read_plugins_txt(&dir.join("missing.txt")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_order_from_plugins_txt() {
+        let dir = test_dir("load-order-paths");
+        for name in ["Skyrim.esm", "Update.esm", "Mod.esp"] {
+            write_plugin(&dir, name, &[]);
+        }
+        let plugins_txt = dir.join("plugins.txt");
+        fs::write(&plugins_txt, "*Mod.esp\n*skyrim.esm\n").unwrap();
+
+        // The masters the game always loads go first (but only those that are there), then plugins.txt, then what's passed in.
+        // Anything listed twice stays where it's first listed:
+        let paths = load_order_paths(&dir.join("Skyrim.esm"), Some(&plugins_txt), &[dir.join("Extra.esp"), dir.join("MOD.ESP")], DataVersion::Skyrim).unwrap();
+        assert_eq!(names(&paths), ["Skyrim.esm", "Update.esm", "Mod.esp", "Extra.esp"]);
+
+        // Without a plugins.txt, it's the main file and then the plugins as given:
+        let paths = load_order_paths(&dir.join("Mod.esp"), None, &[dir.join("Update.esm")], DataVersion::Skyrim).unwrap();
+        assert_eq!(names(&paths), ["Mod.esp", "Update.esm"]);
+
+        // A main file plugins.txt doesn't turn on still goes first:
+        let paths = load_order_paths(&dir.join("Other.esm"), Some(&plugins_txt), &[], DataVersion::Skyrim).unwrap();
+        assert_eq!(names(&paths), ["Other.esm", "Skyrim.esm", "Update.esm", "Mod.esp"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn masters_listed_out_of_load_order() {
+        let dir = test_dir("resolve-masters");
+        let paths = [
+            write_plugin(&dir, "A.esm", &[]),
+            write_plugin(&dir, "B.esm", &["A.esm"]),
+            write_plugin(&dir, "Light.esl", &["A.esm"]),
+            // Its masters in a different order than they're loaded in:
+            write_plugin(&dir, "Plugin.esp", &["B.esm", "Light.esl", "a.esm"]),
+        ];
+        let load_order = LoadOrder::open(&paths, DataVersion::Skyrim).unwrap();
+        let form_ids = &load_order.files()[3].form_ids;
+
+        // The top byte picks the master by where it is in the plugin's own list:
+        assert_eq!(form_ids.resolve(0x00000123), 0x01000123);
+        assert_eq!(form_ids.resolve(0x01000456), 0xFE000456);
+        assert_eq!(form_ids.resolve(0x02000789), 0x00000789);
+        // Past the masters is the plugin itself, the third full plugin. Light plugins don't count towards that:
+        assert_eq!(form_ids.resolve(0x03000ABC), 0x02000ABC);
+
+        // A master that isn't loaded before the plugin:
+        let error = LoadOrder::open(&paths[1..], DataVersion::Skyrim).unwrap_err();
+        assert!(matches!(&error, Error::MissingMaster { master, at } if master == "A.esm" && at.plugin.as_deref() == Some("B.esm")), "{error:?}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_load_order() {
+        let error = LoadOrder::open(&[], DataVersion::Skyrim).unwrap_err();
+        assert!(matches!(&error, Error::Io { source, .. } if source.kind() == std::io::ErrorKind::InvalidData), "{error:?}");
+    }
+}
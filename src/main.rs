@@ -1,47 +1,312 @@
-use std::{fs::File, io::BufReader, path::PathBuf};
+use std::{path::PathBuf, sync::Arc};
 
-use clap::Parser;
-use esm::DataVersion;
-
-mod esm;
-mod world_gen;
+use clap::{builder::PossibleValuesParser, Parser, Subcommand};
+use skyrim2minecraft::{bsa::{Archive, DataFiles}, error::Error, esm::{ConvertOptions, DataVersion, ESMFile, Game}, load_order::LoadOrder, mapping::Mapping, meshes::Meshes, texture_sampling::{generate_mapping, palette_names, SampleColor}, world_gen::{Interpolation, Scale}};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// .esm file to load world data from and convert into Minecraft .mca files.
+    #[command(subcommand)]
+    command : Command,
+}
+
+#[derive(clap::Args, Debug)]
+struct ESMArgs {
+    /// .esm file to load world data from.
     file : PathBuf,
 
-    /// ESM Data Version to use.
+    /// ESM Data Version to use. Detected from the plugin's header if left out.
     #[arg(value_enum)]
-    data_version : DataVersion,
+    data_version : Option<DataVersion>,
+}
+
+impl ESMArgs {
+    /// The data version to read the plugin with, and the game its header says it's from (if we can tell).
+    fn data_version(&self) -> Result<(DataVersion, Option<Game>), Error> {
+        let detected = Game::detect(&self.file);
 
-    #[arg(short, long)]
-    out_path : Option<PathBuf>,
+        match (self.data_version, detected) {
+            (Some(version), Ok(game)) => {
+                if game.data_version() != version {
+                    eprintln!("Warning: {:?} looks like a {game} plugin, but reading it as {version:?} data as asked.", self.file);
+                }
+                Ok((version, Some(game)))
+            },
+            // The override is there for plugins we can't tell the game of:
+            (Some(version), Err(_)) => Ok((version, None)),
+            (None, Ok(game)) => {
+                // Not on stdout, so the output of info and list-worldspaces stays the same:
+                eprintln!("Detected a {game} plugin.");
+                Ok((game.data_version(), Some(game)))
+            },
+            (None, Err(e)) => Err(e)
+        }
+    }
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(clap::Args, Debug)]
+struct LoadOrderArgs {
+    /// Plugin to load after FILE, overriding its cells, LAND and references. Repeat for more, in load order.
+    #[arg(short, long = "plugin")]
+    plugins : Vec<PathBuf>,
+
+    /// plugins.txt to take the load order from. The plugins in it are looked for next to FILE.
+    #[arg(long)]
+    plugins_txt : Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert a worldspace into Minecraft .mca files.
+    Convert {
+        #[command(flatten)]
+        esm : ESMArgs,
+
+        #[command(flatten)]
+        load_order : LoadOrderArgs,
+
+        #[arg(short, long)]
+        out_path : Option<PathBuf>,
+
+        /// Editor ID of the worldspace to convert (e.g. DLC2SolstheimWorld, SEWorld, Wasteland).
+        /// Defaults to the game's main one: Tamriel, Wasteland (Fallout 3), WastelandNV, Commonwealth (Fallout 4) or Vvardenfell (Morrowind).
+        #[arg(short, long)]
+        worldspace : Option<String>,
+
+        /// .toml file mapping land textures, water and objects to blocks. Defaults to the mapping in `mappings/` for the data version.
+        #[arg(short, long)]
+        mapping : Option<PathBuf>,
+
+        /// How many Skyrim Units wide a block is. Lower values make a bigger world.
+        #[arg(long, default_value_t = 64.0, value_parser = parse_scale)]
+        horizontal_scale : f64,
+
+        /// How many Skyrim Units tall a block is. Lower values make taller mountains, which may get cut off at the build limit.
+        #[arg(long, default_value_t = 64.0, value_parser = parse_scale)]
+        vertical_scale : f64,
+
+        /// How to work out the height of blocks in between LAND vertices.
+        #[arg(long, value_enum, default_value_t = Interpolation::default())]
+        interpolation : Interpolation,
+
+        /// Log cells that can't be read and skip them, instead of stopping at the first one.
+        #[arg(long)]
+        keep_going : bool,
+
+        /// The game's Data folder. Placed objects are turned into blocks from their meshes in it (loose files, or in its .bsa archives).
+        /// Without it, only trees are placed.
+        #[arg(long)]
+        data : Option<PathBuf>,
+    },
+    /// Write a mapping that picks a block for each land texture by the color of its texture, to edit by hand from there.
+    GenerateMapping {
+        #[command(flatten)]
+        esm : ESMArgs,
+
+        #[command(flatten)]
+        load_order : LoadOrderArgs,
+
+        /// The game's Data folder, where the textures are (as loose files, or in its .bsa and .ba2 archives).
+        #[arg(long)]
+        data : PathBuf,
+
+        /// Mapping to add the land textures to. Land textures it already has are left alone. Defaults to the mapping in `mappings/` for the data version.
+        #[arg(short, long)]
+        mapping : Option<PathBuf>,
+
+        #[arg(short, long, default_value = "generated_mapping.toml")]
+        out_path : PathBuf,
+
+        /// Which color of each texture picks its block.
+        #[arg(long, value_enum, default_value_t = SampleColor::default())]
+        color : SampleColor,
+
+        /// Blocks to pick from. Repeat for more.
+        #[arg(long = "palette", default_value = "natural", value_parser = PossibleValuesParser::new(palette_names()))]
+        palettes : Vec<String>,
+    },
+    /// Print the plugin's TES4 header (game, version, masters, record count, author).
+    Info {
+        #[command(flatten)]
+        esm : ESMArgs,
+    },
+    /// List every worldspace in the plugin, with its cell count and bounds.
+    ListWorldspaces {
+        #[command(flatten)]
+        esm : ESMArgs,
+    },
+    /// List the files in a .bsa or .ba2 archive.
+    ListArchive {
+        archive : PathBuf,
+    },
+    /// Copy a file out of a .bsa or .ba2 archive.
+    Extract {
+        archive : PathBuf,
+
+        /// Path of the file in the archive (e.g. meshes\clutter\bucket01.nif).
+        path : String,
+
+        /// Where to write it. Defaults to its file name, in the current folder.
+        #[arg(short, long)]
+        out_path : Option<PathBuf>,
+    },
+}
+
+fn parse_scale(s : &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(scale) if scale.is_finite() && scale > 0.0 => Ok(scale),
+        Ok(_) => Err("scale must be a positive number of Skyrim Units per block".into()),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+fn convert(esm : ESMArgs, load_order : LoadOrderArgs, out_path : Option<PathBuf>, worldspace : Option<String>, mapping : Option<PathBuf>, data : Option<PathBuf>, mut options : ConvertOptions) -> Result<(), Error> {
+    let (data_version, _) = esm.data_version()?;
+    let worldspace = worldspace.unwrap_or_else(|| data_version.default_worldspace().into());
+
+    // Load the mapping first, so mistakes in it show up before we touch anything:
+    let mapping = Mapping::load_or_default(mapping.as_deref(), data_version)?;
+
+    if let Some(data) = data {
+        let meshes = Meshes::open(&data)?;
+        skipping_archives(meshes.data());
+        options.meshes = Some(Arc::new(meshes));
+    }
+    let meshes = options.meshes.clone();
+
+    let mut files = LoadOrder::for_plugin(&esm.file, load_order.plugins_txt.as_deref(), &load_order.plugins, data_version)?;
+
+    let out_dir = out_path.unwrap_or(PathBuf::from("./region"));
+    let skipped = files.convert(&out_dir, &worldspace, &mapping, options)?;
+    for e in skipped {
+        eprintln!("Skipped cell: {e}");
+    }
+    if let Some(meshes) = meshes {
+        for (path, e) in meshes.take_failed() {
+            eprintln!("Skipped mesh {path}: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Warns about the archives in the Data folder we can't read from.
+fn skipping_archives(data : &DataFiles) {
+    for e in data.skipped() {
+        eprintln!("Skipping archive: {e}");
+    }
+}
+
+fn generate(esm : ESMArgs, load_order : LoadOrderArgs, data : PathBuf, mapping : Option<PathBuf>, out_path : PathBuf, color : SampleColor, palettes : Vec<String>) -> Result<(), Error> {
+    let (data_version, _) = esm.data_version()?;
+
+    let template = match mapping {
+        Some(path) => std::fs::read_to_string(&path).map_err(|e| std::io::Error::new(e.kind(), format!("Could not read mapping file {path:?}: {e}")))?,
+        None => Mapping::default_text(data_version).into()
+    };
 
-    let skyrim = File::open(args.file).unwrap();
+    let records = LoadOrder::for_plugin(&esm.file, load_order.plugins_txt.as_deref(), &load_order.plugins, data_version)?.world_records()?;
+    let data = DataFiles::open(&data)?;
+    skipping_archives(&data);
+
+    let generated = generate_mapping(&template, &records, &data, data_version, color, &palettes)?;
+    eprintln!("Matched {} of {} new land textures to blocks.", generated.matched, generated.new_land_textures);
+    std::fs::write(&out_path, generated.text).map_err(|e| std::io::Error::new(e.kind(), format!("Could not write {out_path:?}: {e}")))?;
+    Ok(())
+}
 
-    let out_dir = args.out_path.unwrap_or(PathBuf::from("./region"));
-    
-	if !out_dir.exists() {
-		std::fs::create_dir_all(&out_dir).expect("Could not create gen directory.");
-	}
+fn info(esm : ESMArgs) -> Result<(), Error> {
+    let (data_version, game) = esm.data_version()?;
+    let esm_file = ESMFile::open(&esm.file, data_version)?;
 
-    // Clean out .mca in the target directory, so we don't have weird overlaps with previously written data:
-    for path in std::fs::read_dir(&out_dir).expect(&format!("Could not read directory {:?}", out_dir)) {
-        let p = path.expect(&format!("Could not read directory entry")).path();
-        let extension = p.extension().expect(&format!("Could not get extension of {:?}", p));
-        if extension == "mca" {
-            std::fs::remove_file(&p).expect(&format!("Could not remove file {p:?}."));
+    let header = esm_file.header();
+
+    println!("Game: {}", game.map(|g| g.to_string()).unwrap_or("-".into()));
+    println!("Version: {}", header.version);
+
+    let flags : Vec<&str> = [(header.is_master(), "master"), (header.is_localized(), "localized"), (esm_file.is_light(), "light")]
+        .into_iter().filter_map(|(set, name)| set.then_some(name)).collect();
+    println!("Flags: {}", if flags.is_empty() { "-".into() } else { flags.join(", ") });
+    println!("Records: {}", header.record_count);
+    println!("Next Object ID: {:08X}", header.next_object_id);
+    println!("Author: {}", header.author.as_deref().unwrap_or("-"));
+    println!("Description: {}", header.description.as_deref().unwrap_or("-"));
+    if header.masters.is_empty() {
+        println!("Masters: -");
+    } else {
+        println!("Masters:");
+        for master in &header.masters {
+            println!("  {master}");
         }
     }
+    Ok(())
+}
+
+fn list_worldspaces(esm : ESMArgs) -> Result<(), Error> {
+    let (data_version, _) = esm.data_version()?;
+    let worldspaces = ESMFile::open(&esm.file, data_version)?.worldspaces()?;
+
+    let width = worldspaces.iter().map(|w| w.edid.len()).max().unwrap_or(0).max("EDID".len());
+
+    println!("{:<width$}  {:<8}  {:<width$}  {:>6}  Bounds", "EDID", "FormID", "Parent", "Cells");
+    for world in &worldspaces {
+        // Show the parent by name if it's in this file, otherwise fall back to its FormID:
+        let parent = match world.parent {
+            Some(id) => worldspaces.iter().find(|w| w.form_id == id).map(|w| w.edid.clone()).unwrap_or(format!("{id:08X}")),
+            None => "-".into()
+        };
+
+        let bounds = match world.bounds {
+            Some(((min_x, min_y), (max_x, max_y))) => format!("({min_x}, {min_y}) to ({max_x}, {max_y})"),
+            None => "-".into()
+        };
+
+        println!("{:<width$}  {:08X}  {:<width$}  {:>6}  {}", world.edid, world.form_id, parent, world.cell_count, bounds);
+    }
+    Ok(())
+}
+
+fn list_archive(path : PathBuf) -> Result<(), Error> {
+    let archive = Archive::open(&path)?;
+
+    let mut files : Vec<&str> = archive.files().collect();
+    files.sort();
+    for file in files {
+        println!("{file}");
+    }
+    Ok(())
+}
+
+fn extract(path : PathBuf, file : String, out_path : Option<PathBuf>) -> Result<(), Error> {
+    let archive = Archive::open(&path)?;
 
-    let pth = out_dir.as_path();
+    let data = archive.read(&file)?.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{file} isn't in {path:?}.")))?;
+    let out_path = out_path.unwrap_or_else(|| file.rsplit(['\\', '/']).next().unwrap_or(&file).into());
 
-    let mut buf_reader = BufReader::new(skyrim);
-    esm::ESMReader::read(args.data_version, &mut buf_reader, pth);
+    std::fs::write(&out_path, data).map_err(|e| std::io::Error::new(e.kind(), format!("Could not write {out_path:?}: {e}")))?;
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let result = match args.command {
+        Command::Convert { esm, load_order, out_path, worldspace, mapping, horizontal_scale, vertical_scale, interpolation, keep_going, data } => {
+            convert(esm, load_order, out_path, worldspace, mapping, data, ConvertOptions {
+                scale: Scale { horizontal: horizontal_scale, vertical: vertical_scale },
+                interpolation,
+                keep_going,
+                meshes: None
+            })
+        },
+        Command::GenerateMapping { esm, load_order, data, mapping, out_path, color, palettes } => generate(esm, load_order, data, mapping, out_path, color, palettes),
+        Command::Info { esm } => info(esm),
+        Command::ListWorldspaces { esm } => list_worldspaces(esm),
+        Command::ListArchive { archive } => list_archive(archive),
+        Command::Extract { archive, path, out_path } => extract(archive, path, out_path),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
 }
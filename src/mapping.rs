@@ -0,0 +1,878 @@
+use std::{collections::HashMap, path::Path};
+
+use toml::{Table, Value};
+
+use crate::{block_colors, error::Error, esm::{BaseObject, Cell, DataVersion, LandTexture, WorldRecords}, trees::Species, world_gen::Block};
+
+const SKYRIM_MAPPING : &str = include_str!("../mappings/skyrim.toml");
+const OBLIVION_MAPPING : &str = include_str!("../mappings/oblivion.toml");
+const FALLOUT_MAPPING : &str = include_str!("../mappings/fallout.toml");
+const MORROWIND_MAPPING : &str = include_str!("../mappings/morrowind.toml");
+
+/// A set of blocks to pick from (by weight), with overrides for certain altitudes.
+#[derive(Debug)]
+pub struct BlockRule {
+	choices : WeightedBlocks,
+	altitude : Vec<AltitudeOverride>,
+}
+
+#[derive(Debug)]
+struct WeightedBlocks {
+	blocks : Vec<(Block, u32)>,
+	total_weight : u32,
+}
+
+#[derive(Debug)]
+struct AltitudeOverride {
+	above : Option<i32>,
+	below : Option<i32>,
+	choices : WeightedBlocks,
+}
+
+impl WeightedBlocks {
+	fn pick(&self, x : i32, y : i32, z : i32) -> &Block {
+		if self.blocks.len() == 1 {
+			return &self.blocks[0].0;
+		}
+
+		// Hashing the position instead of using an RNG means converting the same file twice gives the same world:
+		let mut roll = (position_hash(x, y, z) % self.total_weight as u64) as u32;
+		for (block, weight) in &self.blocks {
+			if roll < *weight {
+				return block;
+			}
+			roll -= weight;
+		}
+		&self.blocks[self.blocks.len() - 1].0
+	}
+}
+
+impl BlockRule {
+	/// Picks a block for the given world position. `y` is the Minecraft y-level the block will be placed at.
+	pub fn pick(&self, x : i32, y : i32, z : i32) -> &Block {
+		let choices = self.altitude.iter().find(|a| {
+			a.above.is_none_or(|above| y >= above) && a.below.is_none_or(|below| y < below)
+		}).map(|a| &a.choices).unwrap_or(&self.choices);
+
+		choices.pick(x, y, z)
+	}
+}
+
+/// SplitMix64 over the packed position.
+fn position_hash(x : i32, y : i32, z : i32) -> u64 {
+	let mut h = (x as u32 as u64) ^ ((z as u32 as u64) << 32) ^ ((y as u32 as u64).rotate_left(16));
+	h = h.wrapping_add(0x9E3779B97F4A7C15);
+	h = (h ^ (h >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+	h = (h ^ (h >> 27)).wrapping_mul(0x94D049BB133111EB);
+	h ^ (h >> 31)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VertexColorMode {
+	/// Ignore VCLR.
+	Off,
+	/// Multiply the surface block's color by the vertex color, and use the closest block in the palette.
+	Tint,
+	/// Use the block in the palette closest to the vertex color itself.
+	Replace,
+}
+
+/// How LAND vertex colors change the surface blocks.
+#[derive(Debug)]
+pub struct VertexColors {
+	mode : VertexColorMode,
+	/// Vertex colors closer to white than this leave the surface block alone.
+	threshold : f32,
+	palette : Vec<(Block, [u8; 3])>,
+}
+
+impl Default for VertexColors {
+	fn default() -> Self {
+		Self {
+			mode: VertexColorMode::Off,
+			threshold: 0.0,
+			palette: Vec::new()
+		}
+	}
+}
+
+impl VertexColors {
+	pub fn apply<'a>(&'a self, block : &'a Block, color : [u8; 3]) -> &'a Block {
+		if self.mode == VertexColorMode::Off || block_colors::distance(color, [255, 255, 255]) < self.threshold {
+			return block;
+		}
+
+		let target = match self.mode {
+			VertexColorMode::Tint => {
+				let base = self.palette.iter().find(|(b, _)| b.name == block.name).map(|(_, c)| *c)
+					.or_else(|| block_colors::color_of(&block.name));
+
+				// We can't tint a block we don't know the color of:
+				let Some(base) = base else {
+					return block;
+				};
+
+				[0, 1, 2].map(|i| ((base[i] as u16 * color[i] as u16) / 255) as u8)
+			},
+			_ => color
+		};
+
+		block_colors::nearest(target, &self.palette).map(|i| &self.palette[i].0).unwrap_or(block)
+	}
+}
+
+/// Slab and stair versions of a surface block.
+#[derive(Debug)]
+pub struct Variants {
+	pub slab : Option<Block>,
+	pub stairs : Option<Block>,
+}
+
+/// How slopes get smoothed out with slabs and stairs.
+#[derive(Debug)]
+pub struct Smoothing {
+	/// How far (in blocks) the terrain has to rise above the last full block to get a slab.
+	/// At `1 - slab_threshold` it rounds up to a full block instead.
+	pub slab_threshold : f32,
+	/// How steep (in blocks per block) the terrain has to be to get stairs instead of a slab.
+	pub stair_slope : f32,
+	variants : Vec<(Block, Variants)>,
+}
+
+impl Default for Smoothing {
+	fn default() -> Self {
+		Self {
+			slab_threshold: 0.25,
+			stair_slope: 0.5,
+			variants: Vec::new()
+		}
+	}
+}
+
+impl Smoothing {
+	/// Variants are matched by name, and by whichever properties the mapping gave them.
+	pub fn variants(&self, block : &Block) -> Option<&Variants> {
+		self.variants.iter().find(|(b, _)| {
+			b.name == block.name && b.properties.iter().all(|(k, v)| block.properties.get(k) == Some(v))
+		}).map(|(_, v)| v)
+	}
+}
+
+/// What a placed object turns into as a tree.
+#[derive(Debug, Clone)]
+pub struct TreeRule {
+	pub species : Species,
+	/// How tall the tree is in Skyrim Units, before the placed object's own scale.
+	pub height : f32,
+	/// Dead trees go without.
+	pub leaves : bool,
+}
+
+/// How records from the ESM turn into Minecraft blocks. See `mappings/skyrim.toml` for the file format.
+#[derive(Debug)]
+pub struct Mapping {
+	/// Fills the terrain below the surface block.
+	pub underground : BlockRule,
+	/// Surface block for land textures that nothing else matches.
+	pub fallback : BlockRule,
+	/// Water block for water types that aren't in `water`.
+	pub default_water : BlockRule,
+	/// By lowercase LTEX editor ID.
+	land_textures : HashMap<String, BlockRule>,
+	/// Lowercase substrings of an LTEX's editor ID or texture path, in the order they're checked.
+	texture_patterns : Vec<(String, BlockRule)>,
+	/// By lowercase WATR editor ID.
+	water : HashMap<String, BlockRule>,
+	/// Blocks for the meshes of placed STATs, by lowercase editor ID. These are checked before `object_patterns`.
+	objects : HashMap<String, BlockRule>,
+	/// Lowercase substrings of a STAT's editor ID or mesh path, in the order they're checked. `None` leaves the objects out.
+	object_patterns : Vec<(String, Option<BlockRule>)>,
+	/// For STATs nothing else matches. `None` leaves them out.
+	default_object : Option<BlockRule>,
+	pub vertex_colors : VertexColors,
+	pub smoothing : Smoothing,
+	/// For TREE records that no tree pattern matches. `None` (when the mapping doesn't set it) leaves them out.
+	default_tree : Option<TreeRule>,
+	/// Lowercase substrings of a TREE, STAT or FLOR editor ID, in the order they're checked. `None` leaves the objects out.
+	tree_patterns : Vec<(String, Option<TreeRule>)>,
+	/// For cells that nothing in `regions` or `biome_patterns` matches.
+	default_biome : String,
+	/// Biomes by lowercase REGN editor ID.
+	regions : HashMap<String, String>,
+	/// Lowercase substrings of a cell's REGN, CLMT or WTHR editor IDs, in the order they're checked.
+	biome_patterns : Vec<(String, String)>,
+}
+
+impl Mapping {
+	/// The mapping we ship for the given game.
+	pub fn default_for(version : DataVersion) -> Result<Mapping, Error> {
+		let (contents, name) = Self::default_source(version);
+		Self::parse(contents, name)
+	}
+
+	/// The text of the mapping we ship for the given game, to start a mapping of your own from.
+	pub fn default_text(version : DataVersion) -> &'static str {
+		Self::default_source(version).0
+	}
+
+	/// The text of the mapping we ship for the given game, and where it is in the repo.
+	fn default_source(version : DataVersion) -> (&'static str, &'static str) {
+		match version {
+			DataVersion::Skyrim => (SKYRIM_MAPPING, "mappings/skyrim.toml"),
+			DataVersion::Oblivion => (OBLIVION_MAPPING, "mappings/oblivion.toml"),
+			DataVersion::Fallout3 | DataVersion::FalloutNV | DataVersion::Fallout4 => (FALLOUT_MAPPING, "mappings/fallout.toml"),
+			DataVersion::Morrowind => (MORROWIND_MAPPING, "mappings/morrowind.toml"),
+		}
+	}
+
+	/// The mapping at `path`, or the one we ship for the given game if there isn't one.
+	pub fn load_or_default(path : Option<&Path>, version : DataVersion) -> Result<Mapping, Error> {
+		match path {
+			Some(path) => Self::load(path),
+			None => Self::default_for(version)
+		}
+	}
+
+	pub fn load(path : &Path) -> Result<Mapping, Error> {
+		let contents = std::fs::read_to_string(path).map_err(|e| std::io::Error::new(e.kind(), format!("Could not read mapping file {path:?}: {e}")))?;
+		Self::parse(&contents, &path.to_string_lossy())
+	}
+
+	pub(crate) fn parse(contents : &str, name : &str) -> Result<Mapping, Error> {
+		let table : Table = contents.parse().map_err(|e| Error::invalid_data(format!("{name}: {e}")))?;
+
+		Self::from_table(table).map_err(|e| Error::invalid_data(format!("{name}: {e}")))
+	}
+
+	fn from_table(table : Table) -> Result<Mapping, String> {
+		let mut underground = None;
+		let mut fallback = None;
+		let mut default_water = None;
+		let mut land_textures = HashMap::new();
+		let mut texture_patterns = Vec::new();
+		let mut water = HashMap::new();
+		let mut objects = HashMap::new();
+		let mut object_patterns = Vec::new();
+		let mut default_object = None;
+		let mut vertex_colors = VertexColors::default();
+		let mut smoothing = Smoothing::default();
+		let mut default_tree = None;
+		let mut tree_patterns = Vec::new();
+		// Minecraft's own default, for mappings from before biomes:
+		let mut default_biome = "minecraft:plains".to_string();
+		let mut regions = HashMap::new();
+		let mut biome_patterns = Vec::new();
+
+		for (key, value) in &table {
+			match key.as_str() {
+				"underground" => underground = Some(parse_rule(value, key)?),
+				"fallback" => fallback = Some(parse_rule(value, key)?),
+				"default_water" => default_water = Some(parse_rule(value, key)?),
+				"land_textures" => land_textures = parse_rule_table(value, key)?,
+				"water" => water = parse_rule_table(value, key)?,
+				"objects" => objects = parse_rule_table(value, key)?,
+				"default_object" => default_object = Some(parse_rule(value, key)?),
+				"object_patterns" => object_patterns = parse_patterns(key, value, &["block", "blocks", "altitude"], |rule, path| {
+					// `block = "none"` leaves the objects out, like a tree's species can:
+					match rule.get("block").and_then(|b| b.as_str()) {
+						Some("none") if rule.len() == 1 => Ok(None),
+						_ => Ok(Some(parse_rule(&Value::Table(rule), path)?))
+					}
+				})?,
+				"vertex_colors" => vertex_colors = parse_vertex_colors(value, key)?,
+				"smoothing" => smoothing = parse_smoothing(value, key)?,
+				"default_tree" => default_tree = parse_tree(value, key)?,
+				"tree_patterns" => tree_patterns = parse_patterns(key, value, &["species", "height", "leaves"], |tree, path| parse_tree(&Value::Table(tree), path))?,
+				"texture_patterns" => texture_patterns = parse_patterns(key, value, &["block", "blocks", "altitude"], |rule, path| parse_rule(&Value::Table(rule), path))?,
+				"default_biome" => default_biome = parse_biome(value, key)?,
+				"regions" => {
+					let table = value.as_table().ok_or_else(|| format!("{key}: expected a table"))?;
+					for (region, biome) in table {
+						regions.insert(region.to_lowercase(), parse_biome(biome, &format!("{key}.{region}"))?);
+					}
+				},
+				"biome_patterns" => biome_patterns = parse_patterns(key, value, &["biome"], |pattern, path| {
+					let biome = pattern.get("biome").ok_or_else(|| format!("{path}: missing key `biome`"))?;
+					parse_biome(biome, &format!("{path}.biome"))
+				})?,
+				_ => return Err(format!("unknown key `{key}`"))
+			}
+		}
+
+		Ok(Mapping {
+			underground: underground.ok_or("missing key `underground`")?,
+			fallback: fallback.ok_or("missing key `fallback`")?,
+			default_water: default_water.ok_or("missing key `default_water`")?,
+			land_textures,
+			texture_patterns,
+			water,
+			objects,
+			object_patterns,
+			default_object,
+			vertex_colors,
+			smoothing,
+			default_tree,
+			tree_patterns,
+			default_biome,
+			regions,
+			biome_patterns,
+		})
+	}
+
+	/// The surface rule for a land texture: by editor ID first, then the first matching pattern.
+	pub fn land_texture(&self, texture : Option<&LandTexture>) -> &BlockRule {
+		texture.and_then(|t| {
+			let edid = t.edid.to_lowercase();
+			let path = t.texture.as_deref().unwrap_or_default().to_lowercase();
+
+			self.land_textures.get(&edid).or_else(|| {
+				self.texture_patterns.iter().find(|(pattern, _)| {
+					edid.contains(pattern) || path.contains(pattern)
+				}).map(|(_, rule)| rule)
+			})
+		}).unwrap_or(&self.fallback)
+	}
+
+	/// The rule for a water type, by its WATR editor ID.
+	pub fn water(&self, water_type : Option<&str>) -> &BlockRule {
+		water_type.and_then(|w| self.water.get(&w.to_lowercase())).unwrap_or(&self.default_water)
+	}
+
+	/// The blocks an object placed from `object` turns into, if any: by editor ID first, then the first matching object pattern, then `default_object`.
+	/// Only STATs are turned into blocks.
+	pub fn object(&self, object : &BaseObject) -> Option<&BlockRule> {
+		if object.ty != "STAT" {
+			return None;
+		}
+
+		let edid = object.edid.to_lowercase();
+		let model = object.model.as_deref().unwrap_or_default().to_lowercase();
+
+		if let Some(rule) = self.objects.get(&edid) {
+			return Some(rule);
+		}
+		match self.object_patterns.iter().find(|(pattern, _)| edid.contains(pattern) || model.contains(pattern)) {
+			Some((_, rule)) => rule.as_ref(),
+			None => self.default_object.as_ref()
+		}
+	}
+
+	/// The tree an object placed from `object` turns into, if any: the first matching tree pattern,
+	/// or `default_tree` for TREE records. STAT and FLOR records only become trees when a pattern matches.
+	pub fn tree(&self, object : &BaseObject) -> Option<&TreeRule> {
+		let edid = object.edid.to_lowercase();
+
+		match self.tree_patterns.iter().find(|(pattern, _)| edid.contains(pattern)) {
+			Some((_, tree)) => tree.as_ref(),
+			None if object.ty == "TREE" => self.default_tree.as_ref(),
+			None => None
+		}
+	}
+
+	/// The biome of a cell: by the editor ID of one of its regions first, then the first biome pattern that matches one of its regions,
+	/// then its worldspace's climate, then the weather it most likely has.
+	pub fn biome(&self, cell : &Cell, records : &WorldRecords) -> &str {
+		let regions : Vec<_> = cell.regions.iter().filter_map(|id| records.regions.get(id)).collect();
+		let region_edids : Vec<String> = regions.iter().map(|r| r.edid.to_lowercase()).collect();
+
+		if let Some(biome) = region_edids.iter().find_map(|edid| self.regions.get(edid)) {
+			return biome;
+		}
+
+		// A region's weathers replace its climate's:
+		let climate = cell.climate.and_then(|id| records.climates.get(&id));
+		let mut chances : HashMap<u32, u32> = HashMap::new();
+		for (weather, chance) in regions.iter().flat_map(|r| &r.weathers) {
+			*chances.entry(*weather).or_default() += chance;
+		}
+		if chances.is_empty() {
+			chances.extend(climate.iter().flat_map(|c| c.weathers.iter().copied()));
+		}
+		// Ties go to the lowest FormID, so the same cell always gets the same biome:
+		let weather = chances.iter().max_by_key(|(id, chance)| (**chance, std::cmp::Reverse(**id)))
+			.and_then(|(id, _)| records.weathers.get(id));
+
+		let climate_edid = climate.map(|c| c.edid.to_lowercase());
+		let weather_edid = weather.map(|w| w.to_lowercase());
+
+		let names : [Vec<&str>; 3] = [
+			region_edids.iter().map(String::as_str).collect(),
+			climate_edid.as_deref().into_iter().collect(),
+			weather_edid.as_deref().into_iter().collect(),
+		];
+		names.iter().find_map(|names| {
+			self.biome_patterns.iter().find(|(pattern, _)| names.iter().any(|name| name.contains(pattern.as_str())))
+		}).map_or(&self.default_biome, |(_, biome)| biome)
+	}
+}
+
+fn parse_vertex_colors(value : &Value, path : &str) -> Result<VertexColors, String> {
+	let table = value.as_table().ok_or_else(|| format!("{path}: expected a table"))?;
+
+	if let Some(key) = table.keys().find(|k| !matches!(k.as_str(), "mode" | "threshold" | "palette")) {
+		return Err(format!("{path}: unknown key `{key}`"));
+	}
+
+	let mode = match table.get("mode").map(|m| m.as_str()) {
+		None => VertexColorMode::Off,
+		Some(Some("off")) => VertexColorMode::Off,
+		Some(Some("tint")) => VertexColorMode::Tint,
+		Some(Some("replace")) => VertexColorMode::Replace,
+		Some(_) => return Err(format!("{path}.mode: expected \"off\", \"tint\" or \"replace\""))
+	};
+
+	let threshold = match table.get("threshold") {
+		Some(t) => t.as_float().or(t.as_integer().map(|t| t as f64)).filter(|t| *t >= 0.0)
+			.ok_or_else(|| format!("{path}.threshold: expected a number of at least 0"))? as f32,
+		None => 0.0
+	};
+
+	let family = |name : &str, family_path : &str| {
+		block_colors::family(name).ok_or_else(|| {
+			let names : Vec<&str> = block_colors::FAMILIES.iter().map(|(n, _)| *n).collect();
+			format!("{family_path}: unknown palette `{name}` (expected one of {})", names.join(", "))
+		}).map(|blocks| {
+			blocks.iter().map(|(name, color)| (Block {
+				name: (*name).into(),
+				properties: HashMap::new()
+			}, block_colors::rgb(*color))).collect::<Vec<_>>()
+		})
+	};
+
+	let palette_path = format!("{path}.palette");
+	let palette = match table.get("palette") {
+		None => family("natural", &palette_path)?,
+		Some(Value::String(name)) => family(name, &palette_path)?,
+		Some(Value::Array(names)) => {
+			let mut palette = Vec::new();
+			for (i, name) in names.iter().enumerate() {
+				let name_path = format!("{palette_path}[{i}]");
+				let name = name.as_str().ok_or_else(|| format!("{name_path}: expected a palette name"))?;
+				palette.extend(family(name, &name_path)?);
+			}
+			palette
+		},
+		Some(Value::Table(blocks)) => {
+			blocks.iter().map(|(block, color)| {
+				let block_path = format!("{palette_path}.\"{block}\"");
+				let color = color.as_str().and_then(block_colors::parse_hex).ok_or_else(|| format!("{block_path}: expected a color like \"#7e7e7e\""))?;
+				Ok((parse_block(block, &block_path)?, color))
+			}).collect::<Result<Vec<_>, String>>()?
+		},
+		Some(_) => return Err(format!("{palette_path}: expected a palette name, an array of them, or a table of blocks and colors"))
+	};
+
+	if mode != VertexColorMode::Off && palette.is_empty() {
+		return Err(format!("{palette_path}: must have at least one block"));
+	}
+
+	Ok(VertexColors {
+		mode,
+		threshold,
+		palette
+	})
+}
+
+fn parse_smoothing(value : &Value, path : &str) -> Result<Smoothing, String> {
+	let table = value.as_table().ok_or_else(|| format!("{path}: expected a table"))?;
+
+	if let Some(key) = table.keys().find(|k| !matches!(k.as_str(), "slab_threshold" | "stair_slope" | "variants")) {
+		return Err(format!("{path}: unknown key `{key}`"));
+	}
+
+	let number = |key : &str| table.get(key).map(|n| n.as_float().or(n.as_integer().map(|n| n as f64)));
+
+	let mut smoothing = Smoothing::default();
+
+	if let Some(threshold) = number("slab_threshold") {
+		smoothing.slab_threshold = threshold.filter(|t| *t > 0.0 && *t <= 0.5)
+			.ok_or_else(|| format!("{path}.slab_threshold: expected a number above 0 and at most 0.5"))? as f32;
+	}
+
+	if let Some(slope) = number("stair_slope") {
+		smoothing.stair_slope = slope.filter(|s| *s >= 0.0)
+			.ok_or_else(|| format!("{path}.stair_slope: expected a number of at least 0"))? as f32;
+	}
+
+	if let Some(variants) = table.get("variants") {
+		let variants_path = format!("{path}.variants");
+		let variants = variants.as_table().ok_or_else(|| format!("{variants_path}: expected a table"))?;
+
+		for (block, shapes) in variants {
+			let block_path = format!("{variants_path}.\"{block}\"");
+			let shapes = shapes.as_table().ok_or_else(|| format!("{block_path}: expected a table like {{ slab = \"...\", stairs = \"...\" }}"))?;
+
+			if let Some(key) = shapes.keys().find(|k| !matches!(k.as_str(), "slab" | "stairs")) {
+				return Err(format!("{block_path}: unknown key `{key}`"));
+			}
+
+			let shape = |key : &str| shapes.get(key).map(|b| {
+				let shape_path = format!("{block_path}.{key}");
+				parse_block(b.as_str().ok_or_else(|| format!("{shape_path}: expected a block string"))?, &shape_path)
+			}).transpose();
+
+			smoothing.variants.push((parse_block(block, &block_path)?, Variants {
+				slab: shape("slab")?,
+				stairs: shape("stairs")?
+			}));
+		}
+	}
+
+	Ok(smoothing)
+}
+
+/// A tree is a species name (or "none"), or a table with `species` and optional `height` and `leaves`.
+fn parse_tree(value : &Value, path : &str) -> Result<Option<TreeRule>, String> {
+	let species = |name : &Value, species_path : &str| -> Result<Option<Species>, String> {
+		let expected = || {
+			let names : Vec<&str> = Species::ALL.iter().map(|s| s.name()).collect();
+			format!("{species_path}: expected one of {} or \"none\"", names.join(", "))
+		};
+		match name.as_str().ok_or_else(expected)? {
+			"none" => Ok(None),
+			name => Species::from_name(name).map(Some).ok_or_else(expected)
+		}
+	};
+
+	let table = match value {
+		Value::String(_) => return Ok(species(value, path)?.map(|species| TreeRule {
+			species,
+			height: species.default_height(),
+			leaves: true
+		})),
+		Value::Table(table) => table,
+		_ => return Err(format!("{path}: expected a species name or a table"))
+	};
+
+	if let Some(key) = table.keys().find(|k| !matches!(k.as_str(), "species" | "height" | "leaves")) {
+		return Err(format!("{path}: unknown key `{key}`"));
+	}
+
+	let species_path = format!("{path}.species");
+	let Some(species) = species(table.get("species").ok_or_else(|| format!("{path}: missing key `species`"))?, &species_path)? else {
+		return Ok(None);
+	};
+
+	let height = match table.get("height") {
+		Some(h) => h.as_float().or(h.as_integer().map(|h| h as f64)).filter(|h| *h > 0.0)
+			.ok_or_else(|| format!("{path}.height: expected a positive number of Skyrim Units"))? as f32,
+		None => species.default_height()
+	};
+
+	let leaves = match table.get("leaves") {
+		Some(l) => l.as_bool().ok_or_else(|| format!("{path}.leaves: expected true or false"))?,
+		None => true
+	};
+
+	Ok(Some(TreeRule {
+		species,
+		height,
+		leaves
+	}))
+}
+
+/// An array of tables (`[[key]]`), each with a `match` substring and the `known_keys` that `parse` reads from the rest of it.
+/// The substrings are lowercased, to match lowercased editor IDs and names against.
+fn parse_patterns<T>(key : &str, value : &Value, known_keys : &[&str], mut parse : impl FnMut(Table, &str) -> Result<T, String>) -> Result<Vec<(String, T)>, String> {
+	let patterns = value.as_array().ok_or_else(|| format!("{key}: expected an array of tables ([[{key}]])"))?;
+
+	patterns.iter().enumerate().map(|(i, pattern)| {
+		let path = format!("{key}[{i}]");
+		let mut pattern = pattern.as_table().ok_or_else(|| format!("{path}: expected a table"))?.clone();
+
+		if let Some(key) = pattern.keys().find(|k| k.as_str() != "match" && !known_keys.contains(&k.as_str())) {
+			return Err(format!("{path}: unknown key `{key}`"));
+		}
+		let substring = pattern.remove("match").ok_or_else(|| format!("{path}: missing key `match`"))?;
+		let substring = substring.as_str().ok_or_else(|| format!("{path}.match: expected a string"))?;
+		if substring.is_empty() {
+			return Err(format!("{path}.match: must not be empty"));
+		}
+
+		Ok((substring.to_lowercase(), parse(pattern, &path)?))
+	}).collect()
+}
+
+fn parse_rule_table(value : &Value, path : &str) -> Result<HashMap<String, BlockRule>, String> {
+	let table = value.as_table().ok_or_else(|| format!("{path}: expected a table"))?;
+
+	table.iter().map(|(key, rule)| {
+		Ok((key.to_lowercase(), parse_rule(rule, &format!("{path}.{key}"))?))
+	}).collect()
+}
+
+/// A rule is either a block, an array of weighted blocks, or a table with `block`/`blocks` and optional `altitude` overrides.
+fn parse_rule(value : &Value, path : &str) -> Result<BlockRule, String> {
+	let Value::Table(table) = value else {
+		return Ok(BlockRule {
+			choices: parse_choices(value, path)?,
+			altitude: Vec::new()
+		});
+	};
+
+	let choices = parse_choices_table(table, path, &["altitude"])?;
+
+	let mut altitude = Vec::new();
+	if let Some(overrides) = table.get("altitude") {
+		let overrides = overrides.as_array().ok_or_else(|| format!("{path}.altitude: expected an array of tables"))?;
+
+		for (i, o) in overrides.iter().enumerate() {
+			let o_path = format!("{path}.altitude[{i}]");
+			let o = o.as_table().ok_or_else(|| format!("{o_path}: expected a table"))?;
+
+			let above = parse_y(o, "above", &o_path)?;
+			let below = parse_y(o, "below", &o_path)?;
+			if above.is_none() && below.is_none() {
+				return Err(format!("{o_path}: needs `above`, `below` or both"));
+			}
+
+			altitude.push(AltitudeOverride {
+				above,
+				below,
+				choices: parse_choices_table(o, &o_path, &["above", "below"])?
+			});
+		}
+	}
+
+	Ok(BlockRule {
+		choices,
+		altitude
+	})
+}
+
+fn parse_y(table : &Table, key : &str, path : &str) -> Result<Option<i32>, String> {
+	table.get(key).map(|y| {
+		y.as_integer().and_then(|y| i32::try_from(y).ok()).ok_or_else(|| format!("{path}.{key}: expected a y-level"))
+	}).transpose()
+}
+
+/// Reads exactly one of `block` or `blocks` from a table, rejecting any key that isn't that or in `other_keys`.
+fn parse_choices_table(table : &Table, path : &str, other_keys : &[&str]) -> Result<WeightedBlocks, String> {
+	if let Some(key) = table.keys().find(|k| !matches!(k.as_str(), "block" | "blocks") && !other_keys.contains(&k.as_str())) {
+		return Err(format!("{path}: unknown key `{key}`"));
+	}
+
+	match (table.get("block"), table.get("blocks")) {
+		(Some(block), None) => {
+			let block_path = format!("{path}.block");
+			let block = block.as_str().ok_or_else(|| format!("{block_path}: expected a block string"))?;
+			Ok(WeightedBlocks {
+				blocks: vec![(parse_block(block, &block_path)?, 1)],
+				total_weight: 1
+			})
+		},
+		(None, Some(blocks)) => parse_choices(blocks, &format!("{path}.blocks")),
+		(Some(_), Some(_)) => Err(format!("{path}: has both `block` and `blocks`")),
+		(None, None) => Err(format!("{path}: needs `block` or `blocks`"))
+	}
+}
+
+/// Either a single block string, or an array of block strings and `{ block = "...", weight = 2 }` tables.
+fn parse_choices(value : &Value, path : &str) -> Result<WeightedBlocks, String> {
+	let blocks = match value {
+		Value::String(block) => vec![(parse_block(block, path)?, 1)],
+		Value::Array(entries) => {
+			if entries.is_empty() {
+				return Err(format!("{path}: must have at least one block"));
+			}
+
+			entries.iter().enumerate().map(|(i, entry)| {
+				let entry_path = format!("{path}[{i}]");
+				match entry {
+					Value::String(block) => Ok((parse_block(block, &entry_path)?, 1)),
+					Value::Table(t) => {
+						if let Some(key) = t.keys().find(|k| !matches!(k.as_str(), "block" | "weight")) {
+							return Err(format!("{entry_path}: unknown key `{key}`"));
+						}
+
+						let block = t.get("block").ok_or_else(|| format!("{entry_path}: missing key `block`"))?
+							.as_str().ok_or_else(|| format!("{entry_path}.block: expected a block string"))?;
+
+						let weight = match t.get("weight") {
+							Some(w) => w.as_integer().and_then(|w| u32::try_from(w).ok()).filter(|w| *w > 0)
+								.ok_or_else(|| format!("{entry_path}.weight: expected a positive integer"))?,
+							None => 1
+						};
+
+						Ok((parse_block(block, &format!("{entry_path}.block"))?, weight))
+					},
+					_ => Err(format!("{entry_path}: expected a block string or a table"))
+				}
+			}).collect::<Result<Vec<_>, String>>()?
+		},
+		_ => return Err(format!("{path}: expected a block string, an array of blocks or a table"))
+	};
+
+	let total_weight = blocks.iter().try_fold(0u32, |total, (_, w)| total.checked_add(*w)).ok_or_else(|| format!("{path}: weights are too large"))?;
+
+	Ok(WeightedBlocks {
+		blocks,
+		total_weight
+	})
+}
+
+/// A biome ID, e.g. `minecraft:snowy_taiga`. Biomes without a namespace are assumed to be `minecraft:`.
+fn parse_biome(value : &Value, path : &str) -> Result<String, String> {
+	let s = value.as_str().ok_or_else(|| format!("{path}: expected a biome ID, like \"minecraft:plains\""))?;
+
+	parse_id(s, path, "biome")
+}
+
+/// A namespaced ID like Minecraft's resource locations, with `minecraft:` added if there's no namespace.
+fn parse_id(s : &str, path : &str, what : &str) -> Result<String, String> {
+	let valid_char = |c : char| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.' | '/');
+
+	let (namespace, id) = s.split_once(':').unwrap_or(("minecraft", s));
+	if namespace.is_empty() || id.is_empty() || !namespace.chars().all(valid_char) || !id.chars().all(valid_char) {
+		return Err(format!("{path}: `{s}` is not a valid {what} ID"));
+	}
+	Ok(format!("{namespace}:{id}"))
+}
+
+/// Parses a block state in Minecraft's command syntax, e.g. `minecraft:oak_stairs[facing=north,half=bottom]`.
+/// Blocks without a namespace are assumed to be `minecraft:`.
+pub fn parse_block(s : &str, path : &str) -> Result<Block, String> {
+	let (name, properties) = match s.split_once('[') {
+		Some((name, rest)) => {
+			let props = rest.strip_suffix(']').ok_or_else(|| format!("{path}: `{s}` is missing a closing `]`"))?;
+			(name, Some(props))
+		},
+		None => (s, None)
+	};
+
+	let name = parse_id(name, path, "block")?;
+
+	let mut property_map = HashMap::new();
+	if let Some(props) = properties {
+		for prop in props.split(',').filter(|p| !p.trim().is_empty()) {
+			let (k, v) = prop.split_once('=').ok_or_else(|| format!("{path}: property `{prop}` of `{s}` should look like `key=value`"))?;
+			let (k, v) = (k.trim(), v.trim());
+			if k.is_empty() || v.is_empty() {
+				return Err(format!("{path}: property `{prop}` of `{s}` should look like `key=value`"));
+			}
+			property_map.insert(k.into(), v.into());
+		}
+	}
+
+	Ok(Block {
+		name,
+		properties: property_map
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::esm::WeatherChances;
+
+	/// The smallest mapping there is, with `extra` added on.
+	fn mapping(extra : &str) -> Result<Mapping, Error> {
+		Mapping::parse(&format!("underground = \"stone\"\nfallback = \"grass_block\"\ndefault_water = \"water\"\n{extra}"), "test.toml")
+	}
+
+	#[test]
+	fn shipped_mappings_parse() {
+		for (contents, name) in [(SKYRIM_MAPPING, "skyrim"), (OBLIVION_MAPPING, "oblivion"), (FALLOUT_MAPPING, "fallout"), (MORROWIND_MAPPING, "morrowind")] {
+			if let Err(e) = Mapping::parse(contents, name) {
+				panic!("{e}");
+			}
+		}
+	}
+
+	#[test]
+	fn errors_name_the_key() {
+		let error = |extra : &str| mapping(extra).unwrap_err().to_string();
+
+		let bad_block = error("[land_textures]\nSnow = \"minecraft:Snow Block\"");
+		assert!(bad_block.contains("test.toml: land_textures.Snow: `minecraft:Snow Block` is not a valid block ID"), "{bad_block}");
+
+		let bad_weight = error("[land_textures]\nDirt = [\"dirt\", { block = \"coarse_dirt\", weight = 0 }]");
+		assert!(bad_weight.contains("land_textures.Dirt[1].weight: expected a positive integer"), "{bad_weight}");
+
+		let bad_altitude = error("[land_textures.Rock]\nblock = \"stone\"\naltitude = [{ above = \"high\", block = \"snow_block\" }]");
+		assert!(bad_altitude.contains("land_textures.Rock.altitude[0].above: expected a y-level"), "{bad_altitude}");
+	}
+
+	#[test]
+	fn weighted_choice() {
+		let mapping = mapping("[water]\nMarsh = [{ block = \"grass_block\", weight = 3 }, \"dirt\"]").unwrap_or_else(|e| panic!("{e}"));
+		let marsh = mapping.water(Some("Marsh"));
+
+		let mut grass = 0;
+		for x in 0..100 {
+			for z in 0..100 {
+				let block = marsh.pick(x, 64, z);
+				// The same place always gets the same block:
+				assert_eq!(block, marsh.pick(x, 64, z));
+				if block.name == "minecraft:grass_block" {
+					grass += 1;
+				}
+			}
+		}
+		// 3 in 4, give or take:
+		assert!((7000..8000).contains(&grass), "{grass} of 10000");
+	}
+
+	#[test]
+	fn altitude_overrides() {
+		let mapping = mapping("[water.Swamp]\nblock = \"water\"\naltitude = [{ above = 60, below = 70, block = \"ice\" }, { above = 100, block = \"snow_block\" }]").unwrap_or_else(|e| panic!("{e}"));
+		let swamp = mapping.water(Some("Swamp"));
+
+		// `above` takes the y-level itself, `below` doesn't:
+		let names : Vec<&str> = [59, 60, 69, 70, 99, 100].map(|y| swamp.pick(0, y, 0).name.as_str()).to_vec();
+		assert_eq!(names, ["minecraft:water", "minecraft:ice", "minecraft:ice", "minecraft:water", "minecraft:water", "minecraft:snow_block"]);
+	}
+
+	#[test]
+	fn biome_fallbacks() {
+		let mapping = mapping("default_biome = \"forest\"\n[regions]\nReachRegion = \"badlands\"\n\
+			[[biome_patterns]]\nmatch = \"snow\"\nbiome = \"snowy_plains\"\n\
+			[[biome_patterns]]\nmatch = \"tundra\"\nbiome = \"savanna\"\n\
+			[[biome_patterns]]\nmatch = \"rain\"\nbiome = \"swamp\"").unwrap_or_else(|e| panic!("{e}"));
+
+		let chances = |edid : &str, weathers : &[(u32, u32)]| WeatherChances {
+			edid: edid.into(),
+			weathers: weathers.to_vec()
+		};
+		let records = WorldRecords {
+			regions: HashMap::from([
+				(1, chances("ReachRegion", &[])),
+				(2, chances("SnowyRegion", &[])),
+				(3, chances("PlainRegion", &[(10, 30), (11, 60)])),
+				(4, chances("NoWeatherRegion", &[])),
+			]),
+			climates: HashMap::from([
+				(20, chances("TundraClimate", &[(12, 100)])),
+				(21, chances("SkyrimClimate", &[(12, 100)])),
+			]),
+			weathers: HashMap::from([(10, "ClearWeather".into()), (11, "RainyWeather".into()), (12, "SnowWeather".into())]),
+			..WorldRecords::default()
+		};
+		let biome = |regions : &[u32], climate : Option<u32>| {
+			let cell = Cell {
+				x: 0,
+				y: 0,
+				water_height: None,
+				water_type: None,
+				persistent: false,
+				regions: regions.to_vec(),
+				climate
+			};
+			mapping.biome(&cell, &records).to_string()
+		};
+
+		// A region by its editor ID, before any pattern:
+		assert_eq!(biome(&[2, 1], Some(20)), "minecraft:badlands");
+		// Then a pattern matching a region:
+		assert_eq!(biome(&[2], Some(20)), "minecraft:snowy_plains");
+		// Then the climate:
+		assert_eq!(biome(&[3], Some(20)), "minecraft:savanna");
+		// Then the region's most likely weather, which goes before the climate's:
+		assert_eq!(biome(&[3], Some(21)), "minecraft:swamp");
+		// The climate's weather, for regions without any:
+		assert_eq!(biome(&[4], Some(21)), "minecraft:snowy_plains");
+		// And the default when nothing matches:
+		assert_eq!(biome(&[4], None), "minecraft:forest");
+		assert_eq!(biome(&[], None), "minecraft:forest");
+	}
+}
@@ -0,0 +1,163 @@
+use std::{collections::{BTreeSet, HashMap}, path::Path, sync::{Arc, Mutex}};
+
+use crate::{bsa::{self, DataFiles}, error::{Error, Result}, esm::Reference, mapping::BlockRule, nif::{self, Triangle}, regions::RegionWriter, world_gen::Scale};
+
+/// Keeps the sampling of huge (or broken) triangles from taking forever.
+const MAX_SUBDIVISIONS : usize = 1024;
+
+/// Finds the meshes placed objects use in a game's Data folder, as loose files or in its archives,
+/// and keeps each one it has read so it's only read once.
+#[derive(Debug)]
+pub struct Meshes {
+	data : DataFiles,
+	/// By path in the archives. `None` for meshes we couldn't find or read.
+	cache : Mutex<HashMap<String, Option<Arc<Vec<Triangle>>>>>,
+	/// The meshes we couldn't read, and why, until they're taken.
+	failed : Mutex<Vec<(String, Error)>>,
+}
+
+impl Meshes {
+	/// Opens the archives in the Data folder. See [`DataFiles::open`].
+	pub fn open(data_folder : &Path) -> Result<Meshes> {
+		Ok(Self::new(DataFiles::open(data_folder)?))
+	}
+
+	pub fn new(data : DataFiles) -> Meshes {
+		Meshes {
+			data,
+			cache: Mutex::new(HashMap::new()),
+			failed: Mutex::new(Vec::new())
+		}
+	}
+
+	pub fn data(&self) -> &DataFiles {
+		&self.data
+	}
+
+	/// The meshes that couldn't be read since the last call, by path in the archives, with what went wrong.
+	pub fn take_failed(&self) -> Vec<(String, Error)> {
+		let mut failed = std::mem::take(&mut *self.failed.lock().unwrap());
+		// Two threads can both fail to read the same mesh:
+		failed.sort_by(|a, b| a.0.cmp(&b.0));
+		failed.dedup_by(|a, b| a.0 == b.0);
+		failed
+	}
+
+	/// The triangles of a mesh, by its path from the `meshes` folder (like a MODL field has it).
+	/// `None` if it isn't anywhere, or can't be read. Meshes that can't be read are noted the first time, see [`Meshes::take_failed`].
+	pub fn get(&self, model : &str) -> Option<Arc<Vec<Triangle>>> {
+		let path = bsa::normalize(&format!("meshes\\{model}"));
+		if let Some(mesh) = self.cache.lock().unwrap().get(&path) {
+			return mesh.clone();
+		}
+
+		// Not holding the lock while we read, so other threads can read other meshes. Two threads may read the same one, which is fine.
+		let mesh = match self.data.read(&path).and_then(|data| data.map(|d| nif::read_triangles(&d)).transpose()) {
+			Ok(triangles) => triangles.map(Arc::new),
+			Err(e) => {
+				self.failed.lock().unwrap().push((path.clone(), e));
+				None
+			}
+		};
+
+		self.cache.lock().unwrap().insert(path, mesh.clone());
+		mesh
+	}
+}
+
+/// Turns a placed object's mesh into blocks wherever its surface is, picked from `rule`.
+/// Blocks go over whatever is there already.
+pub fn place_object(reference : &Reference, triangles : &[Triangle], rule : &BlockRule, scale : Scale, writer : &RegionWriter) -> Result<()> {
+	let rotation = reference_rotation(reference.rotation);
+
+	// Into blocks, where Skyrim's Y is Minecraft's Z. Shifted up by one like the terrain:
+	let to_blocks = |v : [f32; 3]| -> [f64; 3] {
+		let v = v.map(|c| (c * reference.scale) as f64);
+		let world = [0, 1, 2].map(|i| rotation[i][0] * v[0] + rotation[i][1] * v[1] + rotation[i][2] * v[2] + reference.position[i] as f64);
+		[world[0] / scale.horizontal, world[2] / scale.vertical + 1.0, world[1] / scale.horizontal]
+	};
+
+	let mut blocks = BTreeSet::new();
+	for triangle in triangles {
+		let [a, b, c] = triangle.map(to_blocks);
+		let edge = |p : [f64; 3], q : [f64; 3]| ((p[0] - q[0]).powi(2) + (p[1] - q[1]).powi(2) + (p[2] - q[2]).powi(2)).sqrt();
+
+		// At most half a block between samples, so the surface has no gaps:
+		let longest = edge(a, b).max(edge(b, c)).max(edge(c, a));
+		if !longest.is_finite() {
+			continue;
+		}
+		let steps = ((longest * 2.0).ceil() as usize).clamp(1, MAX_SUBDIVISIONS);
+
+		for i in 0..=steps {
+			for j in 0..=steps - i {
+				let (u, v) = (i as f64 / steps as f64, j as f64 / steps as f64);
+				let point = [0, 1, 2].map(|k| (a[k] + (b[k] - a[k]) * u + (c[k] - a[k]) * v).floor() as i32);
+				blocks.insert(point);
+			}
+		}
+	}
+
+	writer.draw_blocks(blocks.into_iter().map(|[x, y, z]| (x, y, z, rule.pick(x, y, z))), true)?;
+	Ok(())
+}
+
+/// The rotation matrix of a placed object. Its angles turn clockwise, applied around Z first, then Y, then X.
+fn reference_rotation([x, y, z] : [f32; 3]) -> [[f64; 3]; 3] {
+	let (sx, cx) = (-x as f64).sin_cos();
+	let (sy, cy) = (-y as f64).sin_cos();
+	let (sz, cz) = (-z as f64).sin_cos();
+
+	// Rx * Ry * Rz:
+	[
+		[cy * cz, -cy * sz, sy],
+		[sx * sy * cz + cx * sz, -sx * sy * sz + cx * cz, -sx * cy],
+		[-cx * sy * cz + sx * sz, cx * sy * sz + sx * cz, cx * cy],
+	]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{mapping::Mapping, test_util::test_dir, world_gen::{MAX_Y, MIN_Y}};
+
+	#[test]
+	fn turned_objects() {
+		let dir = test_dir("turned-objects");
+		let writer = RegionWriter::new(&dir, Scale::default());
+		let mapping = Mapping::parse("underground = \"stone\"\nfallback = \"stone\"\ndefault_water = \"water\"", "test.toml").unwrap();
+
+		// From the middle of a block to the middles of the blocks east and north of it, turned a quarter clockwise:
+		let triangle = [[32.0, 32.0, 32.0], [96.0, 32.0, 32.0], [32.0, 96.0, 32.0]];
+		let reference = Reference {
+			form_id: 0,
+			base: 0,
+			position: [320.0, 320.0, 0.0],
+			rotation: [0.0, 0.0, std::f32::consts::FRAC_PI_2],
+			scale: 1.0,
+			persistent: false
+		};
+		place_object(&reference, &[triangle], &mapping.fallback, Scale::default(), &writer).unwrap();
+
+		let blocks = writer.edit((0, 0), |chunks| {
+			let mut blocks = BTreeSet::new();
+			for chunk in chunks.values() {
+				for y in MIN_Y + 1..MAX_Y {
+					for z in 0..16 {
+						for x in 0..16 {
+							if chunk.block_at(x, y, z).is_some_and(|b| b.name != "minecraft:air") {
+								blocks.insert((chunk.x_pos * 16 + x as i32, y, chunk.z_pos * 16 + z as i32));
+							}
+						}
+					}
+				}
+			}
+			blocks
+		}).unwrap();
+
+		// Unturned, that would be (5, 5), (6, 5) and (5, 6). East turns to south (-Y, so -Z in Minecraft), and north to east.
+		// Shifted up by one like the terrain:
+		assert_eq!(blocks, BTreeSet::from([(5, 1, 3), (5, 1, 4), (6, 1, 4)]));
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
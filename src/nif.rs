@@ -0,0 +1,552 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use crate::error::{Error, Location, Result};
+
+/// A triangle's corners, in the model's space.
+pub type Triangle = [[f32; 3]; 3];
+
+/// 20.2.0.7, which Fallout 3, New Vegas, Skyrim (both editions) and Fallout 4 all use.
+const VERSION : u32 = 0x14020007;
+
+/// Nodes, which only matter for their transform and children.
+const NODE_TYPES : [&str; 14] = ["NiNode", "BSFadeNode", "BSLeafAnimNode", "BSTreeNode", "BSMultiBoundNode", "BSOrderedNode", "BSValueNode", "BSBlastNode",
+    "BSDamageStage", "BSDebrisNode", "BSRangeNode", "NiBillboardNode", "NiSwitchNode", "NiLODNode"];
+
+/// Shapes that keep their geometry in a NiTriShapeData or NiTriStripsData block.
+const GEOMETRY_TYPES : [&str; 4] = ["NiTriShape", "NiTriStrips", "BSLODTriShape", "BSSegmentedTriShape"];
+
+/// Shapes (from Skyrim Special Edition on) that keep their geometry in the block itself.
+const TRI_SHAPE_TYPES : [&str; 3] = ["BSTriShape", "BSSubIndexTriShape", "BSMeshLODTriShape"];
+
+/// NiAVObject flag for objects that aren't drawn.
+const HIDDEN : u32 = 0x1;
+
+/// Nested deeper than this, the scene graph is more likely broken than that deep.
+const MAX_DEPTH : usize = 64;
+
+/// A shape's vertices, and its triangles as indices into them.
+#[derive(Debug, Default)]
+struct Geometry {
+    vertices : Vec<[f32; 3]>,
+    faces : Vec<[u16; 3]>,
+}
+
+/// Scale, then rotate, then move.
+#[derive(Debug, Clone, Copy)]
+struct Transform {
+    rotation : [[f32; 3]; 3],
+    translation : [f32; 3],
+    scale : f32,
+}
+
+impl Transform {
+    const IDENTITY : Transform = Transform {
+        rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        translation: [0.0; 3],
+        scale: 1.0
+    };
+
+    fn apply(&self, v : [f32; 3]) -> [f32; 3] {
+        let r = &self.rotation;
+        [0, 1, 2].map(|i| (r[i][0] * v[0] + r[i][1] * v[1] + r[i][2] * v[2]) * self.scale + self.translation[i])
+    }
+
+    /// A child's transform, from its parent's space to the model's.
+    fn then(&self, child : &Transform) -> Transform {
+        let (a, b) = (&self.rotation, &child.rotation);
+        Transform {
+            rotation: [0, 1, 2].map(|i| [0, 1, 2].map(|j| a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j])),
+            translation: self.apply(child.translation),
+            scale: self.scale * child.scale
+        }
+    }
+}
+
+/// The parts of a NIF's header we need to find and read its blocks.
+#[derive(Debug)]
+struct Header {
+    /// Which Bethesda game the file is from: 34 for Fallout 3 and New Vegas, 83 for Skyrim, 100 for Special Edition and 130 for Fallout 4.
+    bs_version : u32,
+    block_types : Vec<String>,
+    /// Index into `block_types`, by block.
+    block_type_index : Vec<u16>,
+    /// Where each block starts and ends in the file.
+    blocks : Vec<(u64, u64)>,
+    strings : Vec<String>,
+}
+
+impl Header {
+    fn block_type(&self, block : usize) -> &str {
+        self.block_types.get(self.block_type_index[block] as usize).map(|t| t.as_str()).unwrap_or_default()
+    }
+}
+
+/// Reads every visible triangle in a NIF file, moved into place by the nodes above it.
+///
+/// Only the 20.2.0.7 files of Fallout 3, New Vegas, Skyrim and Fallout 4 can be read, since those give each block's size and unknown ones can be skipped.
+/// Collision, particles and skinned meshes are left out.
+pub fn read_triangles(data : &[u8]) -> Result<Vec<Triangle>> {
+    read_nif(data).map_err(|e| e.truncated_file("NIF file"))
+}
+
+fn read_nif(data : &[u8]) -> Result<Vec<Triangle>> {
+    let mut r = Cursor::new(data);
+    let header = read_header(&mut r)?;
+
+    // The footer lists the root blocks:
+    r.seek(SeekFrom::Start(header.blocks.last().map(|(_, end)| *end).unwrap_or(r.position())))?;
+    let root_count = read_u32(&mut r)?;
+    let roots = (0..root_count).map(|_| read_ref(&mut r)).collect::<Result<Vec<_>>>()?;
+
+    let mut triangles = Vec::new();
+    for root in roots.into_iter().flatten() {
+        read_block(&mut r, &header, root, &Transform::IDENTITY, 0, &mut triangles)?;
+    }
+    Ok(triangles)
+}
+
+fn read_header(r : &mut Cursor<&[u8]>) -> Result<Header> {
+    let data = *r.get_ref();
+    let line_end = data.iter().take(64).position(|b| *b == b'\n').ok_or_else(|| Error::invalid_data("Not a NIF file."))?;
+    if !data.starts_with(b"Gamebryo File Format") && !data.starts_with(b"NetImmerse File Format") {
+        return Err(Error::invalid_data("Not a NIF file."));
+    }
+    r.set_position(line_end as u64 + 1);
+
+    let version = read_u32(r)?;
+    if version != VERSION {
+        let [a, b, c, d] = version.to_be_bytes();
+        return Err(Error::invalid_data(format!("NIF version {a}.{b}.{c}.{d} isn't supported, only 20.2.0.7 (Fallout 3, New Vegas, Skyrim and Fallout 4).")));
+    }
+
+    let mut endian = [0; 1];
+    r.read_exact(&mut endian)?;
+    if endian[0] != 1 {
+        return Err(Error::invalid_data("Big-endian NIF files aren't supported."));
+    }
+
+    let user_version = read_u32(r)?;
+    let block_count = read_u32(r)? as usize;
+
+    let mut bs_version = 0;
+    if user_version >= 3 {
+        bs_version = read_u32(r)?;
+        if bs_version > 130 {
+            return Err(Error::invalid_data(format!("NIF files from after Fallout 4 (BS version {bs_version}) aren't supported.")));
+        }
+
+        // Author, process script, export script:
+        for _ in 0..3 {
+            read_short_string(r)?;
+        }
+        if bs_version >= 103 {
+            // Max's file path:
+            read_short_string(r)?;
+        }
+    }
+
+    let type_count = read_u16(r)?;
+    let block_types = (0..type_count).map(|_| read_sized_string(r)).collect::<Result<Vec<_>>>()?;
+
+    // The top bit is for PhysX blocks, which we don't read anyway:
+    let block_type_index = (0..block_count).map(|_| read_u16(r).map(|i| i & 0x7FFF)).collect::<Result<Vec<_>>>()?;
+    let sizes = (0..block_count).map(|_| read_u32(r)).collect::<Result<Vec<_>>>()?;
+
+    let string_count = read_u32(r)?;
+    // The longest string's length:
+    read_u32(r)?;
+    let strings = (0..string_count).map(|_| read_sized_string(r)).collect::<Result<Vec<_>>>()?;
+
+    let group_count = read_u32(r)?;
+    r.seek(SeekFrom::Current(4 * group_count as i64))?;
+
+    let mut start = r.position();
+    let mut blocks = Vec::with_capacity(block_count);
+    for size in sizes {
+        blocks.push((start, start + size as u64));
+        start += size as u64;
+    }
+    if start > data.len() as u64 {
+        return Err(Error::invalid_data(format!("NIF blocks end at {start}, past the end of the file ({} bytes).", data.len())));
+    }
+
+    Ok(Header {
+        bs_version,
+        block_types,
+        block_type_index,
+        blocks,
+        strings
+    })
+}
+
+/// Reads a node and everything below it, or a shape's triangles. Skips anything else.
+fn read_block(r : &mut Cursor<&[u8]>, header : &Header, block : usize, parent : &Transform, depth : usize, triangles : &mut Vec<Triangle>) -> Result<()> {
+    let Some((start, end)) = header.blocks.get(block).copied() else {
+        return Err(Error::invalid_data(format!("NIF block {block} doesn't exist.")));
+    };
+    if depth > MAX_DEPTH {
+        return Err(Error::invalid_data(format!("NIF nodes are nested more than {MAX_DEPTH} deep.")));
+    }
+
+    let ty = header.block_type(block);
+    if !NODE_TYPES.contains(&ty) && !GEOMETRY_TYPES.contains(&ty) && !TRI_SHAPE_TYPES.contains(&ty) {
+        return Ok(());
+    }
+
+    r.set_position(start);
+    let object = read_av_object(r, header)?;
+    // Editor markers only show up in the Creation Kit:
+    if object.flags & HIDDEN != 0 || object.name.is_some_and(|n| n.to_lowercase().starts_with("editormarker")) {
+        return Ok(());
+    }
+    let transform = parent.then(&object.transform);
+
+    let mut shape = |geometry : Geometry| {
+        for face in geometry.faces {
+            if let [Some(a), Some(b), Some(c)] = face.map(|i| geometry.vertices.get(i as usize)) {
+                triangles.push([*a, *b, *c].map(|v| transform.apply(v)));
+            }
+        }
+    };
+
+    if NODE_TYPES.contains(&ty) {
+        let child_count = read_u32(r)?;
+        let children = (0..child_count).map(|_| read_ref(r)).collect::<Result<Vec<_>>>()?;
+
+        for child in children.into_iter().flatten() {
+            read_block(r, header, child, &transform, depth + 1, triangles)?;
+        }
+    } else if GEOMETRY_TYPES.contains(&ty) {
+        if let Some(data) = read_ref(r)? {
+            shape(read_geometry_data(r, header, data)?);
+        }
+    } else {
+        shape(read_tri_shape(r, header, end)?);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct AVObject<'a> {
+    name : Option<&'a str>,
+    flags : u32,
+    transform : Transform,
+}
+
+/// What every node and shape starts with. Leaves `r` right after it.
+fn read_av_object<'a>(r : &mut Cursor<&[u8]>, header : &'a Header) -> Result<AVObject<'a>> {
+    let name = read_ref(r)?.and_then(|i| header.strings.get(i)).map(|s| s.as_str());
+
+    let extra_data_count = read_u32(r)?;
+    // The extra data, and the controller:
+    r.seek(SeekFrom::Current(4 * extra_data_count as i64 + 4))?;
+
+    let flags = if header.bs_version > 26 { read_u32(r)? } else { read_u16(r)? as u32 };
+
+    let translation = read_vec3(r)?;
+    let rotation = [read_vec3(r)?, read_vec3(r)?, read_vec3(r)?];
+    let scale = read_f32(r)?;
+
+    if header.bs_version <= 34 {
+        let property_count = read_u32(r)?;
+        r.seek(SeekFrom::Current(4 * property_count as i64))?;
+    }
+    // The collision object:
+    r.seek(SeekFrom::Current(4))?;
+
+    Ok(AVObject {
+        name,
+        flags,
+        transform: Transform { rotation, translation, scale }
+    })
+}
+
+/// The vertices and triangles of a NiTriShapeData or NiTriStripsData block.
+fn read_geometry_data(r : &mut Cursor<&[u8]>, header : &Header, block : usize) -> Result<Geometry> {
+    let Some((start, end)) = header.blocks.get(block).copied() else {
+        return Err(Error::invalid_data(format!("NIF block {block} doesn't exist.")));
+    };
+    let ty = header.block_type(block);
+    if !matches!(ty, "NiTriShapeData" | "NiTriStripsData") {
+        return Ok(Geometry::default());
+    }
+
+    r.set_position(start);
+    // The group ID:
+    read_u32(r)?;
+    let vertex_count = read_u16(r)? as i64;
+    // Keep and compress flags:
+    r.seek(SeekFrom::Current(2))?;
+
+    let vertices = if read_bool(r)? {
+        (0..vertex_count).map(|_| read_vec3(r)).collect::<Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
+
+    // Bethesda's flags: 0x1 for UVs, 0x1000 for tangents and bitangents.
+    let vector_flags = read_u16(r)?;
+    if read_bool(r)? {
+        let tangents = if vector_flags & 0x1000 != 0 { 2 } else { 0 };
+        r.seek(SeekFrom::Current(12 * vertex_count * (1 + tangents)))?;
+    }
+    // The bounding sphere:
+    r.seek(SeekFrom::Current(16))?;
+    if read_bool(r)? {
+        r.seek(SeekFrom::Current(16 * vertex_count))?;
+    }
+    r.seek(SeekFrom::Current(8 * vertex_count * (vector_flags & 1) as i64))?;
+    // Consistency flags and additional data:
+    r.seek(SeekFrom::Current(6))?;
+
+    let triangle_count = read_u16(r)?;
+    let mut faces = Vec::with_capacity(triangle_count as usize);
+
+    if ty == "NiTriShapeData" {
+        // How many triangle points there are (three times the triangles):
+        read_u32(r)?;
+        if read_bool(r)? {
+            for _ in 0..triangle_count {
+                faces.push([read_u16(r)?, read_u16(r)?, read_u16(r)?]);
+            }
+        }
+
+        let match_group_count = read_u16(r)?;
+        for _ in 0..match_group_count {
+            let count = read_u16(r)?;
+            r.seek(SeekFrom::Current(2 * count as i64))?;
+        }
+    } else {
+        let strip_count = read_u16(r)?;
+        let lengths = (0..strip_count).map(|_| read_u16(r)).collect::<Result<Vec<_>>>()?;
+        if read_bool(r)? {
+            for length in lengths {
+                let points = (0..length).map(|_| read_u16(r)).collect::<Result<Vec<_>>>()?;
+                // Every other triangle of a strip winds the other way, which doesn't matter to us:
+                faces.extend(points.windows(3).map(|w| [w[0], w[1], w[2]]).filter(|[a, b, c]| a != b && b != c && a != c));
+            }
+        }
+    }
+
+    // We skip a lot on the way here, so make sure we ended up where the block does:
+    if r.position() != end {
+        return Err(Error::invalid_data(format!("{ty} block {block} is {} bytes, but we read {}.", end - start, r.position() - start)));
+    }
+    Ok(Geometry { vertices, faces })
+}
+
+/// The vertices and triangles of a BSTriShape, which `r` is right after the NiAVObject part of.
+fn read_tri_shape(r : &mut Cursor<&[u8]>, header : &Header, end : u64) -> Result<Geometry> {
+    // The bounding sphere, and the skin, shader and alpha properties:
+    r.seek(SeekFrom::Current(28))?;
+
+    let mut desc = [0; 8];
+    r.read_exact(&mut desc)?;
+    let desc = u64::from_le_bytes(desc);
+
+    let triangle_count = if header.bs_version >= 130 { read_u32(r)? } else { read_u16(r)? as u32 };
+    let vertex_count = read_u16(r)?;
+    let data_size = read_u32(r)?;
+
+    // Skinned shapes keep their vertices in the skin partition instead:
+    let stride = (desc & 0xF) * 4;
+    let attributes = desc >> 44;
+    if data_size == 0 || attributes & 0x1 == 0 {
+        return Ok(Geometry::default());
+    }
+
+    if data_size as u64 != stride * vertex_count as u64 + 6 * triangle_count as u64 || r.position() + data_size as u64 > end {
+        return Err(Error::invalid_data(format!("BSTriShape data is {data_size} bytes, which doesn't fit {vertex_count} vertices of {stride} bytes and {triangle_count} triangles.")));
+    }
+
+    // Special Edition's are always full precision, Fallout 4's only with that flag:
+    let full_precision = header.bs_version < 130 || attributes & 0x400 != 0;
+
+    let mut vertices = Vec::with_capacity(vertex_count as usize);
+    for _ in 0..vertex_count {
+        let start = r.position();
+        vertices.push(if full_precision {
+            read_vec3(r)?
+        } else {
+            [read_u16(r)?, read_u16(r)?, read_u16(r)?].map(half_to_f32)
+        });
+        r.set_position(start + stride);
+    }
+
+    let faces = (0..triangle_count).map(|_| Ok([read_u16(r)?, read_u16(r)?, read_u16(r)?])).collect::<Result<Vec<_>>>()?;
+    Ok(Geometry { vertices, faces })
+}
+
+fn half_to_f32(half : u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1F) as i32;
+    let mantissa = (half & 0x3FF) as f32;
+
+    sign * match exponent {
+        0 => mantissa / 1024.0 * 2f32.powi(-14),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15)
+    }
+}
+
+fn read_u16(r : &mut impl Read) -> Result<u16> {
+    let mut buf = [0; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(r : &mut impl Read) -> Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f32(r : &mut impl Read) -> Result<f32> {
+    Ok(f32::from_bits(read_u32(r)?))
+}
+
+fn read_vec3(r : &mut impl Read) -> Result<[f32; 3]> {
+    Ok([read_f32(r)?, read_f32(r)?, read_f32(r)?])
+}
+
+fn read_bool(r : &mut impl Read) -> Result<bool> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0] != 0)
+}
+
+/// A reference to another block (or a string, by index), where -1 is none.
+fn read_ref(r : &mut impl Read) -> Result<Option<usize>> {
+    Ok(usize::try_from(read_u32(r)? as i32).ok())
+}
+
+/// A string prefixed with its length as a u32.
+fn read_sized_string(r : &mut impl Read) -> Result<String> {
+    let length = read_u32(r)?;
+    let mut buf = Vec::new();
+    r.take(length as u64).read_to_end(&mut buf)?;
+    if buf.len() != length as usize {
+        return Err(Error::TruncatedRecord { at: Location::default() });
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// A string prefixed with its length as a u8, counting its null terminator.
+fn read_short_string(r : &mut impl Read) -> Result<()> {
+    let mut length = [0; 1];
+    r.read_exact(&mut length)?;
+    let mut buf = vec![0; length[0] as usize];
+    Ok(r.read_exact(&mut buf)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(values : &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// A 20.2.0.7 NIF with the given blocks, by type, and the first one as its root.
+    fn nif(bs_version : u32, blocks : &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut types : Vec<&str> = Vec::new();
+        for (ty, _) in blocks {
+            if !types.contains(ty) {
+                types.push(ty);
+            }
+        }
+
+        let mut out = b"Gamebryo File Format, Version 20.2.0.7\n".to_vec();
+        out.extend(VERSION.to_le_bytes());
+        out.push(1);
+        out.extend(12u32.to_le_bytes());
+        out.extend((blocks.len() as u32).to_le_bytes());
+        out.extend(bs_version.to_le_bytes());
+        // Author, process script and export script, each just a null:
+        out.extend([1, 0, 1, 0, 1, 0]);
+
+        out.extend((types.len() as u16).to_le_bytes());
+        for ty in &types {
+            out.extend((ty.len() as u32).to_le_bytes());
+            out.extend(ty.as_bytes());
+        }
+        for (ty, _) in blocks {
+            out.extend((types.iter().position(|t| t == ty).unwrap() as u16).to_le_bytes());
+        }
+        for (_, data) in blocks {
+            out.extend((data.len() as u32).to_le_bytes());
+        }
+
+        // One string, the shape's name, and no groups:
+        out.extend([1u32, 5, 5].map(u32::to_le_bytes).concat());
+        out.extend(b"Shape");
+        out.extend(0u32.to_le_bytes());
+
+        for (_, data) in blocks {
+            out.extend(data);
+        }
+        out.extend([1u32, 0].map(u32::to_le_bytes).concat());
+        out
+    }
+
+    /// A visible NiAVObject with no extra data, controller or collision, moved by `translation`.
+    fn av_object(name : i32, translation : [f32; 3]) -> Vec<u8> {
+        [name.to_le_bytes().as_slice(), &0u32.to_le_bytes(), &(-1i32).to_le_bytes(), &0u32.to_le_bytes(),
+            floats(&translation).as_slice(), &floats(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0]), &(-1i32).to_le_bytes()].concat()
+    }
+
+    fn node(translation : [f32; 3], children : &[i32]) -> Vec<u8> {
+        [av_object(-1, translation), (children.len() as u32).to_le_bytes().to_vec(), children.iter().flat_map(|c| c.to_le_bytes()).collect()].concat()
+    }
+
+    const TRIANGLE : [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [64.0, 0.0, 0.0], [0.0, 64.0, 0.0]];
+
+    #[test]
+    fn ni_tri_shape() {
+        let data = [
+            // Group ID, vertices, keep and compress flags, and the vertices themselves:
+            0u32.to_le_bytes().as_slice(), &3u16.to_le_bytes(), &[0, 0, 1], floats(&TRIANGLE.concat()).as_slice(),
+            // UVs but no normals, the bounding sphere, no vertex colors, and the UVs:
+            &1u16.to_le_bytes(), &[0], &[0; 16], &[0], &[0; 24],
+            // Consistency flags and additional data, then one triangle of three points, and no match groups:
+            &[0; 6], &1u16.to_le_bytes(), &3u32.to_le_bytes(), &[1], &[0u16, 1, 2].map(u16::to_le_bytes).concat(), &0u16.to_le_bytes(),
+        ].concat();
+
+        let file = nif(83, &[
+            ("BSFadeNode", node([100.0, 0.0, 0.0], &[1])),
+            ("NiTriShape", [av_object(0, [0.0, 0.0, 10.0]), 2i32.to_le_bytes().to_vec()].concat()),
+            ("NiTriShapeData", data),
+        ]);
+
+        assert_eq!(read_triangles(&file).unwrap(), [[[100.0, 0.0, 10.0], [164.0, 0.0, 10.0], [100.0, 64.0, 10.0]]]);
+
+        // Cut short inside the shape's data:
+        assert!(read_triangles(&file[..file.len() - 20]).is_err());
+    }
+
+    #[test]
+    fn bs_tri_shape() {
+        // 16 byte vertices (the position, and a bitangent's X), with nothing but the position in the attributes:
+        let desc = 4u64 | (1 << 44);
+        let vertices : Vec<u8> = TRIANGLE.iter().flat_map(|v| floats(&[v[0], v[1], v[2], 0.0])).collect();
+        let shape = [
+            av_object(0, [0.0, 0.0, 0.0]).as_slice(), &[0; 28], &desc.to_le_bytes(), &1u16.to_le_bytes(), &3u16.to_le_bytes(),
+            &(vertices.len() as u32 + 6).to_le_bytes(), &vertices, &[0u16, 2, 1].map(u16::to_le_bytes).concat(),
+        ].concat();
+
+        let file = nif(100, &[("NiNode", node([0.0, 0.0, 0.0], &[1])), ("BSTriShape", shape)]);
+        assert_eq!(read_triangles(&file).unwrap(), [[TRIANGLE[0], TRIANGLE[2], TRIANGLE[1]]]);
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(half_to_f32(0x0000), 0.0);
+        assert_eq!(half_to_f32(0x3C00), 1.0);
+        assert_eq!(half_to_f32(0xC000), -2.0);
+        // The smallest subnormal:
+        assert_eq!(half_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(half_to_f32(0x7C00), f32::INFINITY);
+        assert!(half_to_f32(0x7E00).is_nan());
+    }
+}
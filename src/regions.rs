@@ -0,0 +1,296 @@
+use std::{collections::{BTreeMap, HashMap}, fs::{self, OpenOptions}, path::Path, sync::{Arc, Mutex}};
+
+use crate::world_gen::{Block, Chunk, Pos, Scale, CELL_SIZE, MAX_Y, MIN_Y};
+
+/// Makes `folder` if it isn't there yet, and removes the region (.mca) files in it, so they don't mix with the ones we're about to write.
+pub fn clear_regions(folder : &Path) -> std::io::Result<()> {
+	let context = |e : std::io::Error, what : &str, path : &Path| std::io::Error::new(e.kind(), format!("Could not {what} {path:?}: {e}"));
+
+	fs::create_dir_all(folder).map_err(|e| context(e, "create", folder))?;
+	for entry in fs::read_dir(folder).map_err(|e| context(e, "read", folder))? {
+		let path = entry.map_err(|e| context(e, "read", folder))?.path();
+		if path.extension().is_some_and(|e| e == "mca") {
+			fs::remove_file(&path).map_err(|e| context(e, "remove", &path))?;
+		}
+	}
+	Ok(())
+}
+
+/// The chunks cells have drawn into a region so far.
+#[derive(Debug, Default)]
+struct PendingRegion {
+	/// How many sub-blocks that overlap this region haven't finished yet.
+	subblocks_left : usize,
+	/// By chunk position in the world.
+	chunks : HashMap<Pos, Chunk>,
+	/// Whether the chunks are on disk, and no longer in `chunks`.
+	written : bool,
+	/// What's drawn into the region after it's written, to be added to the file by `finish`.
+	late : HashMap<Pos, Chunk>,
+}
+
+/// Keeps the chunks of every region in memory while cells draw into them,
+/// and writes each region file once every sub-block that overlaps it (or its edges) is done.
+///
+/// The cells of a sub-block are right next to each other, so only the regions around the sub-blocks being read at the moment stay in memory.
+#[derive(Debug)]
+pub struct RegionWriter<'a> {
+	out_folder : &'a Path,
+	scale : Scale,
+	/// Regions stay in here once they're written, so anything still drawn into them (an object bigger than a cell, say)
+	/// is kept aside for `finish`, which adds it to just the chunks it touches.
+	regions : Mutex<HashMap<Pos, Arc<Mutex<PendingRegion>>>>,
+	/// How many times each region file was written out whole.
+	#[cfg(test)]
+	writes : Mutex<HashMap<Pos, usize>>,
+}
+
+impl<'a> RegionWriter<'a> {
+	pub fn new(out_folder : &'a Path, scale : Scale) -> Self {
+		Self {
+			out_folder,
+			scale,
+			regions: Mutex::new(HashMap::new()),
+			#[cfg(test)]
+			writes: Mutex::new(HashMap::new())
+		}
+	}
+
+	/// Every region that cells from `min` to `max` (inclusive, in cell coordinates) can draw into.
+	/// Trees and objects near the edge of a cell can reach into the next one over, so that counts too.
+	pub fn regions_for_cells(&self, min : Pos, max : Pos) -> Vec<Pos> {
+		let (min, max) = ((min.0 - 1, min.1 - 1), (max.0 + 1, max.1 + 1));
+
+		// Same as parse_land: a block belongs to whichever cell its middle is in.
+		let region_range = |min : i32, max : i32| {
+			let first_block = (min as f64 * CELL_SIZE / self.scale.horizontal - 0.5).ceil() as i32;
+			let last_block = ((max + 1) as f64 * CELL_SIZE / self.scale.horizontal - 0.5).ceil() as i32 - 1;
+			// 16 blocks to a chunk, 32 chunks to a region:
+			first_block.div_euclid(512)..=last_block.div_euclid(512)
+		};
+
+		region_range(min.1, max.1).flat_map(|z| {
+			region_range(min.0, max.0).map(move |x| (x, z))
+		}).collect()
+	}
+
+	/// Holds off on writing these regions until `done` is called for them.
+	pub fn expect(&self, regions : &[Pos]) {
+		let mut pending = self.regions.lock().unwrap();
+		for region in regions {
+			pending.entry(*region).or_default().lock().unwrap().subblocks_left += 1;
+		}
+	}
+
+	/// Lets `draw` change the chunks of a region, by their position in the world.
+	/// Chunks nobody has drawn into yet aren't in there.
+	pub fn edit<T>(&self, region : Pos, draw : impl FnOnce(&mut HashMap<Pos, Chunk>) -> T) -> std::io::Result<T> {
+		let pending = self.regions.lock().unwrap().entry(region).or_default().clone();
+
+		// Only this region is locked while we draw, so cells in other regions keep going.
+		// It's also locked while it's written, so by the time we have it, it's either still in memory or all on disk:
+		let mut pending = pending.lock().unwrap();
+		if pending.written {
+			return Ok(draw(&mut pending.late));
+		}
+		Ok(draw(&mut pending.chunks))
+	}
+
+	/// Draws single blocks at `(x, y, z)` in the world, leaving out any above or below the world.
+	/// Unless `replace` is set, they only go where there's air, or nothing yet.
+	pub fn draw_blocks<'b>(&self, blocks : impl IntoIterator<Item = (i32, i32, i32, &'b Block)>, replace : bool) -> std::io::Result<()> {
+		// Grouped by chunk, and the chunks by region, like parse_land does:
+		let mut region_chunks = BTreeMap::<Pos, BTreeMap<Pos, Vec<_>>>::new();
+		for (x, y, z, block) in blocks {
+			if y <= MIN_Y || y >= MAX_Y {
+				continue;
+			}
+
+			let chunk = (x.div_euclid(16), z.div_euclid(16));
+			region_chunks.entry((chunk.0.div_euclid(32), chunk.1.div_euclid(32))).or_default()
+				.entry(chunk).or_default().push(([x, y, z], block));
+		}
+
+		for (region, chunks) in region_chunks {
+			self.edit(region, |region| {
+				for (chunk_pos, blocks) in chunks {
+					let chunk = region.entry(chunk_pos).or_insert_with(|| Chunk {
+						x_pos: chunk_pos.0,
+						z_pos: chunk_pos.1,
+						..Chunk::default()
+					});
+
+					for ([x, y, z], block) in blocks {
+						let (x, z) = (x.rem_euclid(16) as usize, z.rem_euclid(16) as usize);
+
+						if !replace && chunk.block_at(x, y, z).is_some_and(|b| b.name != "minecraft:air") {
+							continue;
+						}
+						chunk.draw_height(x, z, y as f32, y as f32 + 1.0, block);
+					}
+				}
+			})?;
+		}
+		Ok(())
+	}
+
+	/// A sub-block overlapping these regions is done. Writes the regions that have no more sub-blocks to wait on.
+	pub fn done(&self, regions : &[Pos]) -> std::io::Result<()> {
+		for region in regions {
+			let Some(pending) = self.regions.lock().unwrap().get(region).cloned() else {
+				continue;
+			};
+
+			let mut pending = pending.lock().unwrap();
+			pending.subblocks_left = pending.subblocks_left.saturating_sub(1);
+			if pending.subblocks_left == 0 && !pending.written {
+				self.write(*region, &mut pending)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Writes whatever regions are left, and adds what was drawn into the others after they were written.
+	pub fn finish(&self) -> std::io::Result<()> {
+		let regions = std::mem::take(&mut *self.regions.lock().unwrap());
+		for (region, pending) in regions {
+			let mut pending = pending.lock().unwrap();
+			if !pending.written {
+				self.write(region, &mut pending)?;
+			} else if !pending.late.is_empty() {
+				self.add_late(region, &mut pending)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Writes a region's chunks, and lets them go.
+	fn write(&self, region : Pos, pending : &mut PendingRegion) -> std::io::Result<()> {
+		pending.written = true;
+		let mut chunks : Vec<(Pos, Chunk)> = std::mem::take(&mut pending.chunks).into_iter().collect();
+		if chunks.is_empty() {
+			return Ok(());
+		}
+		chunks.sort_by_key(|(pos, _)| *pos);
+
+		#[cfg(test)]
+		{
+			*self.writes.lock().unwrap().entry(region).or_default() += 1;
+		}
+
+		let (region_x, region_z) = region;
+		let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(self.out_folder.join(format!("r.{region_x}.{region_z}.mca")))?;
+		let mut out = fastanvil::Region::new(file).map_err(to_io_error)?;
+
+		for ((chunk_x, chunk_z), mut chunk) in chunks {
+			chunk.compact();
+			let data = fastnbt::to_bytes(&chunk).map_err(to_io_error)?;
+			out.write_chunk(chunk_x.rem_euclid(32) as usize, chunk_z.rem_euclid(32) as usize, &data).map_err(to_io_error)?;
+		}
+
+		Ok(())
+	}
+
+	/// Adds the blocks drawn into a region after it was written to the chunks they're in, leaving the rest of the file be.
+	/// Those blocks go over whatever was there.
+	fn add_late(&self, region : Pos, pending : &mut PendingRegion) -> std::io::Result<()> {
+		let (region_x, region_z) = region;
+		let path = self.out_folder.join(format!("r.{region_x}.{region_z}.mca"));
+		// We don't write regions nobody drew anything into, so there may be nothing to add to:
+		if !path.exists() {
+			pending.chunks = std::mem::take(&mut pending.late);
+			return self.write(region, pending);
+		}
+
+		let file = OpenOptions::new().read(true).write(true).open(path)?;
+		let mut existing = fastanvil::Region::from_stream(file).map_err(to_io_error)?;
+
+		for ((chunk_x, chunk_z), late) in std::mem::take(&mut pending.late) {
+			let (x, z) = (chunk_x.rem_euclid(32) as usize, chunk_z.rem_euclid(32) as usize);
+			let mut chunk = match existing.read_chunk(x, z).map_err(to_io_error)? {
+				Some(data) => fastnbt::from_bytes::<Chunk>(&data).map_err(to_io_error)?,
+				None => {
+					let mut late = late;
+					late.compact();
+					existing.write_chunk(x, z, &fastnbt::to_bytes(&late).map_err(to_io_error)?).map_err(to_io_error)?;
+					continue;
+				}
+			};
+
+			// Above the bedrock every chunk starts with:
+			for y in MIN_Y + 1..MAX_Y {
+				for block_z in 0..16 {
+					for block_x in 0..16 {
+						if let Some(block) = late.block_at(block_x, y, block_z).filter(|b| b.name != "minecraft:air") {
+							chunk.draw_height(block_x, block_z, y as f32, y as f32 + 1.0, block);
+						}
+					}
+				}
+			}
+
+			chunk.compact();
+			let data = fastnbt::to_bytes(&chunk).map_err(to_io_error)?;
+			existing.write_chunk(x, z, &data).map_err(to_io_error)?;
+		}
+		Ok(())
+	}
+}
+
+fn to_io_error(e : impl std::fmt::Display) -> std::io::Error {
+	std::io::Error::other(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::test_dir;
+
+	fn stone() -> Block {
+		Block {
+			name: "minecraft:stone".into(),
+			properties: HashMap::new()
+		}
+	}
+
+	fn block_in_file(folder : &Path, (x, y, z) : (i32, i32, i32)) -> Option<String> {
+		let (chunk_x, chunk_z) = (x.div_euclid(16), z.div_euclid(16));
+		let file = fs::File::open(folder.join(format!("r.{}.{}.mca", chunk_x.div_euclid(32), chunk_z.div_euclid(32)))).ok()?;
+		let data = fastanvil::Region::from_stream(file).unwrap().read_chunk(chunk_x.rem_euclid(32) as usize, chunk_z.rem_euclid(32) as usize).unwrap()?;
+		let chunk : Chunk = fastnbt::from_bytes(&data).unwrap();
+		chunk.block_at(x.rem_euclid(16) as usize, y, z.rem_euclid(16) as usize).map(|b| b.name.clone())
+	}
+
+	#[test]
+	fn regions_are_written_once() {
+		let dir = test_dir("regions-written-once");
+		let writer = RegionWriter::new(&dir, Scale::default());
+		let block = stone();
+
+		// Two sub-blocks side by side, at 64 blocks to a cell: each is one region, and can spill into the regions around it.
+		let (west, east) = (writer.regions_for_cells((0, 0), (7, 7)), writer.regions_for_cells((8, 0), (15, 7)));
+		assert_eq!(west.len(), 9);
+		writer.expect(&west);
+		writer.expect(&east);
+
+		writer.draw_blocks([(-1, 0, 0, &block), (0, 0, 0, &block), (512, 0, 0, &block), (1024, 0, 0, &block)], true).unwrap();
+
+		// Only the region the east sub-block can't reach is done with:
+		writer.done(&west).unwrap();
+		assert_eq!(*writer.writes.lock().unwrap(), HashMap::from([((-1, 0), 1)]));
+
+		writer.done(&east).unwrap();
+		assert_eq!(*writer.writes.lock().unwrap(), HashMap::from([((-1, 0), 1), ((0, 0), 1), ((1, 0), 1), ((2, 0), 1)]));
+
+		// Drawn after its region is written, so it waits for finish instead of the region being read back in:
+		writer.draw_blocks([(1, 1, 0, &block), (100, 0, 100, &block)], true).unwrap();
+		assert_eq!(block_in_file(&dir, (1, 1, 0)), Some("minecraft:air".into()));
+		assert_eq!(block_in_file(&dir, (100, 0, 100)), None);
+
+		writer.finish().unwrap();
+		assert_eq!(*writer.writes.lock().unwrap(), HashMap::from([((-1, 0), 1), ((0, 0), 1), ((1, 0), 1), ((2, 0), 1)]));
+		for pos in [(0, 0, 0), (1, 1, 0), (100, 0, 100), (512, 0, 0)] {
+			assert_eq!(block_in_file(&dir, pos), Some("minecraft:stone".into()), "{pos:?}");
+		}
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
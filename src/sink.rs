@@ -0,0 +1,143 @@
+use std::{collections::BTreeSet, path::Path, sync::Arc};
+
+use crate::{error::Result, esm::{Cell, Land, Reference, WorldRecords}, mapping::Mapping, meshes::{place_object, Meshes}, regions::RegionWriter, trees::place_tree, world_gen::{parse_land, Interpolation, Pos, Scale}};
+
+/// The worldspace the reader is about to go through.
+#[derive(Debug, Clone, Copy)]
+pub struct Worldspace<'a> {
+	pub edid : &'a str,
+	pub form_id : u32,
+	/// The land textures and water types its cells refer to.
+	pub records : &'a WorldRecords,
+	/// The first and last cell (inclusive) of every exterior sub-block, in the order they come up in the load order.
+	/// That's the sub-block's own grid, grown to fit any cell in it that's somewhere else.
+	pub subblocks : &'a [(Pos, Pos)],
+	/// Whether there's a persistent cell, whose references can be anywhere in the worldspace.
+	pub persistent_cell : bool,
+}
+
+/// Whatever the reader hands a worldspace's records to, so it doesn't need to know what becomes of them.
+///
+/// Sub-blocks are read on several threads at once, so everything but `on_worldspace` and `finish` can be called from any of them.
+/// The cells of a sub-block all come before `on_subblock_done` for it, but cells of different sub-blocks come in no particular order.
+pub trait LandSink : Sync {
+	/// Called once, before anything else.
+	fn on_worldspace(&mut self, _worldspace : Worldspace) -> Result<()> {
+		Ok(())
+	}
+
+	/// Called for every cell, before its LAND and references. That includes the worldspace's persistent cell (see [`Cell::persistent`]).
+	fn on_cell(&self, _cell : &Cell) -> Result<()> {
+		Ok(())
+	}
+
+	fn on_land(&self, land : Land) -> Result<()>;
+
+	/// Called for every object (REFR) placed in a cell, temporary or persistent (see [`Reference::persistent`]).
+	/// Those in the persistent cell can be anywhere in the worldspace.
+	fn on_reference(&self, _cell : &Cell, _reference : &Reference) -> Result<()> {
+		Ok(())
+	}
+
+	/// Every cell from `min` to `max` (inclusive) has been handed over. Cells outside of any sub-block (like the persistent cell) aren't part of one.
+	fn on_subblock_done(&self, _min : Pos, _max : Pos) -> Result<()> {
+		Ok(())
+	}
+
+	/// The persistent cell and its references have all been handed over. Only called if [`Worldspace::persistent_cell`] is set.
+	fn on_persistent_cell_done(&self) -> Result<()> {
+		Ok(())
+	}
+
+	/// Called once after the last cell, unless reading failed.
+	fn finish(&mut self) -> Result<()> {
+		Ok(())
+	}
+}
+
+/// Draws LAND, and the trees (and, given meshes, the objects) placed on it, into Minecraft region (.mca) files.
+#[derive(Debug)]
+pub struct AnvilSink<'a> {
+	writer : RegionWriter<'a>,
+	mapping : &'a Mapping,
+	scale : Scale,
+	interpolation : Interpolation,
+	/// From `on_worldspace`.
+	records : WorldRecords,
+	/// Where placed objects' meshes come from. Without them, only trees are placed.
+	meshes : Option<Arc<Meshes>>,
+	/// Every region the sub-blocks can draw into, which is where the persistent cell's references can go too.
+	all_regions : Vec<Pos>,
+}
+
+impl<'a> AnvilSink<'a> {
+	pub fn new(out_folder : &'a Path, mapping : &'a Mapping, scale : Scale, interpolation : Interpolation) -> Self {
+		Self {
+			writer: RegionWriter::new(out_folder, scale),
+			mapping,
+			scale,
+			interpolation,
+			records: WorldRecords::default(),
+			meshes: None,
+			all_regions: Vec::new()
+		}
+	}
+
+	/// Turns placed STATs into blocks too, from their meshes.
+	pub fn with_meshes(mut self, meshes : Arc<Meshes>) -> Self {
+		self.meshes = Some(meshes);
+		self
+	}
+}
+
+impl LandSink for AnvilSink<'_> {
+	fn on_worldspace(&mut self, worldspace : Worldspace) -> Result<()> {
+		self.records = worldspace.records.clone();
+
+		// So we know when we've seen the last of each region, and can write it out:
+		let mut all_regions = BTreeSet::new();
+		for (min, max) in worldspace.subblocks {
+			let regions = self.writer.regions_for_cells(*min, *max);
+			self.writer.expect(&regions);
+			all_regions.extend(regions);
+		}
+
+		self.all_regions = if worldspace.persistent_cell { all_regions.into_iter().collect() } else { Vec::new() };
+		self.writer.expect(&self.all_regions);
+		Ok(())
+	}
+
+	fn on_land(&self, land : Land) -> Result<()> {
+		parse_land(land, &self.records, self.mapping, self.scale, self.interpolation, &self.writer)
+	}
+
+	fn on_reference(&self, _cell : &Cell, reference : &Reference) -> Result<()> {
+		let Some(object) = self.records.objects.get(&reference.base) else {
+			return Ok(());
+		};
+
+		if let Some(tree) = self.mapping.tree(object) {
+			return place_tree(reference, tree, self.scale, &self.writer);
+		}
+
+		let (Some(meshes), Some(model)) = (&self.meshes, &object.model) else {
+			return Ok(());
+		};
+		match (self.mapping.object(object), meshes.get(model)) {
+			(Some(rule), Some(triangles)) => place_object(reference, &triangles, rule, self.scale, &self.writer),
+			_ => Ok(())
+		}
+	}
+
+	fn on_subblock_done(&self, min : Pos, max : Pos) -> Result<()> {
+		Ok(self.writer.done(&self.writer.regions_for_cells(min, max))?)
+	}
+
+	fn on_persistent_cell_done(&self) -> Result<()> {
+		Ok(self.writer.done(&self.all_regions)?)
+	}
+
+	fn finish(&mut self) -> Result<()> {
+		Ok(self.writer.finish()?)
+	}
+}
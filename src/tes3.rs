@@ -0,0 +1,864 @@
+use std::{collections::{BTreeMap, HashMap}, io::{Cursor, Read, Seek, SeekFrom}, sync::Mutex};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::ThreadPoolBuilder;
+
+use crate::{error::{Error, Location, Result}, esm::{no_cells, peek_signature, read_zstring, worldspace_not_found,
+    BaseObject, Cell, CellContents, DataHeader, DataVersion, ESMFile, FieldHeader, Land, LandTexture, RecordHeader, Reference, WeatherChances, WorldRecords, WorldspaceInfo},
+    load_order::sorted_skips, sink::{LandSink, Worldspace}, world_gen::Pos};
+
+/// What we call Morrowind's exterior, the only worldspace it has.
+pub(crate) const EXTERIOR : &str = "Vvardenfell";
+
+/// Morrowind's cells are 8192 units across with 65 x 65 vertices, so each of them is handed over as 2 x 2 cells of 33 x 33 vertices like later games have.
+const VERTICES : usize = 65;
+
+/// Morrowind's weathers aren't records, so these are their made-up FormIDs (from 1) and editor IDs.
+/// Regions (WEAT) give the chance of each in this order. Bloodmoon adds the last two.
+const WEATHERS : [&str; 10] = ["Clear", "Cloudy", "Foggy", "Overcast", "Rain", "Thunder", "Ash", "Blight", "Snow", "Blizzard"];
+
+/// A record as it is in its file, to read later.
+#[derive(Debug)]
+struct StoredRecord {
+    file : usize,
+    offset : u64,
+    data : Vec<u8>,
+}
+
+/// Morrowind's records refer to each other by ID (their NAME) rather than FormID, so we make FormIDs up for the ones other records refer to:
+/// numbered from 1, in the order the load order first has them.
+#[derive(Debug, Default)]
+struct Ids(HashMap<String, u32>);
+
+impl Ids {
+    fn insert(&mut self, id : &str) -> u32 {
+        let next = self.0.len() as u32 + 1;
+        *self.0.entry(id.to_lowercase()).or_insert(next)
+    }
+
+    fn get(&self, id : &str) -> Option<u32> {
+        self.0.get(&id.to_lowercase()).copied()
+    }
+}
+
+/// The exterior of every file in the load order.
+#[derive(Debug, Default)]
+struct Exterior {
+    records : WorldRecords,
+    /// Made-up FormIDs of the STATs in `records`.
+    objects : Ids,
+    /// Made-up FormIDs of the REGNs in `records`.
+    regions : Ids,
+    /// Every file's version of each exterior CELL, in load order, by their position in Morrowind's grid.
+    /// A plugin's CELL only has the references it adds or changes, so they're all read.
+    cells : HashMap<(i32, i32), Vec<StoredRecord>>,
+    /// The last LAND of each cell.
+    lands : HashMap<(i32, i32), StoredRecord>,
+    /// Load order index of each file's masters, if they're loaded.
+    masters : Vec<Vec<Option<usize>>>,
+    names : Vec<String>,
+}
+
+/// A reference (FRMR) in a CELL, with its base object by ID.
+#[derive(Debug, Clone, PartialEq)]
+struct PlacedObject {
+    base : String,
+    position : [f32; 3],
+    rotation : [f32; 3],
+    scale : f32,
+    /// Before the CELL's NAM0, with the references that are always loaded.
+    persistent : bool,
+}
+
+/// A reference in a CELL by the plugin it's from (its load order index) and its index there, and `None` if the CELL deletes it.
+type ReferenceChange = ((usize, u32), Option<PlacedObject>);
+
+/// Morrowind's LTEX don't have FormIDs, so we make them up from the load order index of their plugin and the number VTEX refers to them by (their INTV plus one).
+fn land_texture_id(file : usize, index : u32) -> u32 {
+    (file as u32) << 24 | (index & 0x00FFFFFF)
+}
+
+/// Goes through every record from where `reader` is to the end of the file, and hands the data of the ones of the given types to `f`.
+fn read_records(reader : &mut (impl Read + Seek), types : &[&str], mut f : impl FnMut(&RecordHeader, Vec<u8>) -> Result<()>) -> Result<()> {
+    while let Some((record, data)) = next_record(reader, types)? {
+        f(&record, data).map_err(|e| e.in_morrowind_record(record.offset, &record.ty))?;
+    }
+    Ok(())
+}
+
+/// The next record of one of the given types from where `reader` is, with its data. `None` at the end of the file.
+/// Morrowind's plugins are one long list of records, without any groups to skip ahead with.
+fn next_record(reader : &mut (impl Read + Seek), types : &[&str]) -> Result<Option<(RecordHeader, Vec<u8>)>> {
+    loop {
+        match peek_signature(reader) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?
+        };
+
+        let record = RecordHeader::read(reader, DataVersion::Morrowind)?;
+        if !types.contains(&record.ty.as_str()) {
+            record.skip_data(reader)?;
+            continue;
+        }
+
+        // Unlike later games, nothing is compressed:
+        let mut data = vec![0; record.data_size as usize];
+        reader.read_exact(&mut data).map_err(|e| Error::from(e).in_morrowind_record(record.offset, &record.ty))?;
+        return Ok(Some((record, data)));
+    }
+}
+
+/// Reads the LTEX, STAT and REGN records of every file, with later files overriding earlier ones.
+/// With `with_cells`, also finds every exterior CELL and LAND, to read later.
+fn read_exterior(files : &mut [ESMFile], with_cells : bool) -> Result<Exterior> {
+    let mut exterior = Exterior::default();
+    for (id, name) in WEATHERS.iter().enumerate() {
+        exterior.records.weathers.insert(id as u32 + 1, name.to_string());
+    }
+
+    let types : &[&str] = if with_cells { &["LTEX", "STAT", "REGN", "CELL", "LAND"] } else { &["LTEX", "STAT", "REGN"] };
+
+    for i in 0..files.len() {
+        let (loaded, file) = files.split_at_mut(i);
+        let file = &mut file[0];
+        exterior.masters.push(file.header().masters.iter().map(|master| loaded.iter().position(|f| f.name.eq_ignore_ascii_case(master))).collect());
+        exterior.names.push(file.name.clone());
+
+        file.reader.seek(SeekFrom::Start(file.data_start))?;
+        read_records(&mut file.reader, types, |record, data| {
+            match record.ty.as_str() {
+                "LTEX" => {
+                    let (index, land_texture) = read_land_texture(data)?;
+                    exterior.records.land_textures.insert(land_texture_id(i, index + 1), land_texture);
+                },
+                "STAT" => {
+                    let object = read_static(data)?;
+                    exterior.records.objects.insert(exterior.objects.insert(&object.edid), object);
+                },
+                "REGN" => {
+                    let region = read_region(data)?;
+                    exterior.records.regions.insert(exterior.regions.insert(&region.edid), region);
+                },
+                "CELL" => if let Some(grid) = read_exterior_grid(&data)? {
+                    exterior.cells.entry(grid).or_default().push(StoredRecord {
+                        file: i,
+                        offset: record.offset,
+                        data
+                    });
+                },
+                _ => {
+                    let grid = read_land_grid(&data)?;
+                    if record.is_deleted() {
+                        exterior.lands.remove(&grid);
+                    } else {
+                        exterior.lands.insert(grid, StoredRecord {
+                            file: i,
+                            offset: record.offset,
+                            data
+                        });
+                    }
+                }
+            }
+            Ok(())
+        }).map_err(|e| e.in_plugin(&file.name))?;
+    }
+    Ok(exterior)
+}
+
+/// Reads the land textures, statics and regions of every file, with later files overriding earlier ones.
+pub(crate) fn read_world_records(files : &mut [ESMFile]) -> Result<WorldRecords> {
+    Ok(read_exterior(files, false)?.records)
+}
+
+/// Counts the exterior cells, in Morrowind's own (8192 unit) grid.
+pub(crate) fn list_worldspaces(reader : &mut (impl Read + Seek)) -> Result<Vec<WorldspaceInfo>> {
+    let mut cell_count = 0;
+    let mut bounds : Option<((i32, i32), (i32, i32))> = None;
+
+    read_records(reader, &["CELL"], |_, data| {
+        if let Some((x, y)) = read_exterior_grid(&data)? {
+            cell_count += 1;
+            bounds = Some(match bounds {
+                Some(((min_x, min_y), (max_x, max_y))) => ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y))),
+                None => ((x, y), (x, y))
+            });
+        }
+        Ok(())
+    })?;
+
+    Ok(vec![WorldspaceInfo {
+        edid: EXTERIOR.into(),
+        form_id: 0,
+        parent: None,
+        cell_count,
+        bounds
+    }])
+}
+
+/// Reads the exterior in every file, and hands each cell to the sink with the last LAND for it and the references every file places in it.
+/// Only references to STATs are handed over, as those are the base objects we read.
+pub(crate) fn read_worldspace(files : &mut [ESMFile], worldspace : &str, sink : &mut dyn LandSink, keep_going : bool) -> Result<Vec<Error>> {
+    if !worldspace.eq_ignore_ascii_case(EXTERIOR) {
+        return Err(worldspace_not_found(worldspace, &[EXTERIOR.into()]));
+    }
+
+    let exterior = read_exterior(files, true)?;
+    let grids = exterior.grids();
+    if grids.is_empty() {
+        return Err(no_cells(EXTERIOR));
+    }
+
+    // Four of Morrowind's cells across make up one of our sub-blocks of 8 x 8 cells:
+    let cell_count = grids.len() as u64;
+    let mut batches : HashMap<(i32, i32), Vec<(i32, i32)>> = HashMap::new();
+    for grid in grids {
+        batches.entry((grid.0.div_euclid(4), grid.1.div_euclid(4))).or_default().push(grid);
+    }
+    let mut batches : Vec<_> = batches.into_iter().collect();
+    batches.sort_by_key(|(subblock, _)| *subblock);
+
+    let subblocks : Vec<(Pos, Pos)> = batches.iter().map(|(subblock, _)| subblock_cells(*subblock)).collect();
+    sink.on_worldspace(Worldspace {
+        edid: EXTERIOR,
+        form_id: 0,
+        records: &exterior.records,
+        subblocks: &subblocks,
+        persistent_cell: false
+    })?;
+
+    let exterior = &exterior;
+    let shared : &dyn LandSink = sink;
+    let skipped_cells : Mutex<Vec<Error>> = Mutex::new(Vec::new());
+    let skipped = &skipped_cells;
+
+    let bar = ProgressBar::new(cell_count);
+    bar.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:100} {msg}").unwrap());
+
+    let pool = ThreadPoolBuilder::new().thread_name(|i| {
+        format!("Subgroup Reader {i}")
+    }).build().map_err(std::io::Error::other)?;
+
+    // Everything is in memory already, so unlike later games there's no reading to do between spawning batches:
+    let result = pool.install(|| {
+        use rayon::prelude::*;
+
+        batches.into_par_iter().try_for_each(|(subblock, grids)| -> Result<()> {
+            for grid in grids {
+                bar.set_message(format!("Cell {},{}", grid.0, grid.1));
+
+                match exterior.contents(grid) {
+                    Ok(cells) => for CellContents { cell, land, references } in cells {
+                        shared.on_cell(&cell)?;
+                        if let Some(land) = land {
+                            shared.on_land(land)?;
+                        }
+                        for reference in &references {
+                            shared.on_reference(&cell, reference)?;
+                        }
+                    },
+                    Err(e) if keep_going => skipped.lock().unwrap().push(e),
+                    Err(e) => return Err(e)
+                }
+                bar.inc(1);
+            }
+
+            let (min, max) = subblock_cells(subblock);
+            shared.on_subblock_done(min, max)
+        })
+    });
+
+    if let Err(e) = result {
+        bar.abandon();
+        return Err(e);
+    }
+    bar.finish();
+
+    sink.finish()?;
+    Ok(sorted_skips(skipped_cells.into_inner().unwrap()))
+}
+
+/// The exterior cells of a plugin read on its own, one after another. See [`ESMFile::cells`](crate::esm::ESMFile::cells).
+#[derive(Debug)]
+pub(crate) struct ExteriorCells {
+    exterior : Exterior,
+    /// Of the Morrowind cells still to read.
+    grids : std::vec::IntoIter<(i32, i32)>,
+    /// Our cells of the last Morrowind cell that haven't been handed out yet.
+    pending : std::vec::IntoIter<CellContents>,
+}
+
+impl ExteriorCells {
+    pub(crate) fn read(file : &mut ESMFile) -> Result<Self> {
+        let exterior = read_exterior(std::slice::from_mut(file), true)?;
+        let grids = exterior.grids();
+        if grids.is_empty() {
+            return Err(no_cells(EXTERIOR));
+        }
+
+        Ok(ExteriorCells {
+            exterior,
+            grids: grids.into_iter(),
+            pending: Vec::new().into_iter()
+        })
+    }
+
+    pub(crate) fn next_cell(&mut self) -> Result<Option<CellContents>> {
+        loop {
+            if let Some(cell) = self.pending.next() {
+                return Ok(Some(cell));
+            }
+            let Some(grid) = self.grids.next() else {
+                return Ok(None);
+            };
+            self.pending = self.exterior.contents(grid)?.into_iter();
+        }
+    }
+}
+
+impl Exterior {
+    /// Every cell with a CELL or LAND, by position in Morrowind's grid, from the bottom left.
+    fn grids(&self) -> Vec<(i32, i32)> {
+        let mut grids : Vec<(i32, i32)> = self.cells.keys().chain(self.lands.keys()).copied().collect();
+        grids.sort_by_key(|(x, y)| (*y, *x));
+        grids.dedup();
+        grids
+    }
+
+    /// The 2 x 2 of our cells a Morrowind cell is split into, with their part of its LAND, and the references in each.
+    fn contents(&self, grid : (i32, i32)) -> Result<Vec<CellContents>> {
+        let mut region = None;
+        // By the plugin they're from (its load order index) and their index there, so later files replace them:
+        let mut placed : BTreeMap<(usize, u32), PlacedObject> = BTreeMap::new();
+
+        for record in self.cells.get(&grid).into_iter().flatten() {
+            let (cell_region, references) = read_cell(&record.data, record.file, &self.masters[record.file])
+                .map_err(|e| e.in_morrowind_record(record.offset, "CELL").in_plugin(&self.names[record.file]))?;
+
+            region = cell_region.or(region);
+            for (key, object) in references {
+                match object {
+                    Some(object) => placed.insert(key, object),
+                    None => placed.remove(&key)
+                };
+            }
+        }
+
+        let regions : Vec<u32> = region.and_then(|r| self.regions.get(&r)).into_iter().collect();
+        let mut cells : Vec<CellContents> = (0..4).map(|q| CellContents {
+            cell: quarter_cell(grid, q % 2, q / 2, regions.clone()),
+            land: None,
+            references: Vec::new()
+        }).collect();
+
+        if let Some(record) = self.lands.get(&grid) {
+            let lands = read_land(&record.data, record.file).map_err(|e| e.in_morrowind_record(record.offset, "LAND").in_plugin(&self.names[record.file]))?;
+            for (contents, mut land) in cells.iter_mut().zip(lands) {
+                land.cell = contents.cell.clone();
+                contents.land = Some(land);
+            }
+        }
+
+        for ((file, index), object) in placed {
+            // Only those of base objects we know:
+            let Some(base) = self.objects.get(&object.base) else {
+                continue;
+            };
+
+            // Into whichever of our cells it's in, which are 4096 units across:
+            let quarter = |position : f32, grid : i32| ((position / 4096.0).floor() as i32 - grid * 2).clamp(0, 1) as usize;
+            let (i, j) = (quarter(object.position[0], grid.0), quarter(object.position[1], grid.1));
+
+            cells[j * 2 + i].references.push(Reference {
+                // Made up like LTEX FormIDs:
+                form_id: (file as u32) << 24 | (index & 0x00FFFFFF),
+                base,
+                position: object.position,
+                rotation: object.rotation,
+                scale: object.scale,
+                persistent: object.persistent
+            });
+        }
+        Ok(cells)
+    }
+}
+
+/// The first and last of our cells in a sub-block, from its position in sub-blocks.
+fn subblock_cells((x, y) : (i32, i32)) -> (Pos, Pos) {
+    ((x * 8, y * 8), (x * 8 + 7, y * 8 + 7))
+}
+
+/// One of the 2 x 2 cells we split a Morrowind cell into.
+fn quarter_cell(grid : (i32, i32), i : usize, j : usize, regions : Vec<u32>) -> Cell {
+    Cell {
+        x: grid.0 * 2 + i as i32,
+        y: grid.1 * 2 + j as i32,
+        // Morrowind's sea is always at 0, and there's only the one kind of water:
+        water_height: Some(0.0),
+        water_type: None,
+        persistent: false,
+        regions,
+        climate: None
+    }
+}
+
+/// The number (INTV) and texture of an LTEX.
+fn read_land_texture(data : Vec<u8>) -> Result<(u32, LandTexture)> {
+    let size = data.len() as u64;
+    let mut reader = Cursor::new(data);
+    let mut buf : [u8; 4] = [0; 4];
+
+    let mut edid = String::new();
+    let mut index = None;
+    let mut texture = None;
+
+    while reader.position() < size {
+        let field = FieldHeader::read(&mut reader, DataVersion::Morrowind)?;
+        match field.ty.as_str() {
+            "NAME" => edid = read_zstring(&mut reader, field.size)?,
+            "INTV" => {
+                reader.read_exact(&mut buf)?;
+                index = Some(u32::from_le_bytes(buf));
+                reader.seek_relative(field.size as i64 - 4)?;
+            },
+            "DATA" => texture = Some(read_zstring(&mut reader, field.size)?),
+            _ => field.skip_data(&mut reader)?
+        }
+    }
+
+    let Some(index) = index else {
+        return Err(Error::MissingSubrecord {
+            subrecord: "INTV",
+            at: Location::default()
+        });
+    };
+
+    Ok((index, LandTexture {
+        edid,
+        texture,
+        texture_set: None
+    }))
+}
+
+/// A STAT's ID (NAME) and mesh (MODL).
+fn read_static(data : Vec<u8>) -> Result<BaseObject> {
+    let size = data.len() as u64;
+    let mut reader = Cursor::new(data);
+
+    let mut object = BaseObject {
+        ty: "STAT".into(),
+        edid: String::new(),
+        model: None
+    };
+
+    while reader.position() < size {
+        let field = FieldHeader::read(&mut reader, DataVersion::Morrowind)?;
+        match field.ty.as_str() {
+            "NAME" => object.edid = read_zstring(&mut reader, field.size)?,
+            "MODL" => object.model = Some(read_zstring(&mut reader, field.size)?),
+            _ => field.skip_data(&mut reader)?
+        }
+    }
+    Ok(object)
+}
+
+/// A REGN's ID (NAME), and the chance of each of [`WEATHERS`] in it (WEAT).
+fn read_region(data : Vec<u8>) -> Result<WeatherChances> {
+    let size = data.len() as u64;
+    let mut reader = Cursor::new(data);
+    let mut region = WeatherChances::default();
+
+    while reader.position() < size {
+        let field = FieldHeader::read(&mut reader, DataVersion::Morrowind)?;
+        match field.ty.as_str() {
+            "NAME" => region.edid = read_zstring(&mut reader, field.size)?,
+            "WEAT" => {
+                let mut chances = vec![0; field.size as usize];
+                reader.read_exact(&mut chances)?;
+
+                region.weathers = chances.into_iter().zip(1..=WEATHERS.len() as u32)
+                    .filter(|(chance, _)| *chance > 0)
+                    .map(|(chance, weather)| (weather, chance as u32))
+                    .collect();
+            },
+            _ => field.skip_data(&mut reader)?
+        }
+    }
+    Ok(region)
+}
+
+/// The grid position of an exterior CELL, or `None` for interiors.
+fn read_exterior_grid(data : &[u8]) -> Result<Option<(i32, i32)>> {
+    let size = data.len() as u64;
+    let mut reader = Cursor::new(data);
+    let mut buf : [u8; 4] = [0; 4];
+
+    while reader.position() < size {
+        let field = FieldHeader::read(&mut reader, DataVersion::Morrowind)?;
+        if field.ty != "DATA" {
+            field.skip_data(&mut reader)?;
+            continue;
+        }
+
+        reader.read_exact(&mut buf)?;
+        // Interiors are flagged with 0x1:
+        if u32::from_le_bytes(buf) & 0x1 != 0 {
+            return Ok(None);
+        }
+
+        reader.read_exact(&mut buf)?;
+        let x = i32::from_le_bytes(buf);
+        reader.read_exact(&mut buf)?;
+        let y = i32::from_le_bytes(buf);
+        return Ok(Some((x, y)));
+    }
+
+    Err(Error::MissingSubrecord {
+        subrecord: "DATA",
+        at: Location::default()
+    })
+}
+
+/// The region (RGNN) of an exterior CELL from the plugin at `file` in the load order, and the references (FRMR) in it.
+/// `masters` has the load order index of each of the plugin's masters, if they're loaded.
+fn read_cell(data : &[u8], file : usize, masters : &[Option<usize>]) -> Result<(Option<String>, Vec<ReferenceChange>)> {
+    let size = data.len() as u64;
+    let mut reader = Cursor::new(data);
+    let mut buf : [u8; 4] = [0; 4];
+
+    let mut region = None;
+    let mut references = Vec::new();
+    // The reference whose fields we're in, and whether it's deleted:
+    let mut current : Option<((usize, u32), PlacedObject, bool)> = None;
+    let mut persistent = true;
+
+    let finish = |(key, object, deleted) : ((usize, u32), PlacedObject, bool)| (key, (!deleted).then_some(object));
+
+    while reader.position() < size {
+        let field = FieldHeader::read(&mut reader, DataVersion::Morrowind)?;
+        match (field.ty.as_str(), &mut current) {
+            ("FRMR", _) => {
+                references.extend(current.take().map(finish));
+
+                reader.read_exact(&mut buf)?;
+                let index = u32::from_le_bytes(buf);
+                reader.seek_relative(field.size as i64 - 4)?;
+
+                // The top byte says which master (counting from 1) has the reference this one changes, or is 0 for references the plugin adds:
+                let key = match (index >> 24) as usize {
+                    0 => (file, index),
+                    master => match masters.get(master - 1) {
+                        Some(Some(master)) => (*master, index & 0x00FFFFFF),
+                        _ => (file, index)
+                    }
+                };
+
+                current = Some((key, PlacedObject {
+                    base: String::new(),
+                    position: [0.0; 3],
+                    rotation: [0.0; 3],
+                    scale: 1.0,
+                    persistent
+                }, false));
+            },
+            ("NAME", Some((_, object, _))) => object.base = read_zstring(&mut reader, field.size)?,
+            ("XSCL", Some((_, object, _))) => {
+                reader.read_exact(&mut buf)?;
+                object.scale = f32::from_le_bytes(buf);
+                reader.seek_relative(field.size as i64 - 4)?;
+            },
+            ("DELE", Some((_, _, deleted))) => {
+                *deleted = true;
+                field.skip_data(&mut reader)?;
+            },
+            ("DATA", Some((_, object, _))) => {
+                for value in object.position.iter_mut().chain(object.rotation.iter_mut()) {
+                    reader.read_exact(&mut buf)?;
+                    *value = f32::from_le_bytes(buf);
+                }
+                reader.seek_relative(field.size as i64 - 24)?;
+            },
+            // The references after NAM0 are temporary. A moved reference (MVRF and CNDT) comes before the FRMR of the reference it moves:
+            ("NAM0" | "MVRF" | "CNDT", _) => {
+                references.extend(current.take().map(finish));
+                persistent &= field.ty != "NAM0";
+                field.skip_data(&mut reader)?;
+            },
+            ("RGNN", None) => region = Some(read_zstring(&mut reader, field.size)?),
+            _ => field.skip_data(&mut reader)?
+        }
+    }
+    references.extend(current.take().map(finish));
+
+    Ok((region, references))
+}
+
+/// The grid position (INTV) of a LAND.
+fn read_land_grid(data : &[u8]) -> Result<(i32, i32)> {
+    let size = data.len() as u64;
+    let mut reader = Cursor::new(data);
+    let mut buf : [u8; 4] = [0; 4];
+
+    while reader.position() < size {
+        let field = FieldHeader::read(&mut reader, DataVersion::Morrowind)?;
+        if field.ty != "INTV" {
+            field.skip_data(&mut reader)?;
+            continue;
+        }
+
+        reader.read_exact(&mut buf)?;
+        let x = i32::from_le_bytes(buf);
+        reader.read_exact(&mut buf)?;
+        let y = i32::from_le_bytes(buf);
+        return Ok((x, y));
+    }
+
+    Err(Error::MissingSubrecord {
+        subrecord: "INTV",
+        at: Location::default()
+    })
+}
+
+/// Reads a LAND from the plugin at `file` in the load order, as the 2 x 2 cells it covers for us. Empty if it doesn't have any heights.
+fn read_land(data : &[u8], file : usize) -> Result<Vec<Land>> {
+    let size = data.len() as u64;
+    let mut reader = Cursor::new(data);
+    let mut buf : [u8; 4] = [0; 4];
+
+    let mut grid : Option<(i32, i32)> = None;
+    let mut heights : Option<Vec<f32>> = None;
+    let mut vertex_colors : Option<Vec<[u8; 3]>> = None;
+    let mut normals : Option<Vec<[i8; 3]>> = None;
+    let mut textures : Option<Vec<u16>> = None;
+
+    while reader.position() < size {
+        let field = FieldHeader::read(&mut reader, DataVersion::Morrowind)?;
+
+        match field.ty.as_str() {
+            "INTV" => {
+                reader.read_exact(&mut buf)?;
+                let x = i32::from_le_bytes(buf);
+                reader.read_exact(&mut buf)?;
+                let y = i32::from_le_bytes(buf);
+                reader.seek_relative(field.size as i64 - 8)?;
+
+                grid = Some((x, y));
+            },
+            "VHGT" => {
+                // Based on https://en.uesp.net/wiki/Morrowind_Mod:Mod_File_Format/LAND
+                reader.read_exact(&mut buf)?;
+                let offset_height = f32::from_le_bytes(buf);
+
+                let mut height_gradient = vec![0; VERTICES * VERTICES];
+                reader.read_exact(&mut height_gradient)?;
+                let height_gradient : Vec<i8> = height_gradient.into_iter().map(|b| b as i8).collect();
+
+                // Skip the unused bytes at the end:
+                reader.seek_relative(field.size as i64 - 4 - (VERTICES * VERTICES) as i64)?;
+
+                heights = Some(Land::decode_heights(offset_height, &height_gradient, VERTICES));
+            },
+            "VCLR" => {
+                let mut colors = Vec::with_capacity(VERTICES * VERTICES);
+                let mut rgb : [u8; 3] = [0; 3];
+
+                for _ in 0..VERTICES * VERTICES {
+                    reader.read_exact(&mut rgb)?;
+                    colors.push(rgb);
+                }
+                reader.seek_relative(field.size as i64 - (VERTICES * VERTICES * 3) as i64)?;
+
+                vertex_colors = Some(colors);
+            },
+            "VNML" => {
+                let mut vertex_normals = Vec::with_capacity(VERTICES * VERTICES);
+                let mut xyz : [u8; 3] = [0; 3];
+
+                for _ in 0..VERTICES * VERTICES {
+                    reader.read_exact(&mut xyz)?;
+                    vertex_normals.push(xyz.map(|n| n as i8));
+                }
+                reader.seek_relative(field.size as i64 - (VERTICES * VERTICES * 3) as i64)?;
+
+                normals = Some(vertex_normals);
+            },
+            "VTEX" => {
+                let mut buf16 : [u8; 2] = [0; 2];
+                let mut stored = Vec::with_capacity(256);
+
+                for _ in 0..256 {
+                    reader.read_exact(&mut buf16)?;
+                    stored.push(u16::from_le_bytes(buf16));
+                }
+                reader.seek_relative(field.size as i64 - 512)?;
+
+                textures = Some(texture_rows(&stored));
+            },
+            _ => {
+                field.skip_data(&mut reader)?;
+            }
+        }
+    }
+
+    let Some((x, y)) = grid else {
+        return Err(Error::MissingSubrecord {
+            subrecord: "INTV",
+            at: Location::default()
+        });
+    };
+    let Some(heights) = heights else {
+        return Ok(Vec::new());
+    };
+
+    // Each texture covers 4 x 4 of the vertex squares. The last row and column of vertices go with the textures before them:
+    let textures : Vec<Option<u32>> = (0..VERTICES * VERTICES).map(|i| {
+        let (row, col) = (i / VERTICES, i % VERTICES);
+        let texture = textures.as_ref()?[(row / 4).min(15) * 16 + (col / 4).min(15)];
+        // 0 is the default texture, which the mapping's default block stands in for:
+        (texture != 0).then(|| land_texture_id(file, texture as u32))
+    }).collect();
+
+    let mut cells = Vec::with_capacity(4);
+    for j in 0..2 {
+        for i in 0..2 {
+            cells.push(Land {
+                // The region is in the CELL record:
+                cell: quarter_cell((x, y), i, j, Vec::new()),
+                heights: quarter(&heights, i, j),
+                textures: quarter(&textures, i, j),
+                vertex_colors: vertex_colors.as_ref().map(|c| quarter(c, i, j)),
+                normals: normals.as_ref().map(|n| quarter(n, i, j))
+            });
+        }
+    }
+    Ok(cells)
+}
+
+/// VTEX's 16 x 16 textures are stored as 4 x 4 blocks of 4 x 4, which this puts back into rows.
+fn texture_rows(stored : &[u16]) -> Vec<u16> {
+    let mut rows = vec![0; 256];
+    let mut stored = stored.iter();
+    for y1 in 0..4 {
+        for x1 in 0..4 {
+            for y2 in 0..4 {
+                for x2 in 0..4 {
+                    rows[(y1 * 4 + y2) * 16 + x1 * 4 + x2] = *stored.next().unwrap();
+                }
+            }
+        }
+    }
+    rows
+}
+
+/// The 33 x 33 vertices of one of the 2 x 2 cells we split a LAND into.
+/// Neighbouring cells share the row (or column) of vertices between them, just like these do.
+fn quarter<T : Clone>(values : &[T], i : usize, j : usize) -> Vec<T> {
+    (j * 32..=j * 32 + 32).flat_map(|row| values[row * VERTICES + i * 32..=row * VERTICES + i * 32 + 32].iter().cloned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(ty : &str, data : &[u8]) -> Vec<u8> {
+        let mut field = ty.as_bytes().to_vec();
+        field.extend((data.len() as u32).to_le_bytes());
+        field.extend(data);
+        field
+    }
+
+    fn zstring(s : &str) -> Vec<u8> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.push(0);
+        bytes
+    }
+
+    fn reference(index : u32, base : &str, position : [f32; 3]) -> Vec<u8> {
+        let mut data = field("FRMR", &index.to_le_bytes());
+        data.extend(field("NAME", &zstring(base)));
+        data.extend(field("DATA", &position.iter().chain(&[0.0; 3]).flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>()));
+        data
+    }
+
+    #[test]
+    fn quarters_share_their_edges() {
+        let values : Vec<usize> = (0..VERTICES * VERTICES).collect();
+
+        let bottom_left = quarter(&values, 0, 0);
+        assert_eq!(bottom_left.len(), 33 * 33);
+        assert_eq!(bottom_left[0], 0);
+        assert_eq!(bottom_left[32], 32);
+        assert_eq!(bottom_left[33], VERTICES);
+
+        let top_right = quarter(&values, 1, 1);
+        assert_eq!(top_right[0], 32 * VERTICES + 32);
+        assert_eq!(top_right[33 * 33 - 1], VERTICES * VERTICES - 1);
+
+        // The right column of one is the left column of the next:
+        let bottom_right = quarter(&values, 1, 0);
+        for row in 0..33 {
+            assert_eq!(bottom_left[row * 33 + 32], bottom_right[row * 33]);
+        }
+        // And the top row of one is the bottom row of the one above:
+        let top_left = quarter(&values, 0, 1);
+        assert_eq!(bottom_left[32 * 33..], top_left[..33]);
+    }
+
+    #[test]
+    fn texture_blocks_to_rows() {
+        let stored : Vec<u16> = (0..256).collect();
+        let rows = texture_rows(&stored);
+
+        // The first block of 4 x 4 is the bottom left corner:
+        assert_eq!(rows[..4], [0, 1, 2, 3]);
+        assert_eq!(rows[16..20], [4, 5, 6, 7]);
+        // The second is to its right:
+        assert_eq!(rows[4..8], [16, 17, 18, 19]);
+        // The fifth starts the next row of blocks:
+        assert_eq!(rows[4 * 16], 64);
+        assert_eq!(rows[255], 255);
+    }
+
+    #[test]
+    fn cell_references() {
+        let mut data = field("NAME", &zstring(""));
+        data.extend(field("RGNN", &zstring("Bitter Coast Region")));
+        data.extend(reference(1, "flora_tree_ai_01", [1.0, 2.0, 3.0]));
+        data.extend(field("XSCL", &2.0f32.to_le_bytes()));
+        data.extend(field("NAM0", &1u32.to_le_bytes()));
+        // Changes the master's second reference:
+        data.extend(reference(0x01000002, "flora_tree_ai_01", [0.0; 3]));
+        data.extend(field("DELE", &0u32.to_le_bytes()));
+        // From a master that isn't loaded:
+        data.extend(reference(0x02000003, "rock", [4.0, 5.0, 6.0]));
+
+        let (region, references) = read_cell(&data, 3, &[Some(1), None]).unwrap();
+        assert_eq!(region.as_deref(), Some("Bitter Coast Region"));
+        assert_eq!(references, vec![
+            ((3, 1), Some(PlacedObject {
+                base: "flora_tree_ai_01".into(),
+                position: [1.0, 2.0, 3.0],
+                rotation: [0.0; 3],
+                scale: 2.0,
+                persistent: true
+            })),
+            ((1, 2), None),
+            ((3, 0x02000003), Some(PlacedObject {
+                base: "rock".into(),
+                position: [4.0, 5.0, 6.0],
+                rotation: [0.0; 3],
+                scale: 1.0,
+                persistent: false
+            }))
+        ]);
+    }
+
+    #[test]
+    fn region_weathers() {
+        let mut data = field("NAME", &zstring("Ashlands Region"));
+        data.extend(field("WEAT", &[10, 0, 0, 0, 0, 0, 80, 10, 0, 0]));
+
+        let region = read_region(data).unwrap();
+        assert_eq!(region.edid, "Ashlands Region");
+        assert_eq!(region.weathers, vec![(1, 10), (7, 80), (8, 10)]);
+    }
+}
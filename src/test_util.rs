@@ -0,0 +1,11 @@
+//! Helpers shared by the tests of several modules.
+
+use std::{fs, path::PathBuf};
+
+/// An empty folder of its own for each test.
+pub fn test_dir(name : &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("skyrim2minecraft-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
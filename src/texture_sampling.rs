@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, HashMap};
+
+use clap::ValueEnum;
+use toml::{Table, Value};
+
+use crate::{block_colors, bsa::{self, DataFiles}, dds, error::{Error, Result}, esm::{DataVersion, WorldRecords}, mapping::Mapping};
+
+/// Which color of a texture its block is picked by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SampleColor {
+	/// The average of every pixel.
+	#[default]
+	Average,
+	/// The most common color, after grouping similar ones together. Small spots of another color (like flowers in grass) don't pull it off.
+	Dominant,
+}
+
+impl SampleColor {
+	fn name(self) -> &'static str {
+		match self {
+			SampleColor::Average => "average",
+			SampleColor::Dominant => "dominant",
+		}
+	}
+}
+
+/// A mapping written by [`generate_mapping`].
+#[derive(Debug, Clone)]
+pub struct GeneratedMapping {
+	pub text : String,
+	/// How many of the land textures the template didn't have yet got a block.
+	pub matched : usize,
+	/// How many land textures the template didn't have yet.
+	pub new_land_textures : usize,
+}
+
+/// What came of sampling a land texture's diffuse texture.
+enum Sample {
+	Color { path : String, color : [u8; 3] },
+	Missing { paths : Vec<String> },
+	Broken { path : String, error : String },
+}
+
+/// The names of the palettes [`generate_mapping`] can pick blocks from.
+pub fn palette_names() -> impl Iterator<Item = &'static str> {
+	block_colors::FAMILIES.iter().map(|(name, _)| *name)
+}
+
+/// The color of a DDS texture.
+pub fn texture_color(data : &[u8], sample : SampleColor) -> Result<[u8; 3]> {
+	let image = dds::read_image(data)?;
+
+	let mean = |pixels : &mut dyn Iterator<Item = &[u8; 4]>| -> [u8; 3] {
+		let (mut sums, mut count) = ([0u64; 3], 0u64);
+		for pixel in pixels {
+			for (sum, channel) in sums.iter_mut().zip(pixel) {
+				*sum += *channel as u64;
+			}
+			count += 1;
+		}
+		sums.map(|sum| (sum / count.max(1)) as u8)
+	};
+
+	Ok(match sample {
+		SampleColor::Average => mean(&mut image.pixels.iter()),
+		SampleColor::Dominant => {
+			// Grouped by their top 3 bits of each channel, and the average of the biggest group:
+			let bin = |pixel : &[u8; 4]| ((pixel[0] >> 5) as usize) << 6 | ((pixel[1] >> 5) as usize) << 3 | (pixel[2] >> 5) as usize;
+			let mut counts = [0usize; 512];
+			for pixel in &image.pixels {
+				counts[bin(pixel)] += 1;
+			}
+			let biggest = (0..counts.len()).max_by_key(|i| counts[*i]).unwrap_or(0);
+			mean(&mut image.pixels.iter().filter(|pixel| bin(pixel) == biggest))
+		}
+	})
+}
+
+/// Where the game looks for a land texture's diffuse texture, in the order we try them.
+fn texture_paths(version : DataVersion, texture : &str) -> Vec<String> {
+	let path = bsa::normalize(texture);
+	if path.starts_with("textures\\") {
+		return vec![path];
+	}
+
+	match version {
+		// Oblivion's LTEX names a texture in textures\landscape:
+		DataVersion::Oblivion => vec![format!("textures\\landscape\\{path}")],
+		// Morrowind's names a .tga (or .bmp), which the game swaps out for a .dds of the same name:
+		DataVersion::Morrowind => {
+			let stem = path.rsplit_once('.').map_or(path.as_str(), |(stem, _)| stem);
+			vec![format!("textures\\{stem}.dds")]
+		},
+		_ => vec![format!("textures\\{path}")]
+	}
+}
+
+/// Adds every land texture in `records` to the `[land_textures]` of the mapping in `template`, with the block from the palettes in `families`
+/// (see [`palette_names`]) closest to the color of its diffuse texture in `data`. Land textures the template already has are left as they are.
+/// Each line notes the texture and color it came from, and land textures whose texture can't be found or read are noted in comments,
+/// so the result is ready to be edited by hand.
+pub fn generate_mapping(template : &str, records : &WorldRecords, data : &DataFiles, version : DataVersion, sample : SampleColor, families : &[String]) -> Result<GeneratedMapping> {
+	if families.is_empty() {
+		return Err(Error::invalid_data("No palettes to pick blocks from."));
+	}
+
+	let mut palette : Vec<(&str, [u8; 3])> = Vec::new();
+	for name in families {
+		let family = block_colors::family(name).ok_or_else(|| {
+			Error::invalid_data(format!("Unknown palette `{name}`, expected one of: {}", palette_names().collect::<Vec<_>>().join(", ")))
+		})?;
+		palette.extend(family.iter().map(|(block, color)| (*block, block_colors::rgb(*color))));
+	}
+
+	let template_table : Table = template.parse().map_err(|e| Error::invalid_data(format!("Could not read the mapping to add to: {e}")))?;
+	let existing : Vec<String> = match template_table.get("land_textures") {
+		Some(Value::Table(table)) => table.keys().map(|key| key.to_lowercase()).collect(),
+		_ => Vec::new()
+	};
+
+	// By editor ID, so the output is the same every time:
+	let mut land_textures = BTreeMap::new();
+	for land_texture in records.land_textures.values() {
+		if !land_texture.edid.is_empty() && !existing.contains(&land_texture.edid.to_lowercase()) {
+			land_textures.entry(land_texture.edid.to_lowercase()).or_insert(land_texture);
+		}
+	}
+
+	// Many land textures share a texture:
+	let mut samples : HashMap<String, Sample> = HashMap::new();
+	let mut lines = vec![
+		format!("# Generated from the {} color of each land texture's diffuse texture, with the closest {} block.", sample.name(), families.join(" or ")),
+		"# The texture and color each one came from are noted after it. Edit or remove any that look off.".into(),
+	];
+	let mut matched = 0;
+
+	for land_texture in land_textures.values() {
+		let key = toml_key(&land_texture.edid);
+		let Some(texture) = land_texture.texture.as_deref().filter(|t| !t.is_empty()) else {
+			lines.push(format!("# {key}: has no texture"));
+			continue;
+		};
+
+		let result = samples.entry(bsa::normalize(texture)).or_insert_with(|| {
+			let paths = texture_paths(version, texture);
+			for path in &paths {
+				match data.read(path) {
+					Ok(Some(data)) => return match texture_color(&data, sample) {
+						Ok(color) => Sample::Color { path: path.clone(), color },
+						Err(e) => Sample::Broken { path: path.clone(), error: e.to_string() }
+					},
+					Ok(None) => {},
+					Err(e) => return Sample::Broken { path: path.clone(), error: e.to_string() }
+				}
+			}
+			Sample::Missing { paths }
+		});
+
+		match result {
+			Sample::Color { path, color } => {
+				let Some(i) = block_colors::nearest(*color, &palette) else {
+					continue;
+				};
+				lines.push(format!("{key} = \"{}\" # {path}, #{:02x}{:02x}{:02x}", palette[i].0, color[0], color[1], color[2]));
+				matched += 1;
+			},
+			Sample::Missing { paths } => lines.push(format!("# {key}: {} isn't in the Data folder", paths.join(" or "))),
+			Sample::Broken { path, error } => lines.push(format!("# {key}: could not read {path}: {error}"))
+		}
+	}
+
+	// Adding to a mapping we generated before shouldn't repeat its comments:
+	lines.retain(|line| !template.lines().any(|l| l.trim() == line));
+
+	// Right under the [land_textures] header (and the comments that follow it), or in a new section at the end:
+	let mut output : Vec<&str> = template.lines().collect();
+	let at = match output.iter().position(|line| line.trim() == "[land_textures]") {
+		Some(header) => (header + 1..output.len()).find(|i| !output[*i].trim_start().starts_with('#')).unwrap_or(output.len()),
+		None => {
+			output.extend(["", "[land_textures]"]);
+			output.len()
+		}
+	};
+	output.splice(at..at, lines.iter().map(String::as_str));
+
+	let mut text = output.join("\n");
+	text.push('\n');
+
+	// So a template we can't add to shows up here, rather than when converting:
+	Mapping::parse(&text, "generated mapping")?;
+	Ok(GeneratedMapping {
+		text,
+		matched,
+		new_land_textures: land_textures.len()
+	})
+}
+
+/// An editor ID as a TOML key, quoted if it has to be.
+fn toml_key(edid : &str) -> String {
+	if edid.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+		edid.into()
+	} else {
+		Value::String(edid.into()).to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{esm::LandTexture, test_util::test_dir};
+
+	#[test]
+	fn palettes_to_pick_from() {
+		let dir = test_dir("palettes");
+		let data = DataFiles::open(&dir).unwrap();
+		let template = Mapping::default_text(DataVersion::Skyrim);
+		let generate = |families : &[&str]| {
+			let families : Vec<String> = families.iter().map(|f| f.to_string()).collect();
+			generate_mapping(template, &WorldRecords::default(), &data, DataVersion::Skyrim, SampleColor::Average, &families)
+		};
+
+		assert_eq!(generate(&["wool", "natural"]).unwrap().new_land_textures, 0);
+		for families in [&[][..], &["natural", "glass"]] {
+			let error = generate(families).unwrap_err();
+			assert!(matches!(&error, Error::Io { source, .. } if source.kind() == std::io::ErrorKind::InvalidData), "{error:?}");
+		}
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	/// A 4 x 4 BC1 DDS of one 16-bit color.
+	fn solid_dds(color : u16) -> Vec<u8> {
+		let mut data = vec![0; 128];
+		data[..4].copy_from_slice(b"DDS ");
+		data[4..8].copy_from_slice(&124u32.to_le_bytes());
+		data[12..16].copy_from_slice(&4u32.to_le_bytes());
+		data[16..20].copy_from_slice(&4u32.to_le_bytes());
+		data[76..80].copy_from_slice(&32u32.to_le_bytes());
+		data[80..84].copy_from_slice(&4u32.to_le_bytes());
+		data[84..88].copy_from_slice(b"DXT1");
+		data.extend([color.to_le_bytes(), color.to_le_bytes(), [0; 2], [0; 2]].concat());
+		data
+	}
+
+	#[test]
+	fn generated_land_textures() {
+		let dir = test_dir("generated-land-textures");
+		std::fs::create_dir_all(dir.join("textures/landscape")).unwrap();
+		std::fs::write(dir.join("textures/landscape/snow01.dds"), solid_dds(0xFFFF)).unwrap();
+		let data = DataFiles::open(&dir).unwrap();
+
+		let land_texture = |edid : &str, texture : &str| LandTexture {
+			edid: edid.into(),
+			texture: Some(texture.into()),
+			texture_set: None
+		};
+		let records = WorldRecords {
+			land_textures: HashMap::from([
+				(1, land_texture("LSnow01", "Landscape\\Snow01.dds")),
+				(2, land_texture("LMissing01", "landscape\\missing01.dds")),
+				(3, land_texture("LDirt01", "landscape\\dirt01.dds")),
+			]),
+			..WorldRecords::default()
+		};
+		let template = "underground = \"stone\"\nfallback = \"grass_block\"\ndefault_water = \"water\"\n\n[land_textures]\n# By hand:\nLDirt01 = \"dirt\"\n";
+		let generate = |template : &str| generate_mapping(template, &records, &data, DataVersion::Skyrim, SampleColor::Average, &["natural".into()]).unwrap();
+
+		// Under the comments after [land_textures], leaving LDirt01 be:
+		let first = generate(template);
+		let generated = [
+			"# Generated from the average color of each land texture's diffuse texture, with the closest natural block.",
+			"# The texture and color each one came from are noted after it. Edit or remove any that look off.",
+			"# LMissing01: textures\\landscape\\missing01.dds isn't in the Data folder",
+			"LSnow01 = \"minecraft:snow_block\" # textures\\landscape\\snow01.dds, #ffffff",
+		];
+		assert_eq!(first.text, template.replace("# By hand:\n", &format!("# By hand:\n{}\n", generated.join("\n"))));
+		assert_eq!((first.matched, first.new_land_textures), (1, 2));
+
+		// Again, only the missing texture is new, and its comment is already there:
+		let second = generate(&first.text);
+		assert_eq!(second.text, first.text);
+		assert_eq!((second.matched, second.new_land_textures), (0, 1));
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
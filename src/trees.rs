@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use crate::{error::Result, esm::Reference, mapping::TreeRule, regions::RegionWriter, world_gen::{Block, Scale}};
+
+/// The Minecraft tree a placed object turns into, which picks its blocks and shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Species {
+	Oak,
+	Spruce,
+	Birch,
+	Jungle,
+	Acacia,
+	DarkOak,
+}
+
+impl Species {
+	pub const ALL : [Species; 6] = [Species::Oak, Species::Spruce, Species::Birch, Species::Jungle, Species::Acacia, Species::DarkOak];
+
+	/// As in the mapping file, and Minecraft's block names.
+	pub fn name(self) -> &'static str {
+		match self {
+			Species::Oak => "oak",
+			Species::Spruce => "spruce",
+			Species::Birch => "birch",
+			Species::Jungle => "jungle",
+			Species::Acacia => "acacia",
+			Species::DarkOak => "dark_oak",
+		}
+	}
+
+	pub fn from_name(name : &str) -> Option<Species> {
+		Self::ALL.into_iter().find(|s| s.name() == name)
+	}
+
+	/// How tall the tree is in Skyrim Units, when the mapping doesn't say.
+	pub fn default_height(self) -> f32 {
+		match self {
+			Species::Oak | Species::DarkOak => 1024.0,
+			Species::Birch => 1280.0,
+			Species::Spruce => 1536.0,
+			Species::Jungle => 1792.0,
+			Species::Acacia => 896.0,
+		}
+	}
+
+	fn log(self) -> Block {
+		Block {
+			name: format!("minecraft:{}_log", self.name()),
+			properties: HashMap::from([("axis".into(), "y".into())])
+		}
+	}
+
+	/// Persistent, so they don't decay when they end up too far from a log.
+	fn leaves(self) -> Block {
+		Block {
+			name: format!("minecraft:{}_leaves", self.name()),
+			properties: HashMap::from([("persistent".into(), "true".into())])
+		}
+	}
+
+	/// Dark oaks and jungle trees get 2 x 2 trunks, like in Minecraft.
+	fn trunk_width(self) -> i32 {
+		match self {
+			Species::DarkOak | Species::Jungle => 2,
+			_ => 1
+		}
+	}
+}
+
+/// A block of a tree, relative to the bottom of its trunk (or in the world).
+#[derive(Debug, Clone, Copy)]
+struct TreeBlock {
+	pos : [i32; 3],
+	log : bool,
+}
+
+/// Draws a tree where an object is placed, standing on the block its position is in.
+/// Logs go over whatever is there, but leaves only fill in air, so they don't cut into the hillside.
+pub fn place_tree(reference : &Reference, rule : &TreeRule, scale : Scale, writer : &RegionWriter) -> Result<()> {
+	let base_x = (reference.position[0] as f64 / scale.horizontal).floor() as i32;
+	let base_z = (reference.position[1] as f64 / scale.horizontal).floor() as i32;
+	// Shifted up by one like the terrain, which makes this the first block above the ground the tree stands on:
+	let base_y = (reference.position[2] as f64 / scale.vertical + 1.0).round() as i32;
+
+	let height = (rule.height as f64 * reference.scale as f64 / scale.vertical).round().max(3.0) as i32;
+
+	let (log_block, leaves_block) = (rule.species.log(), rule.species.leaves());
+	let (logs, leaves) : (Vec<TreeBlock>, Vec<TreeBlock>) = shape(rule, height).into_iter().partition(|block| block.log);
+
+	let in_world = |blocks : Vec<TreeBlock>, block| blocks.into_iter().map(move |TreeBlock { pos: [dx, dy, dz], .. }| (base_x + dx, base_y + dy, base_z + dz, block));
+	writer.draw_blocks(in_world(logs, &log_block), true)?;
+	writer.draw_blocks(in_world(leaves, &leaves_block), false)?;
+	Ok(())
+}
+
+/// The blocks of a tree `height` blocks tall. Logs come first, so the leaves can go around them.
+fn shape(rule : &TreeRule, height : i32) -> Vec<TreeBlock> {
+	let width = rule.species.trunk_width();
+
+	// Leaves within `radius` of the column, counting in a bit of the corners so the edges look less like a staircase:
+	let disc = |y : i32, radius : i32, blocks : &mut Vec<TreeBlock>| {
+		for dz in -radius..=radius + width - 1 {
+			for dx in -radius..=radius + width - 1 {
+				// Distance to the closest trunk column:
+				let (ox, oz) = (dx - dx.clamp(0, width - 1), dz - dz.clamp(0, width - 1));
+				if ox * ox + oz * oz <= radius * radius + radius {
+					blocks.push(TreeBlock { pos: [dx, y, dz], log: false });
+				}
+			}
+		}
+	};
+
+	let mut blocks = Vec::new();
+	let trunk_top = match rule.species {
+		// Spruce leaves cover the top of the trunk:
+		Species::Spruce => height,
+		_ => height - 1
+	};
+	for y in 0..trunk_top {
+		for dz in 0..width {
+			for dx in 0..width {
+				blocks.push(TreeBlock { pos: [dx, y, dz], log: true });
+			}
+		}
+	}
+
+	if !rule.leaves {
+		return blocks;
+	}
+
+	match rule.species {
+		// A cone, from a quarter of the way up to a point on top:
+		Species::Spruce => {
+			let bottom = height / 4;
+			let widest = (height / 4).max(2) as f32;
+			for y in bottom..=height {
+				let radius = ((height - y) as f32 / (height - bottom).max(1) as f32 * widest).round() as i32;
+				disc(y, radius, &mut blocks);
+			}
+		},
+		// A wide, flat canopy on top:
+		Species::Acacia => {
+			let radius = (height / 3).max(2);
+			disc(height - 1, radius, &mut blocks);
+			disc(height, radius - 1, &mut blocks);
+		},
+		// A ball around the top of the trunk:
+		_ => {
+			let radius = match rule.species {
+				Species::Birch => (height / 5).max(2),
+				Species::DarkOak => (height / 3).max(3),
+				_ => (height / 4).max(2)
+			};
+			let middle = height - radius;
+			for dy in -radius..=radius {
+				// Narrower towards the top and bottom:
+				let layer = ((radius * radius - dy * dy) as f32).sqrt().round() as i32;
+				disc(middle + dy, layer, &mut blocks);
+			}
+		}
+	}
+	blocks
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{esm::{BaseObject, DataVersion}, mapping::Mapping, test_util::test_dir};
+
+	#[test]
+	fn skyrim_species() {
+		let mapping = Mapping::default_for(DataVersion::Skyrim).unwrap();
+		let tree = |ty : &str, edid : &str| mapping.tree(&BaseObject {
+			ty: ty.into(),
+			edid: edid.into(),
+			model: None
+		}).map(|tree| (tree.species, tree.leaves));
+
+		assert_eq!(tree("TREE", "TreePineForest03"), Some((Species::Spruce, true)));
+		assert_eq!(tree("TREE", "TreeAspen01"), Some((Species::Birch, true)));
+		assert_eq!(tree("TREE", "TreeReachTree01"), Some((Species::DarkOak, true)));
+		// Dead pines are bare spruces, since "dead" comes before "pine":
+		assert_eq!(tree("TREE", "TreePineForestDead01"), Some((Species::Spruce, false)));
+		assert_eq!(tree("TREE", "TreeFloraThistle01"), None);
+		// TREE records nothing matches get the default, but other records don't:
+		assert_eq!(tree("TREE", "TreeUnknown01"), Some((Species::Oak, true)));
+		assert_eq!(tree("STAT", "TreeUnknown01"), None);
+	}
+
+	#[test]
+	fn scaled_trunks() {
+		let dir = test_dir("scaled-trunks");
+		let rule = TreeRule {
+			species: Species::Spruce,
+			height: Species::Spruce.default_height(),
+			leaves: false
+		};
+
+		// The logs of a tree placed in the middle of chunk (0, 0) at `scale` (its XSCL), from the bottom up:
+		let trunk = |scale : f32| {
+			let writer = RegionWriter::new(&dir, Scale::default());
+			let reference = Reference {
+				form_id: 0,
+				base: 0,
+				position: [512.0, 512.0, 0.0],
+				rotation: [0.0; 3],
+				scale,
+				persistent: false
+			};
+			place_tree(&reference, &rule, Scale::default(), &writer).unwrap();
+
+			writer.edit((0, 0), |chunks| {
+				(1..100).filter(|y| chunks[&(0, 0)].block_at(8, *y, 8).is_some_and(|b| b.name == "minecraft:spruce_log")).collect::<Vec<i32>>()
+			}).unwrap()
+		};
+
+		// 1536 units is 24 blocks, starting on the block above the ground:
+		assert_eq!(trunk(1.0), (1..=24).collect::<Vec<_>>());
+		assert_eq!(trunk(2.0), (1..=48).collect::<Vec<_>>());
+		// Never less than 3 blocks:
+		assert_eq!(trunk(0.05), [1, 2, 3]);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
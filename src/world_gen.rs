@@ -1,84 +1,258 @@
-use std::{collections::HashMap, fs::{File, OpenOptions}, hash::Hash, io::Seek, path::Path};
+use std::collections::{BTreeMap, HashMap};
 
-use serde::Serialize;
+use fastnbt::LongArray;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
-use file_guard::Lock;
-use crate::esm::Land;
+use crate::{error::Result, esm::{Land, WorldRecords}, mapping::{BlockRule, Mapping}, regions::RegionWriter};
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all="PascalCase")]
 pub struct Block {
 	pub name : String,
 
+	#[serde(default)]
 	pub properties : HashMap<String, String>
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct BlockState {
 	pub palette : Vec<Block>,
-	pub data : Option<Vec<i64>>
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub data : Option<LongArray>
 }
 
+const SECTION_VOLUME : usize = 16 * 16 * 16;
+
 impl BlockState {
+	/// Every block starts out as the first entry of the palette.
 	pub fn new_from_palette(palette : Vec<Block>) -> BlockState {
-		if palette.len() >= 16 {
-			panic!("Palettes of length 16 or greater are not currently supported.");
-		}
-
-		let dat = vec![0; 256];
+		let data = if palette.len() > 1 {
+			Some(Self::pack(&[0; SECTION_VOLUME], Self::bits_per_entry(palette.len())))
+		} else {
+			None
+		};
 
 		BlockState {
 			palette,
-			data: Some(dat)
+			data
 		}
 	}
 
-	pub fn draw_height(&mut self, idx : i8, x : usize, z : usize, start_y : usize, end_y : usize) {
-		let dat = self.data.as_mut().unwrap();
+	/// Anvil uses at least 4 bits per block, and however many more it takes to index the whole palette.
+	fn bits_per_entry(palette_len : usize) -> usize {
+		std::cmp::max(4, (usize::BITS - palette_len.saturating_sub(1).leading_zeros()) as usize)
+	}
 
-		let z_shift = (x % 16) * 4;
+	fn block_index(x : usize, y : usize, z : usize) -> usize {
+		x + (z * 16) + (y * 256)
+	}
 
-		for y in start_y..end_y {
-			let zy_idx = z + (y * 16);
-			let curr = dat[zy_idx];
+	/// Entries never span across two longs, so any bits left over at the top of a long are padding.
+	fn pack(indices : &[usize], bits : usize) -> LongArray {
+		let per_long = 64 / bits;
+
+		let data = indices.chunks(per_long).map(|entries| {
+			entries.iter().enumerate().fold(0, |long, (i, idx)| {
+				long | ((*idx as i64) << (i * bits))
+			})
+		}).collect();
+
+		LongArray::new(data)
+	}
+
+	fn unpack(&self, bits : usize) -> Vec<usize> {
+		match &self.data {
+			Some(dat) => {
+				let per_long = 64 / bits;
+				let mask = (1u64 << bits) - 1;
 
-			let zeroed = curr & !(0b1111 << z_shift);
-			dat[zy_idx] = zeroed | ((idx as i64 & 0b1111) << z_shift);
+				(0..SECTION_VOLUME).map(|i| {
+					((dat[i / per_long] as u64 >> ((i % per_long) * bits)) & mask) as usize
+				}).collect()
+			},
+			None => vec![0; SECTION_VOLUME]
 		}
 	}
 
-	pub fn fill_layer(&mut self, idx : i8, y : usize) {
-		let dat = self.data.as_mut().unwrap();
+	/// Finds the block in the palette, adding it (and repacking the data if we need more bits per block) if it isn't there.
+	pub fn index_of(&mut self, block : &Block) -> usize {
+		if let Some(idx) = self.palette.iter().position(|b| b == block) {
+			return idx;
+		}
+
+		let old_bits = Self::bits_per_entry(self.palette.len());
+		let indices = self.unpack(old_bits);
+
+		self.palette.push(block.clone());
+
+		let new_bits = Self::bits_per_entry(self.palette.len());
+		if self.data.is_none() || new_bits != old_bits {
+			self.data = Some(Self::pack(&indices, new_bits));
+		}
+
+		self.palette.len() - 1
+	}
+
+	/// Index into the palette of the block at a position in the section.
+	pub fn get(&self, x : usize, y : usize, z : usize) -> usize {
+		let Some(dat) = &self.data else {
+			return 0;
+		};
+
+		let bits = Self::bits_per_entry(self.palette.len());
+		let per_long = 64 / bits;
+		let i = Self::block_index(x, y, z);
+		((dat[i / per_long] as u64 >> ((i % per_long) * bits)) & ((1u64 << bits) - 1)) as usize
+	}
+
+	pub fn set(&mut self, x : usize, y : usize, z : usize, idx : usize) {
+		let bits = Self::bits_per_entry(self.palette.len());
 
-		let idx_64 = idx as i64;
+		let dat = match self.data.as_mut() {
+			Some(dat) => dat,
+			// Uniform sections only have the one block:
+			None => return
+		};
+
+		let per_long = 64 / bits;
+		let i = Self::block_index(x, y, z);
+		let shift = (i % per_long) * bits;
+		let mask = ((1u64 << bits) - 1) as i64;
 
-		let mut full = 0;
+		let zeroed = dat[i / per_long] & !(mask << shift);
+		dat[i / per_long] = zeroed | (((idx as i64) & mask) << shift);
+	}
 
-		for i in 0..16 {
-			full |= idx_64 << (i * 4);
+	pub fn draw_height(&mut self, idx : usize, x : usize, z : usize, start_y : usize, end_y : usize) {
+		for y in start_y..end_y {
+			self.set(x, y, z, idx);
 		}
+	}
 
+	pub fn fill_layer(&mut self, idx : usize, y : usize) {
 		for z in 0..16 {
-			let zy_idx = z + (y * 16);
-			dat[zy_idx] = full;
+			for x in 0..16 {
+				self.set(x, y, z, idx);
+			}
 		}
 	}
+
+	/// Drops unused palette entries, and collapses sections made of a single block down to just a palette.
+	pub fn compact(&mut self) {
+		if self.data.is_none() {
+			return;
+		}
+
+		let indices = self.unpack(Self::bits_per_entry(self.palette.len()));
+
+		let mut used = vec![false; self.palette.len()];
+		for idx in &indices {
+			used[*idx] = true;
+		}
+
+		let mut remap = vec![0; self.palette.len()];
+		let mut palette = Vec::new();
+		for (i, block) in std::mem::take(&mut self.palette).into_iter().enumerate() {
+			if used[i] {
+				remap[i] = palette.len();
+				palette.push(block);
+			}
+		}
+		self.palette = palette;
+
+		self.data = if self.palette.len() > 1 {
+			let remapped : Vec<usize> = indices.iter().map(|idx| remap[*idx]).collect();
+			Some(Self::pack(&remapped, Self::bits_per_entry(self.palette.len())))
+		} else {
+			None
+		};
+	}
 }
 
-#[derive(Serialize, Debug)]
+/// The biome of each 4 x 4 x 4 block cube in a section.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Biomes {
 	pub palette : Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub data : Option<LongArray>,
 }
 
+const BIOME_VOLUME : usize = 4 * 4 * 4;
+
 impl Default for Biomes {
 	fn default() -> Self {
 		Self {
-			palette: vec!["minecraft:plains".into()]
+			palette: vec!["minecraft:plains".into()],
+			data: None
+		}
+	}
+}
+
+impl Biomes {
+	/// Unlike blocks, biomes take only as many bits as it takes to index the palette.
+	fn bits_per_entry(palette_len : usize) -> usize {
+		(usize::BITS - palette_len.saturating_sub(1).leading_zeros()) as usize
+	}
+
+	fn unpack(&self) -> Vec<usize> {
+		match &self.data {
+			Some(dat) if self.palette.len() > 1 => {
+				let bits = Self::bits_per_entry(self.palette.len());
+				let per_long = 64 / bits;
+				let mask = (1u64 << bits) - 1;
+
+				(0..BIOME_VOLUME).map(|i| {
+					((dat[i / per_long] as u64 >> ((i % per_long) * bits)) & mask) as usize
+				}).collect()
+			},
+			_ => vec![0; BIOME_VOLUME]
 		}
 	}
+
+	/// Sets the biome of the given columns (`x` and `z` from 0 to 3, in steps of 4 blocks) all the way up the section.
+	/// Biomes that end up unused are dropped from the palette.
+	pub fn set_columns(&mut self, columns : &[(usize, usize)], biome : &str) {
+		if self.data.is_none() && self.palette.len() == 1 && self.palette[0] == biome {
+			return;
+		}
+
+		let mut indices = self.unpack();
+		let idx = self.palette.iter().position(|b| b == biome).unwrap_or_else(|| {
+			self.palette.push(biome.into());
+			self.palette.len() - 1
+		});
+		for (x, z) in columns {
+			for y in 0..4 {
+				indices[x + z * 4 + y * 16] = idx;
+			}
+		}
+
+		let mut used = vec![false; self.palette.len()];
+		for idx in &indices {
+			used[*idx] = true;
+		}
+
+		let mut remap = vec![0; self.palette.len()];
+		let mut palette = Vec::new();
+		for (i, biome) in std::mem::take(&mut self.palette).into_iter().enumerate() {
+			if used[i] {
+				remap[i] = palette.len();
+				palette.push(biome);
+			}
+		}
+		self.palette = palette;
+
+		self.data = if self.palette.len() > 1 {
+			let remapped : Vec<usize> = indices.iter().map(|idx| remap[*idx]).collect();
+			Some(BlockState::pack(&remapped, Self::bits_per_entry(self.palette.len())))
+		} else {
+			None
+		};
+	}
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Section {
 	#[serde(rename="Y")]
 	pub y : i8,
@@ -91,7 +265,7 @@ pub struct Section {
 /// fastanvil doesn't contain an implementation that's good enough for us.
 /// 
 /// Luckily, fastnbt can handle serialization for us.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all="PascalCase")]
 pub struct Chunk {
 	pub data_version : i32,
@@ -109,13 +283,11 @@ pub struct Chunk {
 	pub sections : Vec<Section>,
 }
 
-const MIN_Y : i32 = -592;
+pub(crate) const MIN_Y : i32 = -592;
+/// The top of the world, per the datapack's dimension height.
+pub(crate) const MAX_Y : i32 = MIN_Y + 1216;
 
 impl Chunk {
-	pub fn into_arr(_ : usize) -> Chunk {
-		Chunk::default()
-	}
-
 	pub fn default_palette() -> Vec<Block> {
 		vec![
 			Block { 
@@ -137,19 +309,20 @@ impl Chunk {
 		]
 	}
 
-	pub fn draw_height(&mut self, x : usize, z : usize, start_height : f32, end_height : f32, idx : i8) {
+	pub fn draw_height(&mut self, x : usize, z : usize, start_height : f32, end_height : f32, block : &Block) {
 		let mut i = start_height;
 		while i < end_height.floor() {
 			let curr_y = i as i32;
 
-			let next_idx : usize = (((curr_y - MIN_Y) >> 4) as i8).try_into().expect(&format!("Could not convert index {curr_y}."));
+			let next_idx : usize = (((curr_y - MIN_Y) >> 4) as i8).try_into().unwrap_or_else(|_| panic!("Could not convert index {curr_y}."));
 			let matching_section = self.sections.get_mut(next_idx);
 			
 			let section = if let Some(s) = matching_section {
 				s
 			} else {
-				// Add sections until we hit the target height:
-				let start = self.sections.last().expect("Could not get last section.").y;
+				// Add sections until we hit the target height. They carry on with the biomes of the one below:
+				let last = self.sections.last().expect("Could not get last section.");
+				let (start, biomes) = (last.y, last.biomes.clone());
 				
 				let start_idx = self.sections.len() - 1;
 				for j in start_idx..next_idx {
@@ -158,7 +331,7 @@ impl Chunk {
 					self.sections.push(Section{
 						y,
 						block_states: BlockState::new_from_palette(Self::default_palette()),
-						biomes: Biomes::default()
+						biomes: biomes.clone()
 					});
 				}
 				self.sections.last_mut().unwrap()
@@ -167,10 +340,32 @@ impl Chunk {
 			let height_start = curr_y.rem_euclid(16) as usize;
 			let height_draw = std::cmp::min(16 - height_start, (end_height - i).abs().round_ties_even() as usize);
 
+			let idx = section.block_states.index_of(block);
 			section.block_states.draw_height(idx, x, z, height_start, height_start + height_draw);
 			i += height_draw as f32;
 		}
 	}
+
+	/// The block at y-level `y` of a column. `None` above the sections drawn so far, which is all air.
+	pub fn block_at(&self, x : usize, y : i32, z : usize) -> Option<&Block> {
+		let section = self.sections.get(usize::try_from((y - MIN_Y) >> 4).ok()?)?;
+		let states = &section.block_states;
+		states.palette.get(states.get(x, y.rem_euclid(16) as usize, z))
+	}
+
+	/// Sets the biome of the given columns (`x` and `z` from 0 to 3, in steps of 4 blocks) in every section drawn so far.
+	/// Sections drawn above them later take the biomes of the section below.
+	pub fn set_biome(&mut self, columns : &[(usize, usize)], biome : &str) {
+		for section in self.sections.iter_mut() {
+			section.biomes.set_columns(columns, biome);
+		}
+	}
+
+	pub fn compact(&mut self) {
+		for section in self.sections.iter_mut() {
+			section.block_states.compact();
+		}
+	}
 }
 
 impl Default for Chunk {
@@ -191,133 +386,569 @@ impl Default for Chunk {
 				Section {
 					y: (MIN_Y >> 4) as i8,
 					block_states: bottom_block,
-					biomes: Biomes {
-						palette: vec!["minecraft:plains".into()],
-					}
+					biomes: Biomes::default()
 				}
 			]
 		}
 	}
 }
 
-pub fn parse_land(land : Land, out_folder : &Path) {
+/// How many Skyrim Units make up one Minecraft block.
+#[derive(Debug, Clone, Copy)]
+pub struct Scale {
+	pub horizontal : f64,
+	pub vertical : f64,
+}
+
+impl Default for Scale {
+	fn default() -> Self {
+		Self {
+			horizontal: 64.0,
+			vertical: 64.0
+		}
+	}
+}
+
+/// A cell is 32 x 32 vertex spans of 128 Skyrim Units each.
+pub const CELL_SIZE : f64 = 4096.0;
+const VERTEX_SPACING : f64 = 128.0;
+
+/// How block heights are worked out from the vertices around them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Interpolation {
+	/// Every block takes the height of its closest vertex, which makes 2 x 2 plateaus at the default scale.
+	Nearest,
+	/// Blends the four vertices around the block.
+	#[default]
+	Bilinear,
+	/// Catmull-Rom spline through the sixteen vertices around the block, for rounder hills.
+	Bicubic,
+}
+
+impl Interpolation {
+	/// Height in Skyrim Units at column `u`, row `v` (both in vertices, from 0 to 32) of a cell's 33 x 33 vertex heights.
+	///
+	/// Both sides of a cell edge share the same edge vertices, and every mode passes exactly through them,
+	/// so neighboring cells meet without seams.
+	/// Bicubic wants one vertex past each side, which belongs to the neighboring cell, so it blends linearly across the spans next to the edges instead,
+	/// just like the neighbor does on its side.
+	fn sample(self, heights : &[f64], u : f64, v : f64) -> f64 {
+		let vertex = |c : i32, r : i32| heights[c.clamp(0, 32) as usize + r.clamp(0, 32) as usize * 33];
+
+		match self {
+			Interpolation::Nearest => vertex(u.round() as i32, v.round() as i32),
+			Interpolation::Bilinear => {
+				let (c, r) = (u.floor().min(31.0) as i32, v.floor().min(31.0) as i32);
+				let (tu, tv) = (u - c as f64, v - r as f64);
+
+				let top = lerp(vertex(c, r), vertex(c + 1, r), tu);
+				let bottom = lerp(vertex(c, r + 1), vertex(c + 1, r + 1), tu);
+				lerp(top, bottom, tv)
+			},
+			Interpolation::Bicubic => {
+				let (c, r) = (u.floor().min(31.0) as i32, v.floor().min(31.0) as i32);
+				let (tu, tv) = (u - c as f64, v - r as f64);
+
+				let spline = |p : [f64; 4], first : i32, t : f64| match first {
+					0 | 31 => lerp(p[1], p[2], t),
+					_ => catmull_rom(p, t)
+				};
+
+				let rows = [-1, 0, 1, 2].map(|dr| {
+					spline([-1, 0, 1, 2].map(|dc| vertex(c + dc, r + dr)), c, tu)
+				});
+				spline(rows, r, tv)
+			}
+		}
+	}
+}
+
+fn lerp(a : f64, b : f64, t : f64) -> f64 {
+	a + (b - a) * t
+}
+
+/// Interpolates between `p[1]` and `p[2]`.
+fn catmull_rom(p : [f64; 4], t : f64) -> f64 {
+	0.5 * (2.0 * p[1]
+		+ (p[2] - p[0]) * t
+		+ (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]) * t * t
+		+ (3.0 * (p[1] - p[2]) + p[3] - p[0]) * t * t * t)
+}
+
+/// Turns stairs to climb up the slope, using a corner piece where it climbs steeply in both directions.
+fn orient_stairs(stairs : &Block, (rise_x, rise_z) : (f32, f32), stair_slope : f32) -> Block {
+	let x_facing = if rise_x > 0.0 { "east" } else { "west" };
+	let z_facing = if rise_z > 0.0 { "south" } else { "north" };
+
+	let (facing, side, side_rise) = if rise_x.abs() >= rise_z.abs() {
+		(x_facing, z_facing, rise_z.abs())
+	} else {
+		(z_facing, x_facing, rise_x.abs())
+	};
+
+	let clockwise = match facing {
+		"north" => "east",
+		"east" => "south",
+		"south" => "west",
+		_ => "north"
+	};
+
+	let shape = if side_rise < stair_slope {
+		"straight"
+	} else if side == clockwise {
+		"outer_right"
+	} else {
+		"outer_left"
+	};
+
+	let mut block = stairs.clone();
+	block.properties.insert("facing".into(), facing.into());
+	block.properties.insert("half".into(), "bottom".into());
+	block.properties.insert("shape".into(), shape.into());
+	block
+}
+
+/// Chunk or region coordinates.
+pub type Pos = (i32, i32);
+
+/// One block column of a cell, in a chunk.
+struct Column {
+	/// Within the chunk.
+	x : usize,
+	z : usize,
+	world_x : i32,
+	world_z : i32,
+	/// Where the middle of the column is in the cell, in vertices.
+	u : f64,
+	v : f64,
+	/// The closest vertex, which the column takes its texture and color from.
+	vertex : usize,
+}
+
+pub fn parse_land(land : Land, records : &WorldRecords, mapping : &Mapping, scale : Scale, interpolation : Interpolation, writer : &RegionWriter) -> Result<()> {
 	// Order of operations:
-	// Deduce region ranges and chunk ranges from Cell coordinates.
-	// Write height data to these chunk ranges.
+	// Find the blocks whose middle falls inside this cell, and where in between the vertices each of those sits.
+	// Group those block columns by chunk, and the chunks by region.
+	// Draw the columns into the region's chunks (on top of whatever neighboring cells already drew there). The writer saves them once the whole region is done.
 
-	// A region is 32 x 32 chunks.
-	// We say a Minecraft block is 64 Skyrim Units.
-	// So therefore one cell is 4 x 4 minecraft chunks.
-	// Therefore, we can cram about 8 x 8 cells into one region.
-
-	// So our current region position is:
-	// floor(Cell Position/8)
-	// TODO: Not sure this is giving us negative regions?
-	let curr_region_x = land.cell.x.div_euclid(8);
-	let curr_region_y = land.cell.y.div_euclid(8);
-
-	// Cells are comprised of 4 x 4 chunks, so we skip to the relevant starting chunk.
-	// We need this in chunk coordinates relative to the world origin (0, 0).
+	// Everything below assumes the full grid of vertices, which a LAND from somewhere other than a plugin might not have:
+	if land.heights.len() != 33 * 33 || land.textures.len() != 33 * 33 {
+		return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("LAND for cell {},{} needs 33 x 33 heights and textures.", land.cell.x, land.cell.y)).into());
+	}
+
+	let heights : Vec<f64> = land.heights.iter().map(|h| *h as f64).collect();
+
+	// A block belongs to whichever cell its middle is in. The cell no longer has to be a whole number of chunks,
+	// so a chunk on the edge can be shared with the neighboring cells.
 	// Per cubicmetre, -Z is North (and -X is West).
-	let chunk_start_x  = land.cell.x * 4;
-	let chunk_start_z = land.cell.y * 4;
+	let cell_x = land.cell.x as f64 * CELL_SIZE;
+	let cell_z = land.cell.y as f64 * CELL_SIZE;
+
+	let block_range = |start : f64| {
+		(start / scale.horizontal - 0.5).ceil() as i32..((start + CELL_SIZE) / scale.horizontal - 0.5).ceil() as i32
+	};
+	let to_vertex = |block : i32, start : f64| {
+		(((block as f64 + 0.5) * scale.horizontal - start) / VERTEX_SPACING).clamp(0.0, 32.0)
+	};
+
+	let mut chunk_columns : BTreeMap<Pos, Vec<Column>> = BTreeMap::new();
 
-	let mut chunks : [Chunk; 16] = core::array::from_fn(Chunk::into_arr);
+	for world_z in block_range(cell_z) {
+		let v = to_vertex(world_z, cell_z);
 
-	let mut row_offset : f32 = 0.0;
-	let mut curr_offset = land.offset_height;
+		for world_x in block_range(cell_x) {
+			let u = to_vertex(world_x, cell_x);
 
-	// TODO: Is this conversion right?
-	let water_height = land.cell.water_height.map(|h| { h/64.0 });
+			chunk_columns.entry((world_x.div_euclid(16), world_z.div_euclid(16))).or_default().push(Column {
+				x: world_x.rem_euclid(16) as usize,
+				z: world_z.rem_euclid(16) as usize,
+				world_x,
+				world_z,
+				u,
+				v,
+				vertex: u.round() as usize + v.round() as usize * 33
+			});
+		}
+	}
 
-	for (i, v) in land.height_gradient.iter().enumerate() {
-		let r = i / 33;
-		let c = i % 33;
+	// A region is 32 x 32 chunks.
+	let mut region_chunks : BTreeMap<Pos, Vec<(Pos, Vec<Column>)>> = BTreeMap::new();
+	for (chunk_pos, columns) in chunk_columns {
+		region_chunks.entry((chunk_pos.0.div_euclid(32), chunk_pos.1.div_euclid(32))).or_default().push((chunk_pos, columns));
+	}
 
-		// Each vertex is 128 units apart, or 2 blocks apart.
-		// There are 32 vertices in a row/col, and those are split over 4 chunks.
-		// So we have 8 vertices per chunk.
-		let curr_chunk_x = (c / 8) % 4;
-		let curr_chunk_z = (r / 8) % 4;
+	// Shifting everything up by one to avoid overwriting bedrock.
+	let to_block_y = |units : f64| {
+		((units / scale.vertical) as f32 + 1.0).clamp((MIN_Y + 1) as f32, MAX_Y as f32)
+	};
 
-		// println!("{r},{c} {curr_chunk_z},{curr_chunk_x}");
+	let water_height = land.cell.water_height.map(|h| to_block_y(h as f64));
+	let water_type = land.cell.water_type.and_then(|w| records.water_types.get(&w));
+	let water = mapping.water(water_type.map(|w| w.as_str()));
 
-		let chunk = &mut chunks[curr_chunk_x + curr_chunk_z * 4];
-		// Too lazy to come up with a better way to do this, so we always set the x_pos:
-		chunk.x_pos = curr_chunk_x as i32 + chunk_start_x;
-		chunk.z_pos = curr_chunk_z as i32 + chunk_start_z;
+	let mut surfaces : HashMap<Option<u32>, &BlockRule> = HashMap::new();
+	let biome = mapping.biome(&land.cell, records);
 
-		let vert_height = *v as f32;
+	// How many blocks the terrain rises per block towards +X and +Z at a column, from the vertex normals if we have them:
+	let slope = |column : &Column| -> (f32, f32) {
+		let aspect = scale.horizontal / scale.vertical;
 
-		if c == 0 {
-			row_offset = 0.0;
-			curr_offset += vert_height;
-		} else {
-			row_offset += vert_height;
+		match &land.normals {
+			Some(normals) => {
+				let [nx, ny, nz] = normals[column.vertex].map(|n| n as f64);
+				// A sideways normal would be a wall, which is as steep as it gets anyway:
+				let nz = nz.max(1.0);
+				((-nx / nz * aspect) as f32, (-ny / nz * aspect) as f32)
+			},
+			None => {
+				let rise = |u0 : f64, v0 : f64, u1 : f64, v1 : f64, span : f64| {
+					let run = span * VERTEX_SPACING;
+					if run <= 0.0 {
+						return 0.0;
+					}
+					((interpolation.sample(&heights, u1, v1) - interpolation.sample(&heights, u0, v0)) / run * aspect) as f32
+				};
+
+				let (u0, u1) = ((column.u - 0.5).max(0.0), (column.u + 0.5).min(32.0));
+				let (v0, v1) = ((column.v - 0.5).max(0.0), (column.v + 0.5).min(32.0));
+				(rise(u0, column.v, u1, column.v, u1 - u0), rise(column.u, v0, column.u, v1, v1 - v0))
+			}
 		}
+	};
 
-		// Conversion is: (height * 8)/(64) (Vert units -> Skyrim Units -> Minecraft Units).
-		// But it's just easier to divide by 8.
-		let block_height = (row_offset + curr_offset)/(8.0);
+	for (region, chunks) in region_chunks {
+		writer.edit(region, |region| {
+			for (chunk_pos, columns) in chunks {
+				let chunk = region.entry(chunk_pos).or_insert_with(|| Chunk {
+					x_pos: chunk_pos.0,
+					z_pos: chunk_pos.1,
+					..Chunk::default()
+				});
+
+				// Biomes are set for 4 x 4 columns of blocks at a time:
+				let mut biome_columns : Vec<(usize, usize)> = columns.iter().map(|c| (c.x / 4, c.z / 4)).collect();
+				biome_columns.sort_unstable();
+				biome_columns.dedup();
+
+				for column in columns {
+					let (x, z, world_x, world_z) = (column.x, column.z, column.world_x, column.world_z);
+
+					let start_height = (MIN_Y + 1) as f32;
+					let end_height = to_block_y(interpolation.sample(&heights, column.u, column.v));
+
+					// draw_height rounds the last partial block, so this is the first block above the terrain:
+					let mut surface_height = end_height.round_ties_even();
+					let surface_y = surface_height as i32 - 1;
+
+					let texture = land.textures[column.vertex];
+					let surface = surfaces.entry(texture).or_insert_with(|| {
+						mapping.land_texture(texture.and_then(|t| records.land_textures.get(&t)))
+					});
+
+					let mut surface_block = surface.pick(world_x, surface_y, world_z);
+					if let Some(colors) = &land.vertex_colors {
+						surface_block = mapping.vertex_colors.apply(surface_block, colors[column.vertex]);
+					}
 
-		// TODO: We currently drop the last vertex because we don't account for it. We treat each vertex as having influence over blocks 2 x 2in front of it.
-		// An area of influence would probably be better.
-		if c == 32 || r == 32 {
-			continue;
+					// If the surface block has a slab or stairs, we can do better than rounding to the closest block:
+					let mut partial = None;
+					if let Some(variants) = mapping.smoothing.variants(surface_block) {
+						let threshold = mapping.smoothing.slab_threshold;
+						let fraction = end_height - end_height.floor();
+
+						surface_height = if fraction >= 1.0 - threshold { end_height.floor() + 1.0 } else { end_height.floor() };
+
+						if fraction >= threshold && fraction < 1.0 - threshold {
+							let rise = slope(&column);
+							let steepness = rise.0.abs().max(rise.1.abs());
+
+							partial = match (&variants.stairs, &variants.slab) {
+								(Some(stairs), _) if steepness >= mapping.smoothing.stair_slope => Some(orient_stairs(stairs, rise, mapping.smoothing.stair_slope)),
+								(_, Some(slab)) => Some(slab.clone()),
+								(Some(stairs), None) => Some(orient_stairs(stairs, rise, mapping.smoothing.stair_slope)),
+								(None, None) => None
+							};
+						}
+					}
+
+					chunk.draw_height(x, z, start_height, surface_height - 1.0, mapping.underground.pick(world_x, surface_y - 1, world_z));
+					chunk.draw_height(x, z, surface_height - 1.0, surface_height, surface_block);
+
+					let mut water_start = surface_height;
+					if let Some(h) = water_height {
+						if h.floor() > surface_height {
+							// Only slabs and stairs can sit underwater, anything else (like snow layers) gets washed away:
+							partial = partial.filter(|p| p.name.ends_with("_slab") || p.name.ends_with("_stairs")).map(|mut p| {
+								p.properties.insert("waterlogged".into(), "true".into());
+								p
+							});
+
+							if partial.is_some() {
+								water_start += 1.0;
+							}
+							chunk.draw_height(x, z, water_start, h, water.pick(world_x, h as i32, world_z));
+						}
+					}
+
+					if let Some(p) = &partial {
+						chunk.draw_height(x, z, surface_height, surface_height + 1.0, p);
+					}
+				}
+
+				chunk.set_biome(&biome_columns, biome);
+			}
+		})?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn block(name : &str) -> Block {
+		Block {
+			name: format!("minecraft:{name}"),
+			properties: HashMap::new()
 		}
+	}
 
-		let block_x = (c % 8) * 2;
-		let block_z = (r % 8) * 2;
+	/// A section whose palette has `n` blocks, all of them used.
+	fn section_with_palette(n : usize) -> BlockState {
+		let mut state = BlockState::new_from_palette(vec![block("air")]);
+		for i in 1..n {
+			assert_eq!(state.index_of(&block(&format!("block_{i}"))), i);
+		}
+		for i in 0..SECTION_VOLUME {
+			state.set(i % 16, i / 256, (i / 16) % 16, i % n);
+		}
+		state
+	}
 
-		let min_yf = MIN_Y as f32;
-		let start_height = min_yf + 1.0;
-		let end_height = block_height + 1.0;
+	fn assert_blocks(state : &BlockState, n : usize) {
+		for i in 0..SECTION_VOLUME {
+			assert_eq!(state.get(i % 16, i / 256, (i / 16) % 16), i % n, "block {i}");
+		}
+	}
 
-		// Vertices are two blocks apart, so we write in a 2 x 2 block grid:
-		// Shifting everything up by one to avoid overwriting bedrock.
-		chunk.draw_height(block_x, block_z, start_height, end_height, 2);
-		chunk.draw_height(block_x + 1, block_z, start_height, end_height, 2);
-		chunk.draw_height(block_x, block_z + 1, start_height, end_height, 2);
-		chunk.draw_height(block_x + 1, block_z + 1, start_height, end_height, 2);
+	#[test]
+	fn biome_palettes() {
+		let all_columns : Vec<(usize, usize)> = (0..16).map(|i| (i % 4, i / 4)).collect();
 
-		// if let Some(h) = water_height {
-		// 	if h > end_height {
-		// 		// FIXME: Not sure we account for slight block offsets like this, see line 168:
-		// 		let start = end_height + 1.0;
-		// 		chunk.draw_height(block_x, block_z, start, h, 3);
-		// 		chunk.draw_height(block_x + 1, block_z, start, h, 3);
-		// 		chunk.draw_height(block_x, block_z + 1, start, h, 3);
-		// 		chunk.draw_height(block_x + 1, block_z + 1, start, h, 3);
-		// 	}
-		// }
+		// One biome needs no data at all:
+		let mut biomes = Biomes::default();
+		biomes.set_columns(&all_columns, "minecraft:desert");
+		assert_eq!(biomes.palette, ["minecraft:desert"]);
+		assert!(biomes.data.is_none());
+
+		// Two take a bit each, so the whole section fits in one long, a column at every 16th bit:
+		let mut biomes = Biomes::default();
+		biomes.set_columns(&[(0, 0)], "minecraft:desert");
+		assert_eq!(biomes.palette, ["minecraft:plains", "minecraft:desert"]);
+		assert_eq!(biomes.data.as_deref(), Some([0x0001_0001_0001_0001].as_slice()));
+
+		for (i, column) in [(1, 0), (2, 0), (3, 0), (0, 1)].into_iter().enumerate() {
+			biomes.set_columns(&[column], &format!("minecraft:biome_{i}"));
+		}
+		assert_eq!(biomes.palette, ["minecraft:plains", "minecraft:desert", "minecraft:biome_0", "minecraft:biome_1", "minecraft:biome_2", "minecraft:biome_3"]);
+		biomes.set_columns(&[(0, 0)], "minecraft:plains");
+		assert_eq!(biomes.palette, ["minecraft:plains", "minecraft:biome_0", "minecraft:biome_1", "minecraft:biome_2", "minecraft:biome_3"]);
+
+		// Five take 3 bits, 21 of them to a long:
+		let data = biomes.data.as_deref().unwrap();
+		assert_eq!(data.len(), 4);
+		assert_eq!(data[0] & 0x7FFF, 0b100_011_010_001_000);
+		assert_eq!(biomes.unpack()[16..21], [0, 1, 2, 3, 4]);
+
+		// Biomes nobody uses any more are dropped:
+		biomes.set_columns(&all_columns, "minecraft:plains");
+		assert_eq!(biomes.palette, ["minecraft:plains"]);
+		assert!(biomes.data.is_none());
 	}
 
-	let region_name = format!("r.{curr_region_x}.{curr_region_y}.mca");
-	let region_path = out_folder.join(region_name);
-	
-	let region_exists = region_path.exists();
+	#[test]
+	fn bits_per_entry() {
+		for (palette_len, bits) in [(1, 4), (2, 4), (16, 4), (17, 5), (32, 5), (33, 6), (256, 8), (257, 9), (4096, 12)] {
+			assert_eq!(BlockState::bits_per_entry(palette_len), bits, "{palette_len} blocks");
+		}
+	}
 
-	let mut file =	if region_exists {
-		OpenOptions::new().read(true).write(true).open(region_path).unwrap()
-	} else {
-		OpenOptions::new().read(true).write(true).create(true).open(region_path).unwrap()
-	};
+	#[test]
+	fn entries_never_span_two_longs() {
+		for n in [17, 33, 100] {
+			let state = section_with_palette(n);
+			let bits = BlockState::bits_per_entry(n);
+			let per_long = 64 / bits;
+			let data = state.data.as_ref().unwrap();
+
+			assert_eq!(data.len(), SECTION_VOLUME.div_ceil(per_long), "{n} blocks");
+			// The padding at the top of each long stays empty:
+			if per_long * bits < 64 {
+				assert!(data.iter().all(|long| (*long as u64) >> (per_long * bits) == 0), "{n} blocks");
+			}
+		}
+	}
 
-	
-	let mut lock = file_guard::lock(&mut file, Lock::Exclusive, 0, usize::MAX).expect("Could not lock file.");
+	#[test]
+	fn round_trips() {
+		for n in [16, 17, 4096] {
+			let mut state = section_with_palette(n);
+			assert_blocks(&state, n);
 
-	{
-		let f = &mut lock as &mut File;
-		
-		// Our handy units mean we can only be in one region at a given time:
-		let mut region = if region_exists {
-			fastanvil::Region::from_stream(f).unwrap()
-		} else {
-			fastanvil::Region::new(f).unwrap()
-		};
+			// Every entry is used, so compacting changes nothing:
+			state.compact();
+			assert_eq!(state.palette.len(), n);
+			assert_blocks(&state, n);
+
+			let read : BlockState = fastnbt::from_bytes(&fastnbt::to_bytes(&state).unwrap()).unwrap();
+			assert_eq!(read.palette, state.palette);
+			assert_blocks(&read, n);
+		}
+	}
+
+	#[test]
+	fn growing_past_16_entries_keeps_blocks() {
+		let mut state = section_with_palette(16);
+		let idx = state.index_of(&block("one_more"));
+		assert_eq!(idx, 16);
+		assert_eq!(state.data.as_ref().unwrap().len(), SECTION_VOLUME.div_ceil(64 / 5));
+		assert_blocks(&state, 16);
 
-		for c in chunks {
-			region.write_chunk((c.x_pos).rem_euclid(32) as usize, (c.z_pos).rem_euclid(32) as usize, &fastnbt::to_bytes(&c).unwrap()).unwrap();
+		state.set(3, 4, 5, idx);
+		assert_eq!(state.get(3, 4, 5), idx);
+	}
+
+	#[test]
+	fn compact_collapses_uniform_sections() {
+		let mut state = BlockState::new_from_palette(vec![block("air"), block("stone")]);
+		for y in 0..16 {
+			state.fill_layer(1, y);
 		}
+		state.compact();
+
+		assert_eq!(state.palette, vec![block("stone")]);
+		assert!(state.data.is_none());
+		assert_eq!(state.get(7, 7, 7), 0);
+	}
+
+	#[test]
+	fn compact_drops_unused_blocks() {
+		let mut state = BlockState::new_from_palette(vec![block("air"), block("dirt"), block("stone")]);
+		state.fill_layer(2, 0);
+		state.compact();
+
+		assert_eq!(state.palette, vec![block("air"), block("stone")]);
+		assert_eq!(state.get(0, 0, 0), 1);
+		assert_eq!(state.get(0, 1, 0), 0);
 	}
-}
\ No newline at end of file
+
+	/// Heights that aren't flat in any direction, so every vertex is different from its neighbors.
+	fn bumpy_heights() -> Vec<f64> {
+		(0..1089).map(|i| (((i % 33) * 7 + (i / 33) * 13) % 17) as f64 * 8.0).collect()
+	}
+
+	#[test]
+	fn interpolation_passes_through_vertices() {
+		let heights = bumpy_heights();
+		for mode in [Interpolation::Nearest, Interpolation::Bilinear, Interpolation::Bicubic] {
+			for r in 0..33 {
+				for c in 0..33 {
+					assert_eq!(mode.sample(&heights, c as f64, r as f64), heights[c + r * 33], "{mode:?} at vertex ({c}, {r})");
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn interpolation_between_vertices() {
+		let heights = bumpy_heights();
+		let vertex = |c : usize, r : usize| heights[c + r * 33];
+
+		assert_eq!(Interpolation::Nearest.sample(&heights, 4.49, 7.51), vertex(4, 8));
+		assert_eq!(Interpolation::Bilinear.sample(&heights, 4.5, 7.5), (vertex(4, 7) + vertex(5, 7) + vertex(4, 8) + vertex(5, 8)) / 4.0);
+		assert_eq!(Interpolation::Bilinear.sample(&heights, 4.25, 7.0), vertex(4, 7) * 0.75 + vertex(5, 7) * 0.25);
+	}
+
+	#[test]
+	fn bicubic_is_linear_next_to_edges() {
+		let heights = bumpy_heights();
+		let vertex = |c : usize, r : usize| heights[c + r * 33];
+
+		// Linear across the span next to an edge, and a spline along it:
+		let rows = [2, 3, 4, 5].map(|r| lerp(vertex(0, r), vertex(1, r), 0.25));
+		assert_eq!(Interpolation::Bicubic.sample(&heights, 0.25, 3.5), catmull_rom(rows, 0.5));
+		// Linear both ways in the corners, without reading past the end:
+		assert_eq!(Interpolation::Bicubic.sample(&heights, 31.5, 31.5), Interpolation::Bilinear.sample(&heights, 31.5, 31.5));
+		assert_eq!(Interpolation::Bicubic.sample(&heights, 32.0, 32.0), vertex(32, 32));
+	}
+
+	#[test]
+	fn neighboring_cells_meet() {
+		// Two cells side by side, west and east, cut out of the same hills:
+		let hills = |x : usize, y : usize| (((x * 7 + y * 13) % 17) as f64 * 8.0) + x as f64 * 10.0;
+		let west : Vec<f64> = (0..1089).map(|i| hills(i % 33, i / 33)).collect();
+		let east : Vec<f64> = (0..1089).map(|i| hills(i % 33 + 32, i / 33)).collect();
+
+		for mode in [Interpolation::Nearest, Interpolation::Bilinear, Interpolation::Bicubic] {
+			for step in 0..=128 {
+				let v = step as f64 / 4.0;
+				assert_eq!(mode.sample(&west, 32.0, v), mode.sample(&east, 0.0, v), "{mode:?} at row {v}");
+			}
+		}
+
+		// A straight ramp stays straight across the edge, rather than flattening out towards it on either side:
+		let ramp = |x : usize| x as f64 * 10.0;
+		let west : Vec<f64> = (0..1089).map(|i| ramp(i % 33)).collect();
+		let east : Vec<f64> = (0..1089).map(|i| ramp(i % 33 + 32)).collect();
+		for step in 0..8 {
+			let u = step as f64 / 4.0;
+			assert_eq!(Interpolation::Bicubic.sample(&west, 30.0 + u, 7.5), ramp(30) + u * 10.0);
+			assert_eq!(Interpolation::Bicubic.sample(&east, u, 7.5), ramp(32) + u * 10.0);
+		}
+	}
+
+	#[test]
+	fn catmull_rom_ends_on_the_middle_points() {
+		let p = [3.0, -2.0, 7.0, 1.0];
+		assert_eq!(catmull_rom(p, 0.0), -2.0);
+		assert_eq!(catmull_rom(p, 1.0), 7.0);
+	}
+
+	#[test]
+	fn stairs_face_up_the_slope() {
+		let stairs = block("stone_stairs");
+		let stair_slope = 1.0;
+
+		// How far the neighbors to the east (+X) and south (+Z) rise, and the stairs that makes:
+		let table = [
+			((2.0, 0.0), "east", "straight"),
+			((-2.0, 0.0), "west", "straight"),
+			((0.0, 2.0), "south", "straight"),
+			((0.0, -2.0), "north", "straight"),
+			// A gentle slope to the side doesn't turn the stairs into a corner:
+			((2.0, 0.5), "east", "straight"),
+			((-0.5, -3.0), "north", "straight"),
+			// Steep both ways, turning towards the side that's clockwise of the facing, or the other:
+			((2.0, 1.5), "east", "outer_right"),
+			((2.0, -1.5), "east", "outer_left"),
+			((-2.0, -1.5), "west", "outer_right"),
+			((-2.0, 1.5), "west", "outer_left"),
+			((1.5, -2.0), "north", "outer_right"),
+			((-1.5, -2.0), "north", "outer_left"),
+			((-1.5, 2.0), "south", "outer_right"),
+			((1.5, 2.0), "south", "outer_left"),
+			// Equally steep both ways faces along X:
+			((1.0, 1.0), "east", "outer_right"),
+			// Flat faces west, for lack of anything better:
+			((0.0, 0.0), "west", "straight"),
+		];
+
+		for (rise, facing, shape) in table {
+			let oriented = orient_stairs(&stairs, rise, stair_slope);
+			assert_eq!(oriented.name, stairs.name);
+			assert_eq!(oriented.properties["facing"], facing, "rise {rise:?}");
+			assert_eq!(oriented.properties["shape"], shape, "rise {rise:?}");
+			assert_eq!(oriented.properties["half"], "bottom");
+		}
+	}
+}